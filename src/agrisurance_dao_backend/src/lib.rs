@@ -1,27 +1,138 @@
 #[macro_use]
 extern crate serde;
-use candid::{Decode, Encode};
-//use ic_cdk::api::time;
+use candid::{Decode, Encode, Principal};
+use ic_certification::{AsHashTree, Hash, RbTree};
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
-use ic_stable_structures::{BoundedStorable, Cell, DefaultMemoryImpl, StableBTreeMap, Storable};
-use std::{borrow::Cow, cell::RefCell};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Cell, DefaultMemoryImpl, Memory as _, StableBTreeMap, Storable};
+use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    time::Duration,
+};
+
+// The system API, which the unit tests replace with a simulated one
+#[cfg(test)]
+use tests::system::{
+    caller, canister_balance128, data_certificate, instruction_counter, is_controller, set_certified_data, set_timer,
+    set_timer_interval, time,
+};
+#[cfg(not(test))]
+use ic_cdk::{
+    api::{canister_balance128, data_certificate, instruction_counter, is_controller, set_certified_data, time},
+    caller,
+};
+#[cfg(not(test))]
+use ic_cdk_timers::{set_timer, set_timer_interval};
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 type IdCell = Cell<u64, Memory>;
+// Record hashes by kind label and big-endian id
+type CertifiedTree = RbTree<&'static [u8], RbTree<[u8; 8], Hash>>;
+type Samples = BTreeMap<String, f64>; // Metric values keyed by their rendered label set
+type CsvRow = BTreeMap<String, String>; // Cells keyed by their column header
+
+const CLAIM_HISTORY_EPOCH_NS: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+const CLAIM_HISTORY_BASE_REWARD: f64 = 10.0;
+const CLAIM_HISTORY_REJECTION_WEIGHT: f64 = 2.0;
+const CLAIM_HISTORY_AMOUNT_SCALE: f64 = 1_000.0; // Paid amount that weighs as much as one extra claim
+const MAX_PARTICIPATION_LEVEL: u64 = 10;
+const DISPUTE_EVIDENCE_PERIOD_NS: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+const MAX_EVIDENCE_ITEMS_PER_PARTY: usize = 10;
+const MAX_RULING_OUTCOMES: usize = 8;
+const MAX_PAGE_SIZE: u64 = 100;
+const DEFAULT_API_PAGE_SIZE: u64 = 20;
+const INITIAL_JURY_SIZE: usize = 3;
+const MAX_JURY_ROUNDS: u32 = 3; // Each appeal doubles the jury plus one: 3, 7, 15
+const JUROR_MIN_STAKE: f64 = 10.0;
+const JUROR_PENALTY_RATIO: f64 = 0.05; // Share of the bonded stake lost by incoherent jurors
+const JURY_COMMIT_PERIOD_NS: u64 = 3 * 24 * 60 * 60 * 1_000_000_000;
+const JURY_REVEAL_PERIOD_NS: u64 = 2 * 24 * 60 * 60 * 1_000_000_000;
+const JURY_APPEAL_PERIOD_NS: u64 = 3 * 24 * 60 * 60 * 1_000_000_000;
+const CLAIM_APPEAL_WINDOW_NS: u64 = 14 * 24 * 60 * 60 * 1_000_000_000;
+const BLOB_CHUNK_SIZE: usize = 64 * 1024;
+const MAX_BLOB_SIZE: u64 = 10 * 1024 * 1024;
+const BLOB_QUOTA_BYTES: u64 = 50 * 1024 * 1024; // Per user, including uploads in progress
+const BLOB_ORPHAN_GRACE_NS: u64 = 7 * 24 * 60 * 60 * 1_000_000_000; // Time to cite a blob before it can be pruned
+const MAX_MIME_TYPE_LENGTH: usize = 100;
+const MAX_CLAIM_EVIDENCE_BLOBS: usize = 16;
+const MAX_PLOT_VERTICES: usize = 64;
+const MAX_PLANTINGS_PER_PLOT: usize = 24;
+const MAX_REGION_CODE_LENGTH: usize = 32;
+const MAX_PLOT_LABEL_LENGTH: usize = 64; // Plot names and seasons
+const MAX_CROP_NAME_LENGTH: usize = 32; // Crop names, aliases and growing seasons
+const MAX_CROP_ALIASES: usize = 8;
+const MAX_CROP_SEASONS: usize = 4;
+const MAX_ASSESSORS_PER_CLAIM: usize = 3;
+const MAX_ASSESSMENT_PHOTOS: usize = 10;
+const MAX_ASSESSMENT_NOTES_LENGTH: usize = 1000;
+const ASSESSMENT_LOSS_TOLERANCE: f64 = 15.0; // Percentage points between reports before they disagree
+const ASSESSMENT_AREA_TOLERANCE: f64 = 0.2; // Relative difference in affected area before reports disagree
+const FRAUD_REVIEW_THRESHOLD: u32 = 50; // Claims scoring at least this much are held for manual review
+const FRAUD_DUPLICATE_SCORE: u32 = 40;
+const FRAUD_DUPLICATE_WINDOW_NS: u64 = 90 * 24 * 60 * 60 * 1_000_000_000;
+const FRAUD_EARLY_CLAIM_SCORE: u32 = 25;
+const FRAUD_EARLY_CLAIM_WINDOW_NS: u64 = 14 * 24 * 60 * 60 * 1_000_000_000;
+const FRAUD_WEATHER_MISMATCH_SCORE: u32 = 30;
+const FRAUD_WEATHER_LOOKBACK_NS: u64 = 60 * 24 * 60 * 60 * 1_000_000_000;
+const FRAUD_FREQUENCY_SCORE: u32 = 20;
+const FRAUD_FREQUENCY_LIMIT: usize = 3; // Claims per farmer within the frequency window
+const FRAUD_FREQUENCY_WINDOW_NS: u64 = 365 * 24 * 60 * 60 * 1_000_000_000;
+const FRAUD_REUSED_EVIDENCE_SCORE: u32 = 40;
+const EARTH_RADIUS_M: f64 = 6_371_008.8;
+// Limits on caller-supplied text and lists, checked before anything is stored
+const MAX_USER_NAME_LENGTH: usize = 100;
+const MAX_CONTRACT_TEXT_LENGTH: usize = 4000; // Terms, conditions and payout criteria
+const MAX_CONTRACT_PLOTS: usize = 50;
+const MAX_CONTRACT_CROPS: usize = 20;
+const MAX_INVOLVED_PARTIES: usize = 20;
+const MAX_PROPOSAL_DETAILS_LENGTH: usize = 4000;
+const MAX_CLAIM_DETAILS_LENGTH: usize = 2000;
+const MAX_CLAIM_EVIDENCE_LENGTH: usize = 2000;
+const MAX_AFFECTED_CROPS: usize = 50;
+const MAX_REASON_LENGTH: usize = 1000; // Dispute reasons, resolutions, appeals and stake compensations
+const MAX_STATEMENT_LENGTH: usize = 4000; // Dispute statements, slash appeals and ruling summaries
+const MAX_EVIDENCE_DESCRIPTION_LENGTH: usize = 500;
+const MAX_EVIDENCE_CONTENT_LENGTH: usize = 4000;
+const MAX_BEHAVIOR_METRIC_LENGTH: usize = 50;
+const MEMORY_ID_COUNT: u8 = 42; // Memories 0..MEMORY_ID_COUNT are allocated by the thread_locals below
+const WASM_PAGE_SIZE: u64 = 64 * 1024;
+const EXPORT_CHUNK_SIZE: usize = 1024 * 1024; // Stays well below the 2MiB reply limit
+const MAX_EXPORT_SNAPSHOTS: usize = 4; // The oldest snapshot is dropped when another one is taken
+const EXPORT_STEP_INSTRUCTIONS: u64 = 10_000_000_000; // A quarter of the instruction limit of an update call
+const WRITE_FREEZE_RETRY_DELAY: Duration = Duration::from_secs(60); // Timer jobs wait this long while writes are suspended
+const MAX_IMPORT_ROWS: usize = 1000; // Users, plots and contracts of one batch together
+const MAX_EXTERNAL_REF_LENGTH: usize = 64;
+const BACKUP_MAGIC: &[u8; 8] = b"AGRIBKUP";
+const BACKUP_FORMAT_VERSION: u32 = 1;
+const BACKUP_HEADER_SIZE: usize = 64; // Magic, version, creation time, event seq, memory count and checksum
+const BACKUP_CHUNK_SIZE: usize = 1024 * 1024;
+const BACKUP_STEP_INSTRUCTIONS: u64 = 10_000_000_000; // Hashed per call, like EXPORT_STEP_INSTRUCTIONS
+const BACKUP_WINDOW_NS: u64 = 60 * 60 * 1_000_000_000; // Writes stay suspended at most this long for a download
+const STATE_VERSION: u32 = 1; // Bumped whenever stored records need a migration in post_upgrade
+// Labels of the certified record kinds
+const CERTIFIED_USERS: &[u8] = b"users";
+const CERTIFIED_CONTRACTS: &[u8] = b"contracts";
+const CERTIFIED_CLAIMS: &[u8] = b"claims";
+const CERTIFIED_PROPOSALS: &[u8] = b"proposals";
 
-#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
 struct UserProfile {
     id: u64,
     name: String,
     role: UserRole,
-    transaction_history: Vec<TransactionRecord>,
     stake_in_dao: f64,
+    principal: Principal, // Identity allowed to act on behalf of this user
 }
 
 #[derive(candid::CandidType, Clone, Serialize, Deserialize)]
 enum UserRole {
     Farmer,
     Consumer,
+    Assessor, // Field assessor inspecting claimed losses
 }
 
 impl Default for UserRole {
@@ -47,6 +158,110 @@ struct InsuranceContract {
     terms: String,
     conditions: String,
     payout_criteria: String,
+    status: ContractStatus,
+    activated_at: Option<u64>,
+    plot_ids: Vec<u64>, // Insured plots of the farmer
+    crop_ids: Vec<u64>, // Insured crops from the crop catalogue
+    sum_insured: f64, // Paid for a total loss over all insured plots
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Default)]
+enum ContractStatus {
+    #[default]
+    Pending,
+    Active,
+    Fulfilled,
+    Breached { party_id: u64 },
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+struct GeoPoint {
+    lat: f64, // WGS84 degrees
+    lon: f64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+enum PlotGeometry {
+    Point(GeoPoint),
+    Polygon(Vec<GeoPoint>), // Outer ring, not closed
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+enum SoilType {
+    Clay,
+    Sandy,
+    Silt,
+    Loam,
+    Peat,
+    Chalk,
+    Other,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct SeasonalPlanting {
+    season: String, // e.g. "2026-long-rains"
+    crop_id: u64,
+    planted_at: u64,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Default, Debug)]
+enum Peril {
+    #[default]
+    Drought,
+    Flood,
+    Hail,
+    Frost,
+    Windstorm,
+    Wildfire,
+    Pests,
+    Disease,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Crop {
+    id: u64,
+    name: String,
+    aliases: Vec<String>,
+    growing_seasons: Vec<String>,
+    yield_per_hectare: f64, // Typical yield in tonnes
+    eligible_perils: Vec<Peril>,
+    retired: bool, // Retired crops stay readable but cannot be insured or planted anymore
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct CropPayload {
+    name: String,
+    aliases: Vec<String>,
+    growing_seasons: Vec<String>,
+    yield_per_hectare: f64,
+    eligible_perils: Vec<Peril>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Plot {
+    id: u64,
+    farmer_id: u64,
+    name: String,
+    geometry: PlotGeometry,
+    area_hectares: f64,
+    soil_type: SoilType,
+    region_code: String, // Administrative region, e.g. an ISO 3166-2 code
+    plantings: Vec<SeasonalPlanting>,
+    created_at: u64,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+struct BoundingBox {
+    min_lat: f64,
+    min_lon: f64,
+    max_lat: f64,
+    max_lon: f64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct PlotPage {
+    plots: Vec<Plot>,
+    total: u64,
 }
 
 #[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
@@ -54,11 +269,75 @@ struct GovernanceProposal {
     id: u64,
     proposal_details: String,
     proposer_id: u64,
-    voting_records: Vec<VotingRecord>,
+    status: ProposalStatus,
+    deposit: f64,
+    deposit_status: DepositStatus,
+    created_at: u64,
+    voting_deadline: u64,
+    action: Option<ProposalAction>,
+    executable_at: Option<u64>, // End of the timelock for passed proposals with an action
+    execution_error: Option<String>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Default)]
+enum ProposalStatus {
+    #[default]
+    Open,
+    Passed,
+    Queued, // Passed and waiting for the timelock to expire
+    Executed,
+    Failed,
+    Rejected,
+    Expired, // Voting closed without reaching quorum
+}
+
+// Changes applied by the canister itself once a proposal passes
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+enum ProposalAction {
+    UpdateGovernanceConfig(GovernanceConfig),
+    UpdateStakingConfig(StakingConfig),
+    UpdateReputationConfig(ReputationConfig),
+    UpdateSlashingSchedule(SlashingSchedule),
+    AddCrop(CropPayload),
+    UpdateCrop { crop_id: u64, crop: CropPayload },
+    RetireCrop(u64),
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Default)]
+enum DepositStatus {
+    #[default]
+    Held,
+    Refunded,
+    Burned,
+}
+
+// Parameters that govern how proposals are submitted and settled
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct GovernanceConfig {
+    proposal_deposit: f64,
+    max_open_proposals_per_user: u64,
+    voting_period_ns: u64,
+    quorum_ratio: f64,        // Share of the total DAO stake that must vote
+    burn_rejection_ratio: f64, // Share of the cast stake voting Reject that forfeits the deposit
+    timelock_ns: u64,         // Delay between a proposal passing and its action being executed
+}
+
+impl Default for GovernanceConfig {
+    fn default() -> Self {
+        GovernanceConfig {
+            proposal_deposit: 10.0,
+            max_open_proposals_per_user: 3,
+            voting_period_ns: 7 * 24 * 60 * 60 * 1_000_000_000,
+            quorum_ratio: 0.2,
+            burn_rejection_ratio: 0.67,
+            timelock_ns: 2 * 24 * 60 * 60 * 1_000_000_000,
+        }
+    }
 }
 
 #[derive(candid::CandidType, Clone, Serialize, Deserialize)]
 struct VotingRecord {
+    proposal_id: u64,
     user_id: u64,
     vote: VoteType,
     stake: f64,
@@ -72,12 +351,221 @@ enum VoteType {
     Abstain,
 }
 
-#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+// Entries are only ever appended; mistakes are corrected with a compensating entry
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
 struct StakeAdjustment {
+    id: u64,
     user_id: u64,
     old_stake: f64,
     new_stake: f64,
     reason: String,
+    cause: StakeCause,
+    source_id: Option<u64>, // Record that caused the change, e.g. the proposal holding a deposit
+    actor: Principal,       // Caller of the endpoint that triggered the change
+    timestamp: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq)]
+enum StakeCause {
+    Bond,
+    Unbond,
+    ProposalDeposit,
+    DepositRefund,
+    TransactionOutcome,
+    ClaimHistory,
+    DaoParticipation,
+    PositiveBehavior,
+    Compensation,
+    Slash,
+    DisputeRuling,
+    JurorReward,
+    JurorPenalty,
+    Legacy, // Recorded before causes were tracked
+}
+
+// Liquid and unbonding tokens of a user; the bonded amount lives in `UserProfile.stake_in_dao`
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct StakeAccount {
+    user_id: u64,
+    available: f64,
+    unbonding: Vec<UnbondingEntry>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct UnbondingEntry {
+    amount: f64,
+    release_at: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct StakeBalance {
+    user_id: u64,
+    bonded: f64,
+    unbonding: f64,
+    available: f64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct StakingConfig {
+    unbonding_period_ns: u64,
+    max_unbonding_entries: u64,
+}
+
+impl Default for StakingConfig {
+    fn default() -> Self {
+        StakingConfig {
+            unbonding_period_ns: 14 * 24 * 60 * 60 * 1_000_000_000,
+            max_unbonding_entries: 16,
+        }
+    }
+}
+
+#[derive(candid::CandidType, Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+enum Offence {
+    FraudulentClaim,
+    LostDispute,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+enum SlashDestination {
+    RiskPool,
+    HarmedParty,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct PenaltyRule {
+    stake_fraction: f64, // Share of the bonded stake that is slashed
+    minimum_amount: f64,
+    destination: SlashDestination,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct SlashingSchedule {
+    fraudulent_claim: PenaltyRule,
+    lost_dispute: PenaltyRule,
+    appeal_window_ns: u64,
+}
+
+impl Default for SlashingSchedule {
+    fn default() -> Self {
+        SlashingSchedule {
+            fraudulent_claim: PenaltyRule {
+                stake_fraction: 0.5,
+                minimum_amount: 10.0,
+                destination: SlashDestination::RiskPool,
+            },
+            lost_dispute: PenaltyRule {
+                stake_fraction: 0.1,
+                minimum_amount: 0.0,
+                destination: SlashDestination::HarmedParty,
+            },
+            appeal_window_ns: 3 * 24 * 60 * 60 * 1_000_000_000,
+        }
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq)]
+enum SlashBeneficiary {
+    RiskPool,
+    User(u64),
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq)]
+enum SlashStatus {
+    Pending, // Within the appeal window
+    Appealed,
+    Executed,
+    Cancelled,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Slash {
+    id: u64,
+    user_id: u64,
+    offence: Offence,
+    source_id: u64, // Claim or dispute the offence relates to
+    amount: f64,
+    beneficiary: SlashBeneficiary,
+    status: SlashStatus,
+    created_at: u64,
+    appeal_deadline: u64,
+    appeal_statement: Option<String>,
+    stake_adjustment_id: Option<u64>,
+}
+
+// Funds held by the DAO itself
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct Treasury {
+    risk_pool: f64,
+}
+
+// Running reputation components of a user, decayed to `updated_at`
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct ReputationRecord {
+    user_id: u64,
+    claim_outcomes: f64,
+    dispute_outcomes: f64,
+    contract_fulfilment: f64,
+    voting_participation: f64,
+    peer_attestations: f64,
+    updated_at: u64,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+enum ReputationComponent {
+    ClaimOutcomes,
+    DisputeOutcomes,
+    ContractFulfilment,
+    VotingParticipation,
+    PeerAttestations,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct ReputationConfig {
+    claim_outcomes_weight: f64,
+    dispute_outcomes_weight: f64,
+    contract_fulfilment_weight: f64,
+    voting_participation_weight: f64,
+    peer_attestations_weight: f64,
+    half_life_ns: u64, // Age after which an event counts for half as much
+}
+
+impl Default for ReputationConfig {
+    fn default() -> Self {
+        ReputationConfig {
+            claim_outcomes_weight: 1.0,
+            dispute_outcomes_weight: 2.0,
+            contract_fulfilment_weight: 1.5,
+            voting_participation_weight: 0.5,
+            peer_attestations_weight: 1.0,
+            half_life_ns: 180 * 24 * 60 * 60 * 1_000_000_000,
+        }
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Attestation {
+    attester_id: u64,
+    subject_id: u64,
+    positive: bool,
+    timestamp: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct ReputationComponentScore {
+    component: ReputationComponent,
+    value: f64,
+    weight: f64,
+    contribution: f64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct ReputationBreakdown {
+    user_id: u64,
+    score: f64,
+    components: Vec<ReputationComponentScore>,
+    half_life_ns: u64,
+    as_of: u64,
 }
 #[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
 struct InsuranceClaim {
@@ -85,9 +573,116 @@ struct InsuranceClaim {
     farmer_id: u64,
     contract_id: u64,
     claim_details: String,
-    affected_crops: Vec<String>, // List of affected crops
+    affected_crops: Vec<AffectedCrop>, // Crops of the contract's plots hit by the loss
+    peril: Peril,
     evidence: String, // Evidence as a string, could be a URL or encoded data
     status: ClaimStatus,
+    claimed_amount: f64,
+    submitted_at: u64,
+    rejected_at: Option<u64>,
+    appeal_dispute_id: Option<u64>, // Dispute opened by the farmer against the rejection
+    paid_out: bool,
+    evidence_hashes: Vec<Vec<u8>>, // SHA-256 hashes of blobs in the evidence store
+    assessor_ids: Vec<u64>,
+    assessments_disagree: bool, // Set when two reports differ beyond the assessment tolerances
+    accepted_assessment_id: Option<u64>,
+    payout_amount: Option<f64>, // Computed from the accepted assessment
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Assessment {
+    id: u64,
+    claim_id: u64,
+    assessor_id: u64,
+    yield_loss_percent: f64,
+    affected_area_hectares: f64,
+    photo_hashes: Vec<Vec<u8>>, // Blobs in the evidence store
+    visit_location: GeoPoint,
+    visited_at: u64,
+    notes: String,
+    submitted_at: u64,
+}
+
+// A record with the certificate and witness proving it. `data` holds the Candid encoding the
+// certified leaf hash is computed from, found at path [kind label, big-endian id] in the witness
+#[derive(candid::CandidType)]
+struct Certified<T> {
+    value: T,
+    data: ByteBuf,
+    certificate: ByteBuf,
+    witness: ByteBuf, // CBOR encoded hash tree
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+enum EventKind {
+    UserCreated { user_id: u64, role: UserRole },
+    ContractCreated { contract_id: u64, farmer_id: u64, consumer_id: u64 },
+    ContractActivated { contract_id: u64 },
+    ContractCompleted { contract_id: u64, status: ContractStatus },
+    ClaimStatusChanged { claim_id: u64, status: ClaimStatus },
+    ProposalCreated { proposal_id: u64, proposer_id: u64 },
+    VoteCast { proposal_id: u64, user_id: u64, vote: VoteType, stake: f64 },
+    ProposalExecuted { proposal_id: u64, status: ProposalStatus },
+    StakeAdjusted { adjustment_id: u64, user_id: u64, delta: f64, cause: StakeCause },
+    DisputeStatusChanged { dispute_id: u64, status: DisputeStatus },
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Event {
+    seq: u64,
+    timestamp: u64,
+    caller: Principal,
+    kind: EventKind,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct WeatherObservation {
+    id: u64,
+    region_code: String,
+    peril: Peril,
+    started_at: u64,
+    ended_at: u64,
+    recorded_at: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+enum FraudRule {
+    DuplicateClaim,
+    EarlyClaim,
+    WeatherMismatch,
+    HighFrequency,
+    ReusedEvidence,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct FraudSignal {
+    rule: FraudRule,
+    score: u32,
+    detail: String,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct FraudReport {
+    claim_id: u64,
+    score: u32,
+    signals: Vec<FraudSignal>,
+    screened_at: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct AssessmentReport {
+    yield_loss_percent: f64,
+    affected_area_hectares: f64,
+    photo_hashes: Vec<Vec<u8>>,
+    visit_location: GeoPoint,
+    visited_at: u64,
+    notes: String,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct AffectedCrop {
+    plot_id: u64,
+    crop_id: u64,
 }
 
 #[derive(candid::CandidType, Clone, Serialize, Deserialize,PartialEq)]
@@ -96,6 +691,10 @@ enum ClaimStatus {
     Verified,
     Approved,
     Rejected,
+    Fraudulent,
+    Appealed, // Rejection under review by a linked dispute
+    UnderAssessment, // Assessors have been sent to the field
+    ManualReview, // Held by fraud screening until a controller clears it
 }
 
 impl Default for ClaimStatus {
@@ -103,7 +702,7 @@ impl Default for ClaimStatus {
         ClaimStatus::Submitted
     }
 }
-#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
 struct Dispute {
     id: u64,
     farmer_id: u64,
@@ -111,6 +710,132 @@ struct Dispute {
     reason: String,
     status: DisputeStatus,
     resolution: Option<String>,
+    subject: DisputeSubject,
+    raised_by: u64,
+    created_at: u64,
+    evidence_deadline: u64, // Parties can submit evidence and statements until then
+    arbitrator_id: Option<u64>,
+    ruling: Option<Ruling>,
+    jury_round_id: Option<u64>, // Latest jury round when the DAO decides the dispute
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+enum DisputeSubject {
+    Contract(u64),
+    Claim(u64),
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Ruling {
+    winner_id: u64,
+    outcomes: Vec<MonetaryOutcome>,
+    ruled_at: u64,
+}
+
+// Transfer ordered by a ruling; funds are credited to the recipient's available balance
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct MonetaryOutcome {
+    source: FundSource,
+    recipient_id: u64,
+    amount: f64,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+enum FundSource {
+    Treasury,
+    Stake(u64), // Taken from the bonded stake of the given user
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq)]
+enum JuryRoundStatus {
+    Voting,   // Commit phase followed by the reveal phase
+    Decided,  // Votes tallied, open for appeal until the appeal deadline
+    Appealed, // Superseded by a larger round
+    Final,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct JuryRound {
+    id: u64,
+    dispute_id: u64,
+    round: u32,
+    jurors: Vec<u64>,
+    commit_deadline: u64,
+    reveal_deadline: u64,
+    status: JuryRoundStatus,
+    winner_id: Option<u64>, // None when the jury was split or nobody revealed
+    appeal_deadline: Option<u64>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct JuryVote {
+    round_id: u64,
+    juror_id: u64,
+    commitment: Vec<u8>, // SHA-256 of the winner id (little endian) followed by the salt
+    revealed_winner: Option<u64>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct DisputeEvidence {
+    id: u64,
+    dispute_id: u64,
+    submitted_by: u64,
+    description: String,
+    content: String, // URL or encoded document
+    submitted_at: u64,
+    blob_hash: Option<Vec<u8>>, // Document in the evidence store
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct UploadSession {
+    id: u64,
+    owner_id: u64,
+    mime_type: String,
+    total_size: u64, // Reserved against the owner's quota until the upload is committed or aborted
+    created_at: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct BlobMeta {
+    hash: Vec<u8>,
+    owner_id: u64,
+    mime_type: String,
+    size: u64,
+    chunk_count: u32,
+    created_at: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct BlobUsage {
+    user_id: u64,
+    used_bytes: u64,
+    quota_bytes: u64,
+}
+
+// Chunks are stored as raw bytes rather than Candid to keep them at BLOB_CHUNK_SIZE
+struct BlobChunk(Vec<u8>);
+
+#[derive(candid::CandidType, Deserialize)]
+struct HttpRequest {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: ByteBuf,
+}
+
+#[derive(candid::CandidType)]
+struct HttpResponse {
+    status_code: u16,
+    headers: Vec<(String, String)>,
+    body: ByteBuf,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct DisputeStatement {
+    dispute_id: u64,
+    party_id: u64,
+    statement: String,
+    submitted_at: u64,
 }
 
 #[derive(candid::CandidType, Clone, Serialize, Deserialize)]
@@ -118,6 +843,7 @@ enum DisputeStatus {
     Raised,
     UnderReview,
     Resolved,
+    Archived,
 }
 
 impl Default for DisputeStatus {
@@ -132,880 +858,8944 @@ struct DisputeHistory {
     status_updates: Vec<DisputeStatusUpdate>,
 }
 
-#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
 struct DisputeStatusUpdate {
     status: DisputeStatus,
     resolution: Option<String>,
     timestamp: u64, // Unix timestamp
+    actor: Principal,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+enum DisputeTimelineEvent {
+    StatusChanged(DisputeStatusUpdate),
+    EvidenceSubmitted { evidence_id: u64, party_id: u64 },
+    StatementSubmitted { party_id: u64 },
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct DisputeTimelineEntry {
+    timestamp: u64,
+    event: DisputeTimelineEvent,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct DisputeTimelinePage {
+    entries: Vec<DisputeTimelineEntry>,
+    total: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct VotingRecordPage {
+    records: Vec<VotingRecord>,
+    total: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct TransactionRecordPage {
+    records: Vec<TransactionRecord>,
+    total: u64,
 }
 impl Storable for DisputeHistory {
-    fn to_bytes(&self) -> Cow<[u8]> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
         Cow::Owned(Encode!(self).unwrap())
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
         Decode!(bytes.as_ref(), Self).unwrap()
     }
-}
 
-impl BoundedStorable for DisputeHistory {
-    const MAX_SIZE: u32 = 4096;
-    const IS_FIXED_SIZE: bool = false;
+    const BOUND: Bound = Bound::Unbounded;
 }
 
 
 impl Storable for InsuranceClaim {
-    fn to_bytes(&self) -> Cow<[u8]> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
         Cow::Owned(Encode!(self).unwrap())
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
         Decode!(bytes.as_ref(), Self).unwrap()
     }
-}
 
-impl BoundedStorable for InsuranceClaim {
-    const MAX_SIZE: u32 = 4096; // Adjust as necessary
-    const IS_FIXED_SIZE: bool = false;
+    const BOUND: Bound = Bound::Unbounded;
 }
 impl Storable for UserProfile {
-    fn to_bytes(&self) -> Cow<[u8]> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
         Cow::Owned(Encode!(self).unwrap())
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
         Decode!(bytes.as_ref(), Self).unwrap()
     }
-}
 
-impl BoundedStorable for UserProfile {
-    const MAX_SIZE: u32 = 2048; // Adjust based on expected data size
-    const IS_FIXED_SIZE: bool = false;
+    const BOUND: Bound = Bound::Unbounded;
 }
 
 impl Storable for TransactionRecord {
-    fn to_bytes(&self) -> Cow<[u8]> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
         Cow::Owned(Encode!(self).unwrap())
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
         Decode!(bytes.as_ref(), Self).unwrap()
     }
-}
 
-impl BoundedStorable for TransactionRecord {
-    const MAX_SIZE: u32 = 1024; // Adjust as necessary
-    const IS_FIXED_SIZE: bool = false;
+    const BOUND: Bound = Bound::Unbounded;
 }
 impl Storable for InsuranceContract {
-    fn to_bytes(&self) -> Cow<[u8]> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
         Cow::Owned(Encode!(self).unwrap())
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
         Decode!(bytes.as_ref(), Self).unwrap()
     }
-}
 
-impl BoundedStorable for InsuranceContract {
-    const MAX_SIZE: u32 = 2048; // Adjust according to your needs
-    const IS_FIXED_SIZE: bool = false;
+    const BOUND: Bound = Bound::Unbounded;
 }
 
 impl Storable for GovernanceProposal {
-    fn to_bytes(&self) -> Cow<[u8]> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
         Cow::Owned(Encode!(self).unwrap())
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
         Decode!(bytes.as_ref(), Self).unwrap()
     }
+
+    const BOUND: Bound = Bound::Unbounded;
 }
 
-impl BoundedStorable for GovernanceProposal {
-    const MAX_SIZE: u32 = 2048; // Adjust based on expected size
-    const IS_FIXED_SIZE: bool = false;
+impl Storable for GovernanceConfig {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
 }
 impl Storable for StakeAdjustment {
-    fn to_bytes(&self) -> Cow<[u8]> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
         Cow::Owned(Encode!(self).unwrap())
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
         Decode!(bytes.as_ref(), Self).unwrap()
     }
-}
 
-impl BoundedStorable for StakeAdjustment {
-    const MAX_SIZE: u32 = 1024; // Adjust as needed
-    const IS_FIXED_SIZE: bool = false;
+    const BOUND: Bound = Bound::Unbounded;
 }
-impl Storable for Dispute {
-    fn to_bytes(&self) -> Cow<[u8]> {
+
+impl Storable for StakeAccount {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
         Cow::Owned(Encode!(self).unwrap())
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
         Decode!(bytes.as_ref(), Self).unwrap()
     }
-}
 
-impl BoundedStorable for Dispute {
-    const MAX_SIZE: u32 = 2048; // Example size, adjust as needed
-    const IS_FIXED_SIZE: bool = false;
+    const BOUND: Bound = Bound::Unbounded;
 }
 
+impl Storable for ReputationRecord {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
 
-thread_local! {
-    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
-        MemoryManager::init(DefaultMemoryImpl::default())
-    );
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
 
-    static ID_COUNTER: RefCell<IdCell> = RefCell::new(
-        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0))), 0)
-            .expect("Cannot create a counter")
-    );
-
-    // Storage for UserProfiles
-    static USER_PROFILES: RefCell<StableBTreeMap<u64, UserProfile, Memory>> = RefCell::new(
-        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1))))
-    );
-
-    // Storage for TransactionRecords
-    static TRANSACTION_RECORDS: RefCell<StableBTreeMap<u64, TransactionRecord, Memory>> = RefCell::new(
-        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2))))
-    );
+    const BOUND: Bound = Bound::Unbounded;
+}
 
-    // Storage for InsuranceContracts
-    static INSURANCE_CONTRACTS: RefCell<StableBTreeMap<u64, InsuranceContract, Memory>> = RefCell::new(
-        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3))))
-    );
+impl Storable for Attestation {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
 
-    // Storage for GovernanceProposals
-    static GOVERNANCE_PROPOSALS: RefCell<StableBTreeMap<u64, GovernanceProposal, Memory>> = RefCell::new(
-        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4))))
-    );
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
 
-    // Storage for StakeAdjustments
-    static STAKE_ADJUSTMENTS: RefCell<StableBTreeMap<u64, StakeAdjustment, Memory>> = RefCell::new(
-        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(5))))
-    );
-    static INSURANCE_CLAIMS: RefCell<StableBTreeMap<u64, InsuranceClaim, Memory>> = RefCell::new(
-        StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(6))) // Assuming MemoryId::new(6) is for insurance claims
-        )
-    );
-    static DISPUTE_STORAGE: RefCell<StableBTreeMap<u64, Dispute, Memory>> = RefCell::new(
-        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(7))))
-    );
-    static DISPUTE_HISTORY_STORAGE: RefCell<StableBTreeMap<u64, DisputeHistory, Memory>> = RefCell::new(
-        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(8))))
-    );
-    
+    const BOUND: Bound = Bound::Unbounded;
 }
-#[derive(candid::CandidType, Serialize, Deserialize)]
-struct DisputePayload {
-    farmer_id: u64,
-    consumer_id: u64,
-    reason: String,
-}
-// fn string_to_user_role(role: String) -> Result<UserRole, String> {
-//     match role.as_str() {
-//         "Farmer" => Ok(UserRole::Farmer),
-//         "Consumer" => Ok(UserRole::Consumer),
-//         _ => Err(format!("Invalid role: {}", role)),
-//     }
-// }
-
-#[ic_cdk::update]
-fn create_user_profile(name: String, role: UserRole, stake_in_dao: f64) -> Option<UserProfile> {
-    
-    let id = ID_COUNTER
-        .with(|counter| {
-            let current_value = *counter.borrow().get();
-            counter.borrow_mut().set(current_value + 1)
-        })
-        .expect("cannot increment id counter");
 
-    let user_profile = UserProfile {
-        id,
-        name,
-        role,
-        transaction_history: Vec::new(),
-        stake_in_dao,
-    };
+impl Storable for ReputationConfig {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
 
-    USER_PROFILES.with(|profiles| {
-        profiles.borrow_mut().insert(id, user_profile.clone());
-    });
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
 
-    Some(user_profile)
+    const BOUND: Bound = Bound::Unbounded;
 }
 
-#[ic_cdk::query]
-fn read_user_profile(user_id: u64) -> Result<UserProfile, Error> {
-    if let Some(profile) = USER_PROFILES.with(|profiles| profiles.borrow().get(&user_id)) {
-        Ok(profile.clone())
-    } else {
-        Err(Error::NotFound {
-            msg: format!("User profile with id={} not found", user_id),
-        })
+impl Storable for Slash {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
     }
+
+    const BOUND: Bound = Bound::Unbounded;
 }
-#[ic_cdk::update]
-fn update_user_profile(user_id: u64, name: String, stake_in_dao: f64) -> Result<UserProfile, Error> {
-    USER_PROFILES.with(|profiles| {
-        let mut profiles = profiles.borrow_mut();
 
-        // Check if the user profile exists
-        if let Some(mut profile) = profiles.remove(&user_id) {
-            // Update the fields
-            profile.name = name;
-            profile.stake_in_dao = stake_in_dao;
+impl Storable for SlashingSchedule {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
 
-            // Insert the updated profile back into the map
-            profiles.insert(user_id, profile.clone());
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
 
-            // Return the updated profile
-            Ok(profile)
-        } else {
-            // User profile not found
-            Err(Error::NotFound {
-                msg: format!("User profile with id={} not found", user_id),
-            })
-        }
-    })
+    const BOUND: Bound = Bound::Unbounded;
 }
 
-#[ic_cdk::update]
-fn delete_user_profile(user_id: u64) -> Result<UserProfile, Error> {
-    USER_PROFILES
-        .with(|profiles| profiles.borrow_mut().remove(&user_id))
-        .ok_or(Error::NotFound {
-            msg: format!("User profile with id={} not found", user_id),
-        })
-}
+impl Storable for Treasury {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
 
-#[derive(candid::CandidType, Deserialize, Serialize)]
-enum Error {
-    NotFound { msg: String },
-}
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
 
-#[ic_cdk::update]
-fn create_transaction_record(
-    amount: f64,
-    date: u64,
-    involved_parties: Vec<u64>,
-) -> Option<TransactionRecord> {
-    let id = ID_COUNTER
-        .with(|counter| {
-            let current_value = *counter.borrow().get();
-            counter.borrow_mut().set(current_value + 1)
-        })
-        .expect("cannot increment id counter");
+    const BOUND: Bound = Bound::Unbounded;
+}
 
-    let transaction_record = TransactionRecord {
-        id,
-        amount,
-        date,
-        involved_parties,
-    };
+impl Storable for StakingConfig {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
 
-    TRANSACTION_RECORDS.with(|records| {
-        records.borrow_mut().insert(id, transaction_record.clone());
-    });
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
 
-    Some(transaction_record)
+    const BOUND: Bound = Bound::Unbounded;
 }
+impl Storable for Dispute {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
 
-#[ic_cdk::query]
-fn read_transaction_record(record_id: u64) -> Result<TransactionRecord, Error> {
-    if let Some(record) = TRANSACTION_RECORDS.with(|records| records.borrow().get(&record_id)) {
-        Ok(record.clone())
-    } else {
-        Err(Error::NotFound {
-            msg: format!("Transaction record with id={} not found", record_id),
-        })
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
     }
-}
 
-#[ic_cdk::update]
-fn update_transaction_record(
-    record_id: u64,
-    amount: f64,
-    date: u64,
-    involved_parties: Vec<u64>,
-) -> Result<TransactionRecord, Error> {
-    TRANSACTION_RECORDS.with(|records| {
-        let mut records = records.borrow_mut();
+    const BOUND: Bound = Bound::Unbounded;
+}
 
-        // Check if the transaction record exists
-        if let Some(mut record) = records.remove(&record_id) {
-            // Update the fields
-            record.amount = amount;
-            record.date = date;
-            record.involved_parties = involved_parties;
+impl Storable for Event {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
 
-            // Insert the updated record back into the map
-            records.insert(record_id, record.clone());
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
 
-            // Return the updated record
-            Ok(record)
-        } else {
-            // Transaction record not found
-            Err(Error::NotFound {
-                msg: format!("Transaction record with id={} not found", record_id),
-            })
-        }
-    })
+    const BOUND: Bound = Bound::Unbounded;
 }
 
-#[ic_cdk::update]
-fn delete_transaction_record(record_id: u64) -> Result<TransactionRecord, Error> {
-    TRANSACTION_RECORDS
-        .with(|records| records.borrow_mut().remove(&record_id))
-        .ok_or(Error::NotFound {
-            msg: format!("Transaction record with id={} not found", record_id),
-        })
+impl Storable for ExportManifest {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
 }
 
-#[ic_cdk::update]
-fn create_insurance_contract(
-    farmer_id: u64,
-    consumer_id: u64,
-    terms: String,
-    conditions: String,
-    payout_criteria: String,
-) -> Option<InsuranceContract> {
-    let id = ID_COUNTER
-        .with(|counter| {
-            let current_value = *counter.borrow().get();
-            counter.borrow_mut().set(current_value + 1)
-        })
-        .expect("cannot increment id counter");
+impl Storable for ExportChunk {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
 
-    let insurance_contract = InsuranceContract {
-        id,
-        farmer_id,
-        consumer_id,
-        terms,
-        conditions,
-        payout_criteria,
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        ExportChunk(bytes.into_owned())
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: EXPORT_CHUNK_SIZE as u32,
+        is_fixed_size: false,
     };
+}
 
-    INSURANCE_CONTRACTS.with(|contracts| {
-        contracts.borrow_mut().insert(id, insurance_contract.clone());
-    });
+impl Storable for WeatherObservation {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
 
-    Some(insurance_contract)
+    const BOUND: Bound = Bound::Unbounded;
 }
 
-#[ic_cdk::query]
-fn read_insurance_contract(contract_id: u64) -> Result<InsuranceContract, Error> {
-    if let Some(contract) = INSURANCE_CONTRACTS.with(|contracts| contracts.borrow().get(&contract_id)) {
-        Ok(contract.clone())
-    } else {
-        Err(Error::NotFound {
-            msg: format!("Insurance contract with id={} not found", contract_id),
-        })
+impl Storable for FraudReport {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
     }
-}
 
-#[ic_cdk::update]
-fn update_insurance_contract(
-    contract_id: u64,
-    farmer_id: u64,
-    consumer_id: u64,
-    terms: String,
-    conditions: String,
-    payout_criteria: String,
-) -> Result<InsuranceContract, Error> {
-    INSURANCE_CONTRACTS.with(|contracts| {
-        let mut contracts = contracts.borrow_mut();
-
-        // Check if the insurance contract exists
-        if let Some(mut contract) = contracts.remove(&contract_id) {
-            // Update the fields
-            contract.farmer_id = farmer_id;
-            contract.consumer_id = consumer_id;
-            contract.terms = terms;
-            contract.conditions = conditions;
-            contract.payout_criteria = payout_criteria;
-
-            // Insert the updated contract back into the map
-            contracts.insert(contract_id, contract.clone());
-
-            // Return the updated contract
-            Ok(contract)
-        } else {
-            // Insurance contract not found
-            Err(Error::NotFound {
-                msg: format!("Insurance contract with id={} not found", contract_id),
-            })
-        }
-    })
-}
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
 
-#[ic_cdk::update]
-fn delete_insurance_contract(contract_id: u64) -> Result<InsuranceContract, Error> {
-    INSURANCE_CONTRACTS
-        .with(|contracts| contracts.borrow_mut().remove(&contract_id))
-        .ok_or(Error::NotFound {
-            msg: format!("Insurance contract with id={} not found", contract_id),
-        })
+    const BOUND: Bound = Bound::Unbounded;
 }
 
-#[ic_cdk::update]
-fn create_governance_proposal(
-    proposal_details: String,
-    proposer_id: u64,
-    voting_records: Vec<VotingRecord>,
-) -> Option<GovernanceProposal> {
-    let id = ID_COUNTER
-        .with(|counter| {
-            let current_value = *counter.borrow().get();
-            counter.borrow_mut().set(current_value + 1)
-        })
-        .expect("cannot increment id counter");
+impl Storable for Assessment {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
 
-    let governance_proposal = GovernanceProposal {
-        id,
-        proposal_details,
-        proposer_id,
-        voting_records,
-    };
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
 
-    GOVERNANCE_PROPOSALS.with(|proposals| {
-        proposals.borrow_mut().insert(id, governance_proposal.clone());
-    });
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for Crop {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
 
-    Some(governance_proposal)
+    const BOUND: Bound = Bound::Unbounded;
 }
 
-#[ic_cdk::query]
-fn read_governance_proposal(proposal_id: u64) -> Result<GovernanceProposal, Error> {
-    if let Some(proposal) = GOVERNANCE_PROPOSALS.with(|proposals| proposals.borrow().get(&proposal_id)) {
-        Ok(proposal.clone())
+impl Storable for Plot {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for UploadSession {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for BlobMeta {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for BlobChunk {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        BlobChunk(bytes.into_owned())
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: BLOB_CHUNK_SIZE as u32,
+        is_fixed_size: false,
+    };
+}
+
+impl Storable for JuryRound {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for JuryVote {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for VotingRecord {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for DisputeEvidence {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for DisputeStatement {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
+        MemoryManager::init(DefaultMemoryImpl::default())
+    );
+
+    static ID_COUNTER: RefCell<IdCell> = RefCell::new(
+        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0))), 0)
+            .expect("Cannot create a counter")
+    );
+
+    // Storage for UserProfiles
+    static USER_PROFILES: RefCell<StableBTreeMap<u64, UserProfile, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1))))
+    );
+
+    // Storage for TransactionRecords
+    static TRANSACTION_RECORDS: RefCell<StableBTreeMap<u64, TransactionRecord, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2))))
+    );
+
+    // Storage for InsuranceContracts
+    static INSURANCE_CONTRACTS: RefCell<StableBTreeMap<u64, InsuranceContract, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3))))
+    );
+
+    // Storage for GovernanceProposals
+    static GOVERNANCE_PROPOSALS: RefCell<StableBTreeMap<u64, GovernanceProposal, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4))))
+    );
+
+    // Storage for StakeAdjustments
+    static STAKE_ADJUSTMENTS: RefCell<StableBTreeMap<u64, StakeAdjustment, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(5))))
+    );
+    static INSURANCE_CLAIMS: RefCell<StableBTreeMap<u64, InsuranceClaim, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(6))) // Assuming MemoryId::new(6) is for insurance claims
+        )
+    );
+    static DISPUTE_STORAGE: RefCell<StableBTreeMap<u64, Dispute, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(7))))
+    );
+    static DISPUTE_HISTORY_STORAGE: RefCell<StableBTreeMap<u64, DisputeHistory, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(8))))
+    );
+    static GOVERNANCE_CONFIG: RefCell<Cell<GovernanceConfig, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(9))), GovernanceConfig::default())
+            .expect("Cannot create the governance config")
+    );
+    static STAKE_ACCOUNTS: RefCell<StableBTreeMap<u64, StakeAccount, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(10))))
+    );
+    static STAKING_CONFIG: RefCell<Cell<StakingConfig, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11))), StakingConfig::default())
+            .expect("Cannot create the staking config")
+    );
+    static REPUTATION_RECORDS: RefCell<StableBTreeMap<u64, ReputationRecord, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(12))))
+    );
+    static REPUTATION_CONFIG: RefCell<Cell<ReputationConfig, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(13))), ReputationConfig::default())
+            .expect("Cannot create the reputation config")
+    );
+    // Peer attestations keyed by (attester_id, subject_id)
+    static ATTESTATIONS: RefCell<StableBTreeMap<(u64, u64), Attestation, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(14))))
+    );
+    static SLASHES: RefCell<StableBTreeMap<u64, Slash, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(15))))
+    );
+    static SLASHING_SCHEDULE: RefCell<Cell<SlashingSchedule, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(16))), SlashingSchedule::default())
+            .expect("Cannot create the slashing schedule")
+    );
+    static TREASURY: RefCell<Cell<Treasury, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(17))), Treasury::default())
+            .expect("Cannot create the treasury")
+    );
+    // Last claim history epoch each user has been rewarded for
+    static CLAIM_HISTORY_EPOCHS: RefCell<StableBTreeMap<u64, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(18))))
+    );
+    // Pool of user ids that can be assigned as arbitrators
+    static ARBITRATORS: RefCell<StableBTreeMap<u64, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(19))))
+    );
+    // Evidence keyed by (dispute_id, evidence_id)
+    static DISPUTE_EVIDENCE: RefCell<StableBTreeMap<(u64, u64), DisputeEvidence, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(20))))
+    );
+    // Statements keyed by (dispute_id, party_id)
+    static DISPUTE_STATEMENTS: RefCell<StableBTreeMap<(u64, u64), DisputeStatement, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(21))))
+    );
+    static JURY_ROUNDS: RefCell<StableBTreeMap<u64, JuryRound, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(22))))
+    );
+    // Votes keyed by (round_id, juror_id)
+    static JURY_VOTES: RefCell<StableBTreeMap<(u64, u64), JuryVote, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(23))))
+    );
+    static UPLOAD_SESSIONS: RefCell<StableBTreeMap<u64, UploadSession, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(24))))
+    );
+    // Chunks of uploads in progress keyed by (session_id, chunk index)
+    static UPLOAD_CHUNKS: RefCell<StableBTreeMap<(u64, u32), BlobChunk, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(25))))
+    );
+    static BLOB_METAS: RefCell<StableBTreeMap<[u8; 32], BlobMeta, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(26))))
+    );
+    // Chunks of committed blobs keyed by (SHA-256 hash, chunk index)
+    static BLOB_CHUNKS: RefCell<StableBTreeMap<([u8; 32], u32), BlobChunk, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(27))))
+    );
+    // Bytes stored or reserved per user
+    static BLOB_USAGE: RefCell<StableBTreeMap<u64, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(28))))
+    );
+    static PLOTS: RefCell<StableBTreeMap<u64, Plot, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(29))))
+    );
+    static CROPS: RefCell<StableBTreeMap<u64, Crop, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(30))))
+    );
+    // Assessment reports keyed by (claim_id, assessment_id)
+    static ASSESSMENTS: RefCell<StableBTreeMap<(u64, u64), Assessment, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(31))))
+    );
+    static WEATHER_OBSERVATIONS: RefCell<StableBTreeMap<u64, WeatherObservation, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(32))))
+    );
+    // Latest fraud screening of each claim
+    static FRAUD_REPORTS: RefCell<StableBTreeMap<u64, FraudReport, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(33))))
+    );
+    // Hashes of the certified records, rebuilt from stable memory after upgrades
+    static CERTIFIED_RECORDS: RefCell<CertifiedTree> = const { RefCell::new(RbTree::new()) };
+
+    // Call and error counts per update endpoint; kept on the heap, so they restart at zero after an upgrade
+    static ENDPOINT_METRICS: RefCell<BTreeMap<&'static str, EndpointMetrics>> = const { RefCell::new(BTreeMap::new()) };
+
+    // Progress of the export being built and the reason writes are suspended, if they are. Both
+    // are lost on upgrade, which abandons the export or backup
+    static EXPORT_BUILD: RefCell<Option<ExportBuild>> = const { RefCell::new(None) };
+    static WRITE_FREEZE: RefCell<Option<WriteFreeze>> = const { RefCell::new(None) };
+
+    // The backup being downloaded and the backup being uploaded for a restore; an upgrade abandons both
+    static BACKUP: RefCell<Option<Backup>> = const { RefCell::new(None) };
+    static RESTORE_UPLOAD: RefCell<Option<RestoreUpload>> = const { RefCell::new(None) };
+    // Append-only log of domain events keyed by sequence number, starting at 1
+    static EVENT_LOG: RefCell<StableBTreeMap<u64, Event, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(34))))
+    );
+
+    // Ids of imported records keyed by the hash of their kind and external reference
+    static EXTERNAL_REFS: RefCell<StableBTreeMap<[u8; 32], u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(35))))
+    );
+
+    // Votes keyed by (proposal_id, user_id)
+    static VOTING_RECORDS: RefCell<StableBTreeMap<(u64, u64), VotingRecord, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(36))))
+    );
+
+    // Transaction records of each involved party, keyed by (user_id, transaction_id)
+    static USER_TRANSACTIONS: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(37))))
+    );
+
+    // Version of the stored records, compared with STATE_VERSION after an upgrade
+    static STORED_STATE_VERSION: RefCell<Cell<u32, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(38))), 0)
+            .expect("Cannot create the state version")
+    );
+
+    // Users who uploaded each blob, keyed by (hash, user_id); identical uploads share one blob
+    static BLOB_HOLDERS: RefCell<StableBTreeMap<([u8; 32], u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(39))))
+    );
+
+    // Exports for auditors keyed by snapshot id, with their files keyed by (snapshot_id, dataset, index)
+    static EXPORT_MANIFESTS: RefCell<StableBTreeMap<u64, ExportManifest, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(40))))
+    );
+    static EXPORT_CHUNKS: RefCell<StableBTreeMap<(u64, u8, u32), ExportChunk, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(41))))
+    );
+}
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct ContractCoverage {
+    plot_ids: Vec<u64>,
+    crop_ids: Vec<u64>,
+    sum_insured: f64,
+}
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct PlotPayload {
+    name: String,
+    geometry: PlotGeometry,
+    area_hectares: Option<f64>, // Computed from the polygon when omitted; required for points
+    soil_type: SoilType,
+    region_code: String,
+}
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct DisputePayload {
+    subject: DisputeSubject,
+    raised_by: u64,
+    reason: String,
+}
+// fn string_to_user_role(role: String) -> Result<UserRole, String> {
+//     match role.as_str() {
+//         "Farmer" => Ok(UserRole::Farmer),
+//         "Consumer" => Ok(UserRole::Consumer),
+//         _ => Err(format!("Invalid role: {}", role)),
+//     }
+// }
+
+#[ic_cdk::update]
+fn create_user_profile(name: String, role: UserRole, stake_in_dao: f64) -> Result<UserProfile, Error> {
+    observe("create_user_profile", || {
+        validate_length("name", &name, MAX_USER_NAME_LENGTH)?;
+        if !stake_in_dao.is_finite() || stake_in_dao < 0.0 {
+            return Err(Error::InvalidInput {
+                msg: "stake_in_dao cannot be negative".to_string(),
+            });
+        }
+        // Other users bond stake from their funded balance once the profile exists
+        if stake_in_dao > 0.0 {
+            ensure_controller()?;
+        }
+
+        let id = ID_COUNTER
+            .with(|counter| {
+                let current_value = *counter.borrow().get();
+                counter.borrow_mut().set(current_value + 1)
+            })
+            .expect("cannot increment id counter");
+
+        let mut user_profile = UserProfile {
+            id,
+            name,
+            role,
+            stake_in_dao: 0.0,
+            principal: caller(),
+        };
+
+        store_user(&user_profile);
+        record_event(EventKind::UserCreated {
+            user_id: id,
+            role: user_profile.role.clone(),
+        });
+
+        // The initial stake granted by a controller is bonded like any other stake so that it
+        // shows up in the ledger
+        if stake_in_dao > 0.0 {
+            apply_stake_change(id, stake_in_dao, StakeCause::Bond, None, "Initial stake".to_string())?;
+            user_profile.stake_in_dao = stake_in_dao;
+        }
+
+        Ok(user_profile)
+    })
+}
+
+#[ic_cdk::query]
+fn read_user_profile(user_id: u64) -> Result<UserProfile, Error> {
+    if let Some(profile) = USER_PROFILES.with(|profiles| profiles.borrow().get(&user_id)) {
+        Ok(profile.clone())
     } else {
         Err(Error::NotFound {
-            msg: format!("Governance proposal with id={} not found", proposal_id),
+            msg: format!("User profile with id={} not found", user_id),
+        })
+    }
+}
+#[ic_cdk::update]
+fn update_user_profile(user_id: u64, name: String) -> Result<UserProfile, Error> {
+    observe("update_user_profile", || {
+        validate_length("name", &name, MAX_USER_NAME_LENGTH)?;
+        ensure_controller().or_else(|_| ensure_caller_is(user_id))?;
+        USER_PROFILES.with(|profiles| {
+            let mut profiles = profiles.borrow_mut();
+
+            // Check if the user profile exists
+            if let Some(mut profile) = profiles.remove(&user_id) {
+                // Update the fields
+                profile.name = name;
+
+                // Insert the updated profile back into the map
+                profiles.insert(user_id, profile.clone());
+                certify_record(CERTIFIED_USERS, user_id, &profile);
+
+                // Return the updated profile
+                Ok(profile)
+            } else {
+                // User profile not found
+                Err(Error::NotFound {
+                    msg: format!("User profile with id={} not found", user_id),
+                })
+            }
+        })
+    })
+}
+
+// Hands a profile to another identity, e.g. one migrated from before principals were recorded
+#[ic_cdk::update]
+fn assign_user_principal(user_id: u64, principal: Principal) -> Result<UserProfile, Error> {
+    observe("assign_user_principal", || {
+        ensure_controller()?;
+        if principal == Principal::anonymous() {
+            return Err(Error::InvalidInput {
+                msg: "Profiles cannot be assigned to the anonymous principal".to_string(),
+            });
+        }
+        let mut profile = USER_PROFILES
+            .with(|profiles| profiles.borrow().get(&user_id))
+            .ok_or(Error::NotFound {
+                msg: format!("User profile with id={} not found", user_id),
+            })?;
+        profile.principal = principal;
+        store_user(&profile);
+        Ok(profile)
+    })
+}
+
+#[ic_cdk::update]
+fn delete_user_profile(user_id: u64) -> Result<UserProfile, Error> {
+    observe("delete_user_profile", || {
+        ensure_controller().or_else(|_| ensure_caller_is(user_id))?;
+        // The stake ledger has to account for every token, so nothing may be left behind
+        let balance = get_stake_balance(user_id)?;
+        if balance.bonded > 0.0 || balance.unbonding > 0.0 || balance.available > 0.0 {
+            return Err(Error::InvalidInput {
+                msg: "Profiles can only be deleted once all stake has been unbonded and withdrawn".to_string(),
+            });
+        }
+        let profile = USER_PROFILES
+            .with(|profiles| profiles.borrow_mut().remove(&user_id))
+            .ok_or(Error::NotFound {
+                msg: format!("User profile with id={} not found", user_id),
+            })?;
+        uncertify_record(CERTIFIED_USERS, user_id);
+        USER_TRANSACTIONS.with(|index| {
+            let mut index = index.borrow_mut();
+            let keys: Vec<(u64, u64)> = index.range((user_id, 0)..(user_id + 1, 0)).map(|(key, _)| key).collect();
+            for key in keys {
+                index.remove(&key);
+            }
+        });
+        Ok(profile)
+    })
+}
+
+#[derive(candid::CandidType, Deserialize, Serialize)]
+enum Error {
+    NotFound { msg: String },
+    InvalidInput { msg: String },
+    Unauthorized { msg: String },
+}
+
+fn ensure_caller_is(user_id: u64) -> Result<(), Error> {
+    let profile = USER_PROFILES
+        .with(|profiles| profiles.borrow().get(&user_id))
+        .ok_or(Error::NotFound {
+            msg: format!("User profile with id={} not found", user_id),
+        })?;
+    if is_profile_caller(&profile) {
+        Ok(())
+    } else {
+        Err(Error::Unauthorized {
+            msg: format!("Caller does not act on behalf of user with id={}", user_id),
+        })
+    }
+}
+
+// Profiles migrated from before principals were recorded hold the anonymous principal until a
+// controller assigns one, so nobody can act for them in the meantime
+fn is_profile_caller(profile: &UserProfile) -> bool {
+    let caller = caller();
+    caller != Principal::anonymous() && profile.principal == caller
+}
+
+fn validate_length(field: &str, value: &str, max: usize) -> Result<(), Error> {
+    if value.chars().count() > max {
+        return Err(Error::InvalidInput {
+            msg: format!("{} must be at most {} characters", field, max),
+        });
+    }
+    Ok(())
+}
+
+fn validate_count(field: &str, count: usize, max: usize) -> Result<(), Error> {
+    if count > max {
+        return Err(Error::InvalidInput {
+            msg: format!("{} can have at most {} entries", field, max),
+        });
+    }
+    Ok(())
+}
+
+fn validate_contract_fields(
+    terms: &str,
+    conditions: &str,
+    payout_criteria: &str,
+    plot_count: usize,
+    crop_count: usize,
+) -> Result<(), Error> {
+    validate_length("terms", terms, MAX_CONTRACT_TEXT_LENGTH)?;
+    validate_length("conditions", conditions, MAX_CONTRACT_TEXT_LENGTH)?;
+    validate_length("payout_criteria", payout_criteria, MAX_CONTRACT_TEXT_LENGTH)?;
+    validate_count("plot_ids", plot_count, MAX_CONTRACT_PLOTS)?;
+    validate_count("crop_ids", crop_count, MAX_CONTRACT_CROPS)
+}
+
+fn ensure_controller() -> Result<(), Error> {
+    if is_controller(&caller()) {
+        Ok(())
+    } else {
+        Err(Error::Unauthorized {
+            msg: "Only controllers of the canister can perform this action".to_string(),
         })
     }
-}
+}
+
+#[ic_cdk::update]
+fn create_transaction_record(
+    amount: f64,
+    date: u64,
+    involved_parties: Vec<u64>,
+) -> Result<TransactionRecord, Error> {
+    observe("create_transaction_record", || {
+        validate_count("involved_parties", involved_parties.len(), MAX_INVOLVED_PARTIES)?;
+        let id = ID_COUNTER
+            .with(|counter| {
+                let current_value = *counter.borrow().get();
+                counter.borrow_mut().set(current_value + 1)
+            })
+            .expect("cannot increment id counter");
+
+        let transaction_record = TransactionRecord {
+            id,
+            amount,
+            date,
+            involved_parties,
+        };
+
+        store_transaction_record(&transaction_record);
+
+        Ok(transaction_record)
+    })
+}
+
+#[ic_cdk::query]
+fn read_transaction_record(record_id: u64) -> Result<TransactionRecord, Error> {
+    if let Some(record) = TRANSACTION_RECORDS.with(|records| records.borrow().get(&record_id)) {
+        Ok(record.clone())
+    } else {
+        Err(Error::NotFound {
+            msg: format!("Transaction record with id={} not found", record_id),
+        })
+    }
+}
+
+#[ic_cdk::update]
+fn update_transaction_record(
+    record_id: u64,
+    amount: f64,
+    date: u64,
+    involved_parties: Vec<u64>,
+) -> Result<TransactionRecord, Error> {
+    observe("update_transaction_record", || {
+        validate_count("involved_parties", involved_parties.len(), MAX_INVOLVED_PARTIES)?;
+        let mut record = read_transaction_record(record_id)?;
+        unindex_transaction_record(&record);
+        record.amount = amount;
+        record.date = date;
+        record.involved_parties = involved_parties;
+        store_transaction_record(&record);
+        Ok(record)
+    })
+}
+
+#[ic_cdk::update]
+fn delete_transaction_record(record_id: u64) -> Result<TransactionRecord, Error> {
+    observe("delete_transaction_record", || {
+        let record = TRANSACTION_RECORDS
+            .with(|records| records.borrow_mut().remove(&record_id))
+            .ok_or(Error::NotFound {
+                msg: format!("Transaction record with id={} not found", record_id),
+            })?;
+        unindex_transaction_record(&record);
+        Ok(record)
+    })
+}
+
+fn store_transaction_record(record: &TransactionRecord) {
+    TRANSACTION_RECORDS.with(|records| records.borrow_mut().insert(record.id, record.clone()));
+    USER_TRANSACTIONS.with(|index| {
+        let mut index = index.borrow_mut();
+        for party_id in &record.involved_parties {
+            index.insert((*party_id, record.id), ());
+        }
+    });
+}
+
+fn unindex_transaction_record(record: &TransactionRecord) {
+    USER_TRANSACTIONS.with(|index| {
+        let mut index = index.borrow_mut();
+        for party_id in &record.involved_parties {
+            index.remove(&(*party_id, record.id));
+        }
+    });
+}
+
+// Transaction records involving a user, oldest first
+#[ic_cdk::query]
+fn get_user_transactions(user_id: u64, offset: u64, limit: u64) -> TransactionRecordPage {
+    USER_TRANSACTIONS.with(|index| {
+        let index = index.borrow();
+        TransactionRecordPage {
+            total: index.range((user_id, 0)..(user_id + 1, 0)).count() as u64,
+            records: index
+                .range((user_id, 0)..(user_id + 1, 0))
+                .skip(offset as usize)
+                .take(limit.min(MAX_PAGE_SIZE) as usize)
+                .filter_map(|((_, id), _)| TRANSACTION_RECORDS.with(|records| records.borrow().get(&id)))
+                .collect(),
+        }
+    })
+}
+
+#[ic_cdk::update]
+fn create_insurance_contract(
+    farmer_id: u64,
+    consumer_id: u64,
+    terms: String,
+    conditions: String,
+    payout_criteria: String,
+    coverage: ContractCoverage,
+) -> Result<InsuranceContract, Error> {
+    observe("create_insurance_contract", || {
+        validate_contract_fields(
+            &terms,
+            &conditions,
+            &payout_criteria,
+            coverage.plot_ids.len(),
+            coverage.crop_ids.len(),
+        )?;
+        validate_contract_plots(farmer_id, &coverage.plot_ids)?;
+        validate_crop_ids(&coverage.crop_ids)?;
+        validate_stake_amount(coverage.sum_insured)?;
+
+        let id = ID_COUNTER
+            .with(|counter| {
+                let current_value = *counter.borrow().get();
+                counter.borrow_mut().set(current_value + 1)
+            })
+            .expect("cannot increment id counter");
+
+        let insurance_contract = InsuranceContract {
+            id,
+            farmer_id,
+            consumer_id,
+            terms,
+            conditions,
+            payout_criteria,
+            status: ContractStatus::Pending,
+            activated_at: None,
+            plot_ids: coverage.plot_ids,
+            crop_ids: coverage.crop_ids,
+            sum_insured: coverage.sum_insured,
+        };
+
+        store_contract(&insurance_contract);
+        record_event(EventKind::ContractCreated {
+            contract_id: id,
+            farmer_id,
+            consumer_id,
+        });
+
+        Ok(insurance_contract)
+    })
+}
+
+#[ic_cdk::query]
+fn read_insurance_contract(contract_id: u64) -> Result<InsuranceContract, Error> {
+    if let Some(contract) = INSURANCE_CONTRACTS.with(|contracts| contracts.borrow().get(&contract_id)) {
+        Ok(contract.clone())
+    } else {
+        Err(Error::NotFound {
+            msg: format!("Insurance contract with id={} not found", contract_id),
+        })
+    }
+}
+
+#[ic_cdk::update]
+fn update_insurance_contract(
+    contract_id: u64,
+    farmer_id: u64,
+    consumer_id: u64,
+    terms: String,
+    conditions: String,
+    payout_criteria: String,
+    coverage: ContractCoverage,
+) -> Result<InsuranceContract, Error> {
+    observe("update_insurance_contract", || {
+        validate_contract_fields(
+            &terms,
+            &conditions,
+            &payout_criteria,
+            coverage.plot_ids.len(),
+            coverage.crop_ids.len(),
+        )?;
+        validate_contract_plots(farmer_id, &coverage.plot_ids)?;
+        validate_crop_ids(&coverage.crop_ids)?;
+        validate_stake_amount(coverage.sum_insured)?;
+        let mut contract = read_insurance_contract(contract_id)?;
+        ensure_controller()
+            .or_else(|_| ensure_caller_is(contract.farmer_id))
+            .or_else(|_| ensure_caller_is(contract.consumer_id))?;
+        // Once a contract is active, neither party can change who is insured or for what
+        let changes_coverage = contract.farmer_id != farmer_id
+            || contract.consumer_id != consumer_id
+            || contract.plot_ids != coverage.plot_ids
+            || contract.crop_ids != coverage.crop_ids
+            || contract.sum_insured != coverage.sum_insured;
+        if changes_coverage && contract.status != ContractStatus::Pending {
+            return Err(Error::InvalidInput {
+                msg: "The parties and coverage can only be changed while the contract is pending".to_string(),
+            });
+        }
+
+        contract.farmer_id = farmer_id;
+        contract.consumer_id = consumer_id;
+        contract.terms = terms;
+        contract.conditions = conditions;
+        contract.payout_criteria = payout_criteria;
+        contract.plot_ids = coverage.plot_ids;
+        contract.crop_ids = coverage.crop_ids;
+        contract.sum_insured = coverage.sum_insured;
+        store_contract(&contract);
+        Ok(contract)
+    })
+}
+
+#[ic_cdk::update]
+fn delete_insurance_contract(contract_id: u64) -> Result<InsuranceContract, Error> {
+    observe("delete_insurance_contract", || {
+        let contract = INSURANCE_CONTRACTS
+            .with(|contracts| contracts.borrow_mut().remove(&contract_id))
+            .ok_or(Error::NotFound {
+                msg: format!("Insurance contract with id={} not found", contract_id),
+            })?;
+        uncertify_record(CERTIFIED_CONTRACTS, contract_id);
+        Ok(contract)
+    })
+}
+
+// Contracts may only insure plots registered by their farmer
+fn validate_contract_plots(farmer_id: u64, plot_ids: &[u64]) -> Result<(), Error> {
+    for plot_id in plot_ids {
+        let plot = load_plot(*plot_id)?;
+        if plot.farmer_id != farmer_id {
+            return Err(Error::InvalidInput {
+                msg: format!("Plot with id={} does not belong to the farmer", plot_id),
+            });
+        }
+    }
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn activate_insurance_contract(contract_id: u64) -> Result<InsuranceContract, Error> {
+    observe("activate_insurance_contract", || {
+        let mut contract = INSURANCE_CONTRACTS
+            .with(|contracts| contracts.borrow().get(&contract_id))
+            .ok_or(Error::NotFound {
+                msg: format!("Insurance contract with id={} not found", contract_id),
+            })?;
+        // The farmer proposes the contract, so activating it is the consumer's confirmation
+        ensure_controller().or_else(|_| ensure_caller_is(contract.consumer_id))?;
+        if contract.status != ContractStatus::Pending {
+            return Err(Error::InvalidInput {
+                msg: "Only pending contracts can be activated".to_string(),
+            });
+        }
+
+        contract.status = ContractStatus::Active;
+        contract.activated_at = Some(time());
+        store_contract(&contract);
+        record_event(EventKind::ContractActivated { contract_id });
+        Ok(contract)
+    })
+}
+
+// Closes an active contract; a breaching party loses reputation, otherwise both parties gain some
+#[ic_cdk::update]
+fn complete_insurance_contract(contract_id: u64, breached_by: Option<u64>) -> Result<InsuranceContract, Error> {
+    observe("complete_insurance_contract", || {
+        ensure_controller()?;
+        let mut contract = INSURANCE_CONTRACTS
+            .with(|contracts| contracts.borrow().get(&contract_id))
+            .ok_or(Error::NotFound {
+                msg: format!("Insurance contract with id={} not found", contract_id),
+            })?;
+        if contract.status != ContractStatus::Active {
+            return Err(Error::InvalidInput {
+                msg: "Only active contracts can be completed".to_string(),
+            });
+        }
+
+        match breached_by {
+            Some(party_id) if party_id == contract.farmer_id || party_id == contract.consumer_id => {
+                record_reputation_event(party_id, ReputationComponent::ContractFulfilment, -1.0);
+                contract.status = ContractStatus::Breached { party_id };
+            }
+            Some(party_id) => {
+                return Err(Error::InvalidInput {
+                    msg: format!("User with id={} is not a party to this contract", party_id),
+                })
+            }
+            None => {
+                record_reputation_event(contract.farmer_id, ReputationComponent::ContractFulfilment, 1.0);
+                record_reputation_event(contract.consumer_id, ReputationComponent::ContractFulfilment, 1.0);
+                contract.status = ContractStatus::Fulfilled;
+            }
+        }
+
+        store_contract(&contract);
+        record_event(EventKind::ContractCompleted {
+            contract_id,
+            status: contract.status.clone(),
+        });
+        Ok(contract)
+    })
+}
+
+#[ic_cdk::update]
+fn create_governance_proposal(
+    proposal_details: String,
+    proposer_id: u64,
+    action: Option<ProposalAction>,
+) -> Result<GovernanceProposal, Error> {
+    observe("create_governance_proposal", || {
+        open_governance_proposal(proposer_id, proposal_details, action)
+    })
+}
+
+#[ic_cdk::query]
+fn get_governance_config() -> GovernanceConfig {
+    GOVERNANCE_CONFIG.with(|config| config.borrow().get().clone())
+}
+
+#[ic_cdk::query]
+fn read_governance_proposal(proposal_id: u64) -> Result<GovernanceProposal, Error> {
+    if let Some(proposal) = GOVERNANCE_PROPOSALS.with(|proposals| proposals.borrow().get(&proposal_id)) {
+        Ok(proposal.clone())
+    } else {
+        Err(Error::NotFound {
+            msg: format!("Governance proposal with id={} not found", proposal_id),
+        })
+    }
+}
+
+#[ic_cdk::update]
+fn update_governance_proposal(proposal_id: u64, proposal_details: String) -> Result<GovernanceProposal, Error> {
+    observe("update_governance_proposal", || {
+        validate_length("proposal_details", &proposal_details, MAX_PROPOSAL_DETAILS_LENGTH)?;
+        let mut proposal = read_governance_proposal(proposal_id)?;
+        ensure_caller_is(proposal.proposer_id)?;
+        // Voters must be able to rely on the text they voted on
+        let has_votes = VOTING_RECORDS.with(|votes| {
+            votes.borrow().range((proposal_id, 0)..(proposal_id + 1, 0)).next().is_some()
+        });
+        if proposal.status != ProposalStatus::Open || has_votes {
+            return Err(Error::InvalidInput {
+                msg: "Proposals can only be edited while open and before the first vote".to_string(),
+            });
+        }
+
+        proposal.proposal_details = proposal_details;
+        store_proposal(&proposal);
+        Ok(proposal)
+    })
+}
+
+#[ic_cdk::update]
+fn delete_governance_proposal(proposal_id: u64) -> Result<GovernanceProposal, Error> {
+    observe("delete_governance_proposal", || {
+        let proposal = read_governance_proposal(proposal_id)?;
+        ensure_controller().or_else(|_| ensure_caller_is(proposal.proposer_id))?;
+        // Deleting a proposal that passed would veto the vote before its action is executed
+        if !matches!(proposal.status, ProposalStatus::Expired | ProposalStatus::Rejected | ProposalStatus::Executed) {
+            return Err(Error::InvalidInput {
+                msg: "Only expired, rejected or executed proposals can be deleted".to_string(),
+            });
+        }
+        GOVERNANCE_PROPOSALS.with(|proposals| proposals.borrow_mut().remove(&proposal_id));
+        uncertify_record(CERTIFIED_PROPOSALS, proposal_id);
+        VOTING_RECORDS.with(|votes| {
+            let mut votes = votes.borrow_mut();
+            let keys: Vec<(u64, u64)> = votes
+                .range((proposal_id, 0)..(proposal_id + 1, 0))
+                .map(|(key, _)| key)
+                .collect();
+            for key in keys {
+                votes.remove(&key);
+            }
+        });
+        Ok(proposal)
+    })
+}
+
+#[ic_cdk::query]
+fn read_stake_adjustment(adjustment_id: u64) -> Result<StakeAdjustment, Error> {
+    if let Some(adjustment) = STAKE_ADJUSTMENTS.with(|adjustments| adjustments.borrow().get(&adjustment_id)) {
+        Ok(adjustment.clone())
+    } else {
+        Err(Error::NotFound {
+            msg: format!("Stake adjustment with id={} not found", adjustment_id),
+        })
+    }
+}
+
+// Reverses an earlier ledger entry by appending an opposite adjustment that references it
+#[ic_cdk::update]
+fn compensate_stake_adjustment(adjustment_id: u64, reason: String) -> Result<StakeAdjustment, Error> {
+    observe("compensate_stake_adjustment", || {
+        ensure_controller()?;
+        validate_length("reason", &reason, MAX_REASON_LENGTH)?;
+        let original = STAKE_ADJUSTMENTS
+            .with(|adjustments| adjustments.borrow().get(&adjustment_id))
+            .ok_or(Error::NotFound {
+                msg: format!("Stake adjustment with id={} not found", adjustment_id),
+            })?;
+
+        let compensation_id = apply_stake_change(
+            original.user_id,
+            original.old_stake - original.new_stake,
+            StakeCause::Compensation,
+            Some(adjustment_id),
+            reason,
+        )?;
+        read_stake_adjustment(compensation_id)
+    })
+}
+
+// Changes the bonded stake of a user and appends the matching entry to the stake ledger.
+// This is the only place allowed to modify `stake_in_dao`. Returns the id of the new entry.
+fn apply_stake_change(
+    user_id: u64,
+    delta: f64,
+    cause: StakeCause,
+    source_id: Option<u64>,
+    reason: String,
+) -> Result<u64, Error> {
+    let mut profile = USER_PROFILES.with(|profiles| profiles.borrow().get(&user_id)).ok_or(Error::NotFound {
+        msg: format!("User profile with id={} not found", user_id),
+    })?;
+
+    let old_stake = profile.stake_in_dao;
+    let new_stake = old_stake + delta;
+    if new_stake < 0.0 {
+        return Err(Error::InvalidInput {
+            msg: format!("User with id={} has a bonded stake of {} only", user_id, old_stake),
+        });
+    }
+    profile.stake_in_dao = new_stake;
+    store_user(&profile);
+
+    let id = ID_COUNTER.with(|counter| {
+        let current_value = *counter.borrow().get();
+        counter.borrow_mut().set(current_value + 1).unwrap();
+        current_value
+    });
+    let adjustment = StakeAdjustment {
+        id,
+        user_id,
+        old_stake,
+        new_stake,
+        reason,
+        cause: cause.clone(),
+        source_id,
+        actor: caller(),
+        timestamp: time(),
+    };
+    STAKE_ADJUSTMENTS.with(|adjustments| adjustments.borrow_mut().insert(id, adjustment));
+    record_event(EventKind::StakeAdjusted {
+        adjustment_id: id,
+        user_id,
+        delta,
+        cause,
+    });
+
+    Ok(id)
+}
+
+// Applies a stake change whose preconditions have already been checked. A failure traps, which
+// rolls the whole message back instead of leaving it half applied
+fn apply_checked_stake_change(user_id: u64, delta: f64, cause: StakeCause, source_id: Option<u64>, reason: String) {
+    if let Err(error) = apply_stake_change(user_id, delta, cause, source_id, reason) {
+        ic_cdk::trap(&error_message(error));
+    }
+}
+
+// Moves unbonding entries whose cooldown has elapsed into the available balance
+fn release_unbonded(account: &mut StakeAccount, now: u64) {
+    let (released, pending): (Vec<_>, Vec<_>) = account.unbonding.drain(..).partition(|entry| entry.release_at <= now);
+    account.available += released.iter().map(|entry| entry.amount).sum::<f64>();
+    account.unbonding = pending;
+}
+
+fn validate_stake_amount(amount: f64) -> Result<(), Error> {
+    if !amount.is_finite() || amount <= 0.0 {
+        return Err(Error::InvalidInput {
+            msg: "Amount must be a positive number".to_string(),
+        });
+    }
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn bond_stake(user_id: u64, amount: f64) -> Result<StakeBalance, Error> {
+    observe("bond_stake", || {
+        ensure_caller_is(user_id)?;
+        validate_stake_amount(amount)?;
+        let mut account = STAKE_ACCOUNTS
+            .with(|accounts| accounts.borrow().get(&user_id))
+            .unwrap_or(StakeAccount { user_id, ..Default::default() });
+        release_unbonded(&mut account, time());
+        if account.available < amount {
+            return Err(Error::InvalidInput {
+                msg: format!("Only {} is available for bonding", account.available),
+            });
+        }
+
+        apply_stake_change(user_id, amount, StakeCause::Bond, None, format!("Bonded {}", amount))?;
+        account.available -= amount;
+        STAKE_ACCOUNTS.with(|accounts| accounts.borrow_mut().insert(user_id, account));
+        get_stake_balance(user_id)
+    })
+}
+
+// Credits tokens received outside of the canister to the available balance of a user
+#[ic_cdk::update]
+fn fund_stake_account(user_id: u64, amount: f64) -> Result<StakeBalance, Error> {
+    observe("fund_stake_account", || {
+        ensure_controller()?;
+        validate_stake_amount(amount)?;
+        read_user_profile(user_id)?;
+        credit_available_balance(user_id, amount);
+        get_stake_balance(user_id)
+    })
+}
+
+#[ic_cdk::update]
+fn unbond_stake(user_id: u64, amount: f64) -> Result<StakeBalance, Error> {
+    observe("unbond_stake", || {
+        ensure_caller_is(user_id)?;
+        validate_stake_amount(amount)?;
+        let config = STAKING_CONFIG.with(|config| config.borrow().get().clone());
+        let now = time();
+
+        let mut account = STAKE_ACCOUNTS
+            .with(|accounts| accounts.borrow().get(&user_id))
+            .unwrap_or(StakeAccount { user_id, ..Default::default() });
+        release_unbonded(&mut account, now);
+        let bonded = USER_PROFILES
+            .with(|profiles| profiles.borrow().get(&user_id).map(|profile| profile.stake_in_dao))
+            .unwrap_or(0.0);
+        let pending_slashes = pending_slash_amount(user_id);
+        if bonded - amount < pending_slashes {
+            return Err(Error::InvalidInput {
+                msg: format!("A stake of {} is held for pending slashes and cannot be unbonded", pending_slashes),
+            });
+        }
+        if account.unbonding.len() as u64 >= config.max_unbonding_entries {
+            return Err(Error::InvalidInput {
+                msg: format!(
+                    "User with id={} already has {} unbonding entries pending",
+                    user_id, config.max_unbonding_entries
+                ),
+            });
+        }
+
+        apply_stake_change(user_id, -amount, StakeCause::Unbond, None, format!("Unbonded {}", amount))?;
+        account.unbonding.push(UnbondingEntry {
+            amount,
+            release_at: now + config.unbonding_period_ns,
+        });
+        STAKE_ACCOUNTS.with(|accounts| accounts.borrow_mut().insert(user_id, account));
+
+        get_stake_balance(user_id)
+    })
+}
+
+#[ic_cdk::update]
+fn withdraw_stake(user_id: u64, amount: f64) -> Result<StakeBalance, Error> {
+    observe("withdraw_stake", || {
+        ensure_caller_is(user_id)?;
+        validate_stake_amount(amount)?;
+        let mut account = STAKE_ACCOUNTS
+            .with(|accounts| accounts.borrow().get(&user_id))
+            .ok_or(Error::NotFound {
+                msg: format!("Stake account for user with id={} not found", user_id),
+            })?;
+
+        release_unbonded(&mut account, time());
+        if account.available < amount {
+            return Err(Error::InvalidInput {
+                msg: format!("Only {} is available for withdrawal", account.available),
+            });
+        }
+        account.available -= amount;
+        STAKE_ACCOUNTS.with(|accounts| accounts.borrow_mut().insert(user_id, account));
+
+        get_stake_balance(user_id)
+    })
+}
+
+#[ic_cdk::query]
+fn get_stake_balance(user_id: u64) -> Result<StakeBalance, Error> {
+    let bonded = USER_PROFILES
+        .with(|profiles| profiles.borrow().get(&user_id).map(|profile| profile.stake_in_dao))
+        .ok_or(Error::NotFound {
+            msg: format!("User profile with id={} not found", user_id),
+        })?;
+
+    let mut account = STAKE_ACCOUNTS
+        .with(|accounts| accounts.borrow().get(&user_id))
+        .unwrap_or(StakeAccount { user_id, ..Default::default() });
+    release_unbonded(&mut account, time());
+
+    Ok(StakeBalance {
+        user_id,
+        bonded,
+        unbonding: account.unbonding.iter().map(|entry| entry.amount).sum(),
+        available: account.available,
+    })
+}
+
+#[ic_cdk::query]
+fn get_stake_history(user_id: u64) -> Vec<StakeAdjustment> {
+    STAKE_ADJUSTMENTS.with(|adjustments| {
+        adjustments
+            .borrow()
+            .iter()
+            .filter(|(_, adjustment)| adjustment.user_id == user_id)
+            .map(|(_, adjustment)| adjustment)
+            .collect()
+    })
+}
+
+#[ic_cdk::query]
+fn get_staking_config() -> StakingConfig {
+    STAKING_CONFIG.with(|config| config.borrow().get().clone())
+}
+
+#[ic_cdk::update]
+fn submit_insurance_claim(
+    farmer_id: u64,
+    contract_id: u64,
+    claim_details: String,
+    affected_crops: Vec<AffectedCrop>,
+    peril: Peril,
+    evidence: String,
+    claimed_amount: f64,
+) -> Result<InsuranceClaim, Error> {
+    observe("submit_insurance_claim", || {
+        ensure_caller_is(farmer_id)?;
+        validate_length("claim_details", &claim_details, MAX_CLAIM_DETAILS_LENGTH)?;
+        validate_length("evidence", &evidence, MAX_CLAIM_EVIDENCE_LENGTH)?;
+        validate_count("affected_crops", affected_crops.len(), MAX_AFFECTED_CROPS)?;
+        if !claimed_amount.is_finite() || claimed_amount <= 0.0 {
+            return Err(Error::InvalidInput {
+                msg: "claimed_amount must be positive".to_string(),
+            });
+        }
+        // Affected crops must be insured by the contract, planted on its plots and eligible for the peril
+        let contract = read_insurance_contract(contract_id)?;
+        let covered = affected_crops.iter().all(|affected| {
+            contract.plot_ids.contains(&affected.plot_id)
+                && contract.crop_ids.contains(&affected.crop_id)
+                && load_crop(affected.crop_id).is_ok_and(|crop| crop.eligible_perils.contains(&peril))
+                && load_plot(affected.plot_id)
+                    .is_ok_and(|plot| plot.plantings.iter().any(|planting| planting.crop_id == affected.crop_id))
+        });
+        if contract.farmer_id != farmer_id || !covered {
+            return Err(Error::InvalidInput {
+                msg: "The affected crops are not covered by the farmer's contract".to_string(),
+            });
+        }
+        if contract.status != ContractStatus::Active {
+            return Err(Error::InvalidInput {
+                msg: "Claims can only be made against active contracts".to_string(),
+            });
+        }
+
+        let id = ID_COUNTER
+            .with(|counter| {
+                let current_value = *counter.borrow().get();
+                counter.borrow_mut().set(current_value + 1)
+            })
+            .expect("cannot increment id counter");
+
+        let mut claim = InsuranceClaim {
+            id,
+            farmer_id,
+            contract_id,
+            claim_details,
+            affected_crops,
+            peril,
+            evidence,
+            status: ClaimStatus::Submitted,
+            claimed_amount,
+            submitted_at: time(),
+            rejected_at: None,
+            appeal_dispute_id: None,
+            paid_out: false,
+            evidence_hashes: Vec::new(),
+            assessor_ids: Vec::new(),
+            assessments_disagree: false,
+            accepted_assessment_id: None,
+            payout_amount: None,
+        };
+        screen_claim(&mut claim);
+        store_claim(&claim);
+
+        Ok(claim)
+    })
+}
+// Sends an assessor to inspect the claimed loss; several assessors may report on the same claim
+#[ic_cdk::update]
+fn assign_assessor(claim_id: u64, assessor_id: u64) -> Result<InsuranceClaim, Error> {
+    observe("assign_assessor", || {
+        ensure_controller()?;
+        let mut claim = load_claim(claim_id)?;
+        if !matches!(claim.status, ClaimStatus::Submitted | ClaimStatus::UnderAssessment) {
+            return Err(Error::InvalidInput {
+                msg: "Assessors can only be assigned to claims awaiting assessment".to_string(),
+            });
+        }
+        let role = USER_PROFILES
+            .with(|profiles| profiles.borrow().get(&assessor_id).map(|profile| profile.role))
+            .ok_or(Error::NotFound {
+                msg: format!("User profile with id={} not found", assessor_id),
+            })?;
+        if !matches!(role, UserRole::Assessor) {
+            return Err(Error::InvalidInput {
+                msg: format!("User with id={} is not an assessor", assessor_id),
+            });
+        }
+        let (farmer_id, consumer_id) = dispute_parties(DisputeSubject::Claim(claim_id))?;
+        if assessor_id == farmer_id || assessor_id == consumer_id {
+            return Err(Error::InvalidInput {
+                msg: "Parties to the contract cannot assess its claims".to_string(),
+            });
+        }
+        if claim.assessor_ids.contains(&assessor_id) {
+            return Err(Error::InvalidInput {
+                msg: "This assessor is already assigned to the claim".to_string(),
+            });
+        }
+        if claim.assessor_ids.len() >= MAX_ASSESSORS_PER_CLAIM {
+            return Err(Error::InvalidInput {
+                msg: format!("A claim can have at most {} assessors", MAX_ASSESSORS_PER_CLAIM),
+            });
+        }
+
+        claim.assessor_ids.push(assessor_id);
+        claim.status = ClaimStatus::UnderAssessment;
+        store_claim(&claim);
+        Ok(claim)
+    })
+}
+
+#[ic_cdk::update]
+fn submit_assessment(claim_id: u64, assessor_id: u64, report: AssessmentReport) -> Result<Assessment, Error> {
+    observe("submit_assessment", || {
+        ensure_caller_is(assessor_id)?;
+        let mut claim = load_claim(claim_id)?;
+        if claim.status != ClaimStatus::UnderAssessment || !claim.assessor_ids.contains(&assessor_id) {
+            return Err(Error::Unauthorized {
+                msg: "Only assessors assigned to a claim under assessment can report on it".to_string(),
+            });
+        }
+        let existing = get_claim_assessments(claim_id);
+        if existing.iter().any(|assessment| assessment.assessor_id == assessor_id) {
+            return Err(Error::InvalidInput {
+                msg: "This assessor has already reported on the claim".to_string(),
+            });
+        }
+        validate_assessment_report(&report, insured_area_hectares(claim.contract_id))?;
+
+        let id = ID_COUNTER.with(|c| {
+            let current_value = *c.borrow().get();
+            c.borrow_mut().set(current_value + 1).unwrap();
+            current_value
+        });
+        let assessment = Assessment {
+            id,
+            claim_id,
+            assessor_id,
+            yield_loss_percent: report.yield_loss_percent,
+            affected_area_hectares: report.affected_area_hectares,
+            photo_hashes: report.photo_hashes,
+            visit_location: report.visit_location,
+            visited_at: report.visited_at,
+            notes: report.notes,
+            submitted_at: time(),
+        };
+
+        if existing.iter().any(|other| assessments_disagree(other, &assessment)) {
+            claim.assessments_disagree = true;
+            store_claim(&claim);
+        }
+        ASSESSMENTS.with(|assessments| assessments.borrow_mut().insert((claim_id, id), assessment.clone()));
+        Ok(assessment)
+    })
+}
+
+fn validate_assessment_report(report: &AssessmentReport, insured_area: Option<f64>) -> Result<(), Error> {
+    if !(0.0..=100.0).contains(&report.yield_loss_percent) {
+        return Err(Error::InvalidInput {
+            msg: "The yield loss must be between 0 and 100 percent".to_string(),
+        });
+    }
+    let area = report.affected_area_hectares;
+    if !area.is_finite() || area <= 0.0 || insured_area.is_some_and(|insured| area > insured) {
+        return Err(Error::InvalidInput {
+            msg: "The affected area must be positive and within the insured plots".to_string(),
+        });
+    }
+    let location = report.visit_location;
+    if !(-90.0..=90.0).contains(&location.lat) || !(-180.0..=180.0).contains(&location.lon) {
+        return Err(Error::InvalidInput {
+            msg: "The visit location must be a valid WGS84 coordinate".to_string(),
+        });
+    }
+    if report.visited_at > time() {
+        return Err(Error::InvalidInput {
+            msg: "The visit cannot be in the future".to_string(),
+        });
+    }
+    if report.notes.len() > MAX_ASSESSMENT_NOTES_LENGTH || report.photo_hashes.len() > MAX_ASSESSMENT_PHOTOS {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "Reports can have at most {} photos and {} characters of notes",
+                MAX_ASSESSMENT_PHOTOS, MAX_ASSESSMENT_NOTES_LENGTH
+            ),
+        });
+    }
+    for hash in &report.photo_hashes {
+        load_readable_blob(hash)?;
+    }
+    Ok(())
+}
+
+fn assessments_disagree(a: &Assessment, b: &Assessment) -> bool {
+    let larger_area = a.affected_area_hectares.max(b.affected_area_hectares);
+    (a.yield_loss_percent - b.yield_loss_percent).abs() > ASSESSMENT_LOSS_TOLERANCE
+        || (a.affected_area_hectares - b.affected_area_hectares).abs() > ASSESSMENT_AREA_TOLERANCE * larger_area
+}
+
+// Total area of the contract's plots, or None when the contract does not list any
+fn insured_area_hectares(contract_id: u64) -> Option<f64> {
+    let plot_ids = INSURANCE_CONTRACTS.with(|contracts| contracts.borrow().get(&contract_id))?.plot_ids;
+    if plot_ids.is_empty() {
+        return None;
+    }
+    Some(plot_ids.iter().filter_map(|plot_id| load_plot(*plot_id).ok()).map(|plot| plot.area_hectares).sum())
+}
+
+#[ic_cdk::query]
+fn get_claim_assessments(claim_id: u64) -> Vec<Assessment> {
+    ASSESSMENTS.with(|assessments| {
+        assessments
+            .borrow()
+            .range((claim_id, 0)..(claim_id + 1, 0))
+            .map(|(_, assessment)| assessment)
+            .collect()
+    })
+}
+
+fn load_claim(claim_id: u64) -> Result<InsuranceClaim, Error> {
+    INSURANCE_CLAIMS
+        .with(|claims| claims.borrow().get(&claim_id))
+        .ok_or(Error::NotFound {
+            msg: format!("Insurance claim with id={} not found", claim_id),
+        })
+}
+
+// Verifies a claim by accepting one of its assessments, which sets the amount paid if the claim is approved:
+// the sum insured scaled by the yield loss and the share of the insured area affected, capped at the claimed amount
+#[ic_cdk::update]
+fn verify_insurance_claim(claim_id: u64, assessment_id: u64) -> Result<InsuranceClaim, Error> {
+    observe("verify_insurance_claim", || {
+        ensure_controller()?;
+        let mut claim = load_claim(claim_id)?;
+        if claim.status != ClaimStatus::UnderAssessment {
+            return Err(Error::InvalidInput {
+                msg: "Only claims under assessment can be verified".to_string(),
+            });
+        }
+        let assessment = ASSESSMENTS
+            .with(|assessments| assessments.borrow().get(&(claim_id, assessment_id)))
+            .ok_or(Error::NotFound {
+                msg: format!("Assessment with id={} not found for this claim", assessment_id),
+            })?;
+        let contract = INSURANCE_CONTRACTS
+            .with(|contracts| contracts.borrow().get(&claim.contract_id))
+            .ok_or(Error::NotFound {
+                msg: format!("Insurance contract with id={} not found", claim.contract_id),
+            })?;
+
+        let area_share = insured_area_hectares(contract.id)
+            .map(|insured| (assessment.affected_area_hectares / insured).min(1.0))
+            .unwrap_or(1.0);
+        let payout = contract.sum_insured * assessment.yield_loss_percent / 100.0 * area_share;
+
+        claim.status = ClaimStatus::Verified;
+        claim.accepted_assessment_id = Some(assessment_id);
+        claim.payout_amount = Some(payout.min(claim.claimed_amount));
+        store_claim(&claim);
+        Ok(claim)
+    })
+}
+#[ic_cdk::update]
+fn approve_or_reject_claim(claim_id: u64, approve: bool, _reason: String) -> Result<(), Error> {
+    observe("approve_or_reject_claim", || {
+        ensure_controller()?;
+        let mut claim = INSURANCE_CLAIMS
+            .with(|claims| claims.borrow().get(&claim_id))
+            .ok_or(Error::NotFound {
+                msg: "Claim not found".to_string(),
+            })?;
+
+        if claim.status != ClaimStatus::Verified {
+            return Err(Error::InvalidInput {
+                msg: "Claim must be verified before approval/rejection".to_string(),
+            });
+        }
+
+        // Update the claim status based on the approval flag
+        let reputation_delta = if approve {
+            claim.status = ClaimStatus::Approved;
+            // A risk pool that cannot cover the claim leaves the payout pending
+            let _ = pay_out_claim(&mut claim);
+            1.0
+        } else {
+            claim.status = ClaimStatus::Rejected;
+            claim.rejected_at = Some(time());
+            -1.0
+        };
+
+        // Optionally, store the reason for approval or rejection
+        // ... (add logic here if needed)
+
+        record_reputation_event(claim.farmer_id, ReputationComponent::ClaimOutcomes, reputation_delta);
+        store_claim(&claim);
+        Ok(())
+    })
+}
+
+// Saves a claim and logs its status whenever it changes
+fn store_claim(claim: &InsuranceClaim) {
+    let previous = INSURANCE_CLAIMS.with(|claims| claims.borrow_mut().insert(claim.id, claim.clone()));
+    certify_record(CERTIFIED_CLAIMS, claim.id, claim);
+    if previous.is_none_or(|previous| previous.status != claim.status) {
+        record_event(EventKind::ClaimStatusChanged {
+            claim_id: claim.id,
+            status: claim.status.clone(),
+        });
+    }
+}
+
+// Transfers the payout amount from the risk pool to the farmer's withdrawable balance
+fn pay_out_claim(claim: &mut InsuranceClaim) -> Result<(), Error> {
+    if claim.status != ClaimStatus::Approved || claim.paid_out {
+        return Err(Error::InvalidInput {
+            msg: "Only approved claims that have not been paid can be paid out".to_string(),
+        });
+    }
+    let amount = claim.payout_amount.unwrap_or_default();
+    debit_risk_pool(amount)?;
+    credit_available_balance(claim.farmer_id, amount);
+    claim.paid_out = true;
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn retry_claim_payout(claim_id: u64) -> Result<InsuranceClaim, Error> {
+    observe("retry_claim_payout", || {
+        ensure_controller()?;
+        let mut claim = INSURANCE_CLAIMS
+            .with(|claims| claims.borrow().get(&claim_id))
+            .ok_or(Error::NotFound {
+                msg: format!("Insurance claim with id={} not found", claim_id),
+            })?;
+        pay_out_claim(&mut claim)?;
+        store_claim(&claim);
+        Ok(claim)
+    })
+}
+
+// Lets the farmer contest a rejection by opening a dispute about the claim
+#[ic_cdk::update]
+fn appeal_claim(claim_id: u64, reason: String) -> Result<Dispute, Error> {
+    observe("appeal_claim", || {
+        let mut claim = INSURANCE_CLAIMS
+            .with(|claims| claims.borrow().get(&claim_id))
+            .ok_or(Error::NotFound {
+                msg: "Claim not found".to_string(),
+            })?;
+        ensure_caller_is(claim.farmer_id)?;
+        if claim.status != ClaimStatus::Rejected {
+            return Err(Error::InvalidInput {
+                msg: "Only rejected claims can be appealed".to_string(),
+            });
+        }
+        if claim.appeal_dispute_id.is_some() {
+            return Err(Error::InvalidInput {
+                msg: "This claim has already been appealed".to_string(),
+            });
+        }
+        if claim.rejected_at.is_none_or(|at| time() >= at + CLAIM_APPEAL_WINDOW_NS) {
+            return Err(Error::InvalidInput {
+                msg: "The appeal window for this claim has closed".to_string(),
+            });
+        }
+
+        let dispute = open_dispute(DisputeSubject::Claim(claim_id), claim.farmer_id, reason)?;
+        claim.status = ClaimStatus::Appealed;
+        claim.appeal_dispute_id = Some(dispute.id);
+        store_claim(&claim);
+        Ok(dispute)
+    })
+}
+
+// Approves and pays an appealed claim when the farmer wins its dispute. Otherwise, including when
+// nobody prevails, the rejection stands
+fn settle_claim_appeal(dispute: &Dispute, winner_id: Option<u64>) {
+    let DisputeSubject::Claim(claim_id) = dispute.subject else {
+        return;
+    };
+    let Some(mut claim) = INSURANCE_CLAIMS.with(|claims| claims.borrow().get(&claim_id)) else {
+        return;
+    };
+    if claim.status != ClaimStatus::Appealed || claim.appeal_dispute_id != Some(dispute.id) {
+        return;
+    }
+
+    if winner_id == Some(claim.farmer_id) {
+        claim.status = ClaimStatus::Approved;
+        claim.rejected_at = None;
+        let _ = pay_out_claim(&mut claim);
+        // Undo the penalty of the rejection and credit the approval
+        record_reputation_event(claim.farmer_id, ReputationComponent::ClaimOutcomes, 2.0);
+    } else {
+        claim.status = ClaimStatus::Rejected;
+    }
+    store_claim(&claim);
+}
+#[ic_cdk::update]
+fn adjust_stake_transaction(
+    user_id: u64,
+    transaction_id: u64,
+    transaction_success: bool,
+    adjustment_amount: f64,
+) -> Result<(), String> {
+    observe("adjust_stake_transaction", || {
+        ensure_controller().map_err(error_message)?;
+        validate_stake_amount(adjustment_amount).map_err(error_message)?;
+        let transaction = TRANSACTION_RECORDS
+            .with(|records| records.borrow().get(&transaction_id))
+            .ok_or("Transaction record not found".to_string())?;
+        if !transaction.involved_parties.contains(&user_id) {
+            return Err("User is not a party to this transaction".to_string());
+        }
+        if adjustment_amount > transaction.amount.abs() {
+            return Err("Adjustments cannot exceed the amount of the transaction".to_string());
+        }
+        let already_adjusted = STAKE_ADJUSTMENTS.with(|adjustments| {
+            adjustments.borrow().iter().any(|(_, adjustment)| {
+                adjustment.user_id == user_id
+                    && adjustment.cause == StakeCause::TransactionOutcome
+                    && adjustment.source_id == Some(transaction_id)
+            })
+        });
+        if already_adjusted {
+            return Err("The stake of this user has already been adjusted for this transaction".to_string());
+        }
+        let stake = USER_PROFILES
+            .with(|profiles| profiles.borrow().get(&user_id).map(|profile| profile.stake_in_dao))
+            .ok_or("User profile not found".to_string())?;
+
+        // Adjust the stake based on the transaction outcome, ensuring it does not go negative
+        let (delta, reason) = if transaction_success {
+            (adjustment_amount, "Successful transaction")
+        } else {
+            (-adjustment_amount.min(stake), "Failed transaction")
+        };
+
+        apply_stake_change(user_id, delta, StakeCause::TransactionOutcome, Some(transaction_id), reason.to_string())
+            .map(|_| ())
+            .map_err(error_message)
+    })
+}
+
+// Rewards farmers once per epoch for a clean claim history recorded by the canister itself
+#[ic_cdk::update]
+fn adjust_stake_claim_history(user_id: u64) -> Result<(), String> {
+    observe("adjust_stake_claim_history", || {
+        let epoch = current_claim_history_epoch();
+        if CLAIM_HISTORY_EPOCHS.with(|epochs| epochs.borrow().get(&user_id)) == Some(epoch) {
+            return Err(format!("Claim history of user has already been rewarded for epoch {}", epoch));
+        }
+        let profile = USER_PROFILES
+            .with(|profiles| profiles.borrow().get(&user_id))
+            .ok_or("User profile not found".to_string())?;
+        if !matches!(profile.role, UserRole::Farmer) {
+            return Err("Only farmers have a claim history".to_string());
+        }
+
+        let history = claim_histories(Some(user_id)).remove(&user_id).unwrap_or_default();
+        apply_claim_history_adjustment(user_id, epoch, &history).map_err(error_message)
+    })
+}
+
+// Batch job run by a timer at every epoch; users already rewarded for the epoch are skipped
+#[ic_cdk::update]
+fn run_claim_history_epoch() -> u64 {
+    observe("run_claim_history_epoch", || {
+        let epoch = current_claim_history_epoch();
+        let pending = USER_PROFILES.with(|profiles| {
+            profiles
+                .borrow()
+                .iter()
+                .filter(|(_, profile)| matches!(profile.role, UserRole::Farmer))
+                .map(|(id, _)| id)
+                .filter(|id| CLAIM_HISTORY_EPOCHS.with(|epochs| epochs.borrow().get(id)) != Some(epoch))
+                .collect::<Vec<_>>()
+        });
+
+        let mut histories = claim_histories(None);
+        pending
+            .into_iter()
+            .filter(|user_id| {
+                let history = histories.remove(user_id).unwrap_or_default();
+                apply_claim_history_adjustment(*user_id, epoch, &history).is_ok()
+            })
+            .count() as u64
+    })
+}
+
+fn current_claim_history_epoch() -> u64 {
+    time() / CLAIM_HISTORY_EPOCH_NS
+}
+
+// Claim record of a farmer as weighed by the claim history reward
+#[derive(Default)]
+struct ClaimHistory {
+    approved_count: u64,
+    paid_amount: f64,
+    rejected_count: u64,
+    fraudulent: bool,
+    claim_free_contracts: u64, // Active contracts without any claim against them
+}
+
+// Gathers the claim history of every farmer, or of a single one, in one pass over the contracts
+// and claims
+fn claim_histories(farmer_id: Option<u64>) -> BTreeMap<u64, ClaimHistory> {
+    let matches = |id: u64| farmer_id.is_none_or(|farmer_id| farmer_id == id);
+    let mut active_contracts: BTreeMap<u64, u64> = INSURANCE_CONTRACTS.with(|contracts| {
+        contracts
+            .borrow()
+            .iter()
+            .filter(|(_, contract)| contract.status == ContractStatus::Active && matches(contract.farmer_id))
+            .map(|(id, contract)| (id, contract.farmer_id))
+            .collect()
+    });
+
+    let mut histories: BTreeMap<u64, ClaimHistory> = BTreeMap::new();
+    INSURANCE_CLAIMS.with(|claims| {
+        for (_, claim) in claims.borrow().iter().filter(|(_, claim)| matches(claim.farmer_id)) {
+            active_contracts.remove(&claim.contract_id);
+            let history = histories.entry(claim.farmer_id).or_default();
+            match claim.status {
+                ClaimStatus::Approved => {
+                    history.approved_count += 1;
+                    history.paid_amount += claim.payout_amount.unwrap_or_default();
+                }
+                ClaimStatus::Rejected => history.rejected_count += 1,
+                ClaimStatus::Fraudulent => history.fraudulent = true,
+                // Claims still being processed do not count yet
+                _ => {}
+            }
+        }
+    });
+    for farmer_id in active_contracts.into_values() {
+        histories.entry(farmer_id).or_default().claim_free_contracts += 1;
+    }
+    histories
+}
+
+fn apply_claim_history_adjustment(user_id: u64, epoch: u64, history: &ClaimHistory) -> Result<(), Error> {
+    // Only farmers who are insured and have kept a contract free of claims are rewarded, so that
+    // profiles without any coverage cannot collect it. Fewer and smaller paid claims earn more and
+    // a single fraudulent claim forfeits the reward
+    let reward = if history.fraudulent || history.claim_free_contracts == 0 {
+        0.0
+    } else {
+        CLAIM_HISTORY_BASE_REWARD
+            / (1.0
+                + history.approved_count as f64
+                + CLAIM_HISTORY_REJECTION_WEIGHT * history.rejected_count as f64
+                + history.paid_amount / CLAIM_HISTORY_AMOUNT_SCALE)
+    };
+
+    if reward > 0.0 {
+        apply_stake_change(
+            user_id,
+            reward,
+            StakeCause::ClaimHistory,
+            None,
+            format!(
+                "Claim history for epoch {}: {} approved, {} rejected",
+                epoch, history.approved_count, history.rejected_count
+            ),
+        )?;
+    }
+    CLAIM_HISTORY_EPOCHS.with(|epochs| epochs.borrow_mut().insert(user_id, epoch));
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn adjust_stake_dao_participation(user_id: u64, participation_level: u64) -> Result<(), String> {
+    observe("adjust_stake_dao_participation", || {
+        ensure_controller().map_err(error_message)?;
+        if participation_level == 0 || participation_level > MAX_PARTICIPATION_LEVEL {
+            return Err(format!("Participation level must be between 1 and {}", MAX_PARTICIPATION_LEVEL));
+        }
+        // Adjust stake based on DAO participation level
+        let reward = 5.0 * participation_level as f64;
+
+        apply_stake_change(
+            user_id,
+            reward,
+            StakeCause::DaoParticipation,
+            None,
+            format!("DAO participation level {}", participation_level),
+        )
+        .map(|_| ())
+        .map_err(error_message)
+    })
+}
+#[ic_cdk::update]
+fn reward_user_for_positive_behavior(user_id: u64, behavior_metric: String) -> Result<(), String> {
+    observe("reward_user_for_positive_behavior", || {
+        ensure_controller().map_err(error_message)?;
+        validate_length("behavior_metric", &behavior_metric, MAX_BEHAVIOR_METRIC_LENGTH).map_err(error_message)?;
+        let reward = match behavior_metric.as_str() {
+            "excellent" => 20.0,
+            "good" => 10.0,
+            _ => 5.0,
+        };
+
+        // Adjust stake based on positive behavior
+        apply_stake_change(
+            user_id,
+            reward,
+            StakeCause::PositiveBehavior,
+            None,
+            format!("Rewarded for {} behavior", behavior_metric),
+        )
+        .map(|_| ())
+        .map_err(error_message)
+    })
+}
+
+fn error_message(error: Error) -> String {
+    match error {
+        Error::NotFound { msg } | Error::InvalidInput { msg } | Error::Unauthorized { msg } => msg,
+    }
+}
+#[ic_cdk::update]
+fn submit_governance_proposal(
+    proposer_id: u64,
+    proposal_details: String,
+    action: Option<ProposalAction>,
+) -> Result<GovernanceProposal, Error> {
+    observe("submit_governance_proposal", || {
+        open_governance_proposal(proposer_id, proposal_details, action)
+    })
+}
+
+// Locks the proposal deposit from the proposer's stake and opens the proposal for voting
+fn open_governance_proposal(
+    proposer_id: u64,
+    proposal_details: String,
+    action: Option<ProposalAction>,
+) -> Result<GovernanceProposal, Error> {
+    let config = GOVERNANCE_CONFIG.with(|config| config.borrow().get().clone());
+
+    ensure_caller_is(proposer_id)?;
+    validate_length("proposal_details", &proposal_details, MAX_PROPOSAL_DETAILS_LENGTH)?;
+    if let Some(action) = &action {
+        validate_proposal_action(action).map_err(|msg| Error::InvalidInput { msg })?;
+    }
+
+    let open_proposals = GOVERNANCE_PROPOSALS.with(|proposals| {
+        proposals
+            .borrow()
+            .iter()
+            .filter(|(_, proposal)| proposal.proposer_id == proposer_id && proposal.status == ProposalStatus::Open)
+            .count() as u64
+    });
+    if open_proposals >= config.max_open_proposals_per_user {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "User with id={} already has {} open proposals (limit is {})",
+                proposer_id, open_proposals, config.max_open_proposals_per_user
+            ),
+        });
+    }
+
+    let proposer_stake = USER_PROFILES.with(|profiles| {
+        profiles.borrow().get(&proposer_id).map(|profile| profile.stake_in_dao)
+    }).ok_or(Error::NotFound {
+        msg: format!("User profile with id={} not found", proposer_id),
+    })?;
+    if proposer_stake < config.proposal_deposit {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "A stake of at least {} is required to submit a proposal",
+                config.proposal_deposit
+            ),
+        });
+    }
+
+    let id = ID_COUNTER.with(|counter| {
+        let current_value = *counter.borrow().get();
+        counter.borrow_mut().set(current_value + 1).unwrap();
+        current_value
+    });
+
+    apply_stake_change(
+        proposer_id,
+        -config.proposal_deposit,
+        StakeCause::ProposalDeposit,
+        Some(id),
+        "Deposit locked for governance proposal".to_string(),
+    )?;
+
+    let now = time();
+    let governance_proposal = GovernanceProposal {
+        id,
+        proposal_details,
+        proposer_id,
+        status: ProposalStatus::Open,
+        deposit: config.proposal_deposit,
+        deposit_status: DepositStatus::Held,
+        created_at: now,
+        voting_deadline: now + config.voting_period_ns,
+        action,
+        executable_at: None,
+        execution_error: None,
+    };
+
+    store_proposal(&governance_proposal);
+    record_event(EventKind::ProposalCreated {
+        proposal_id: id,
+        proposer_id,
+    });
+    schedule_proposal_timer(id, governance_proposal.voting_deadline);
+
+    Ok(governance_proposal)
+}
+
+// Tallies the votes of a proposal whose voting period is over and settles the deposit
+fn finalize_proposal(proposal: &mut GovernanceProposal) {
+    let config = GOVERNANCE_CONFIG.with(|config| config.borrow().get().clone());
+    let total_dao_stake = USER_PROFILES.with(|profiles| {
+        profiles.borrow().iter().map(|(_, profile)| profile.stake_in_dao).sum::<f64>()
+    });
+
+    let voting_records: Vec<VotingRecord> = VOTING_RECORDS.with(|votes| {
+        votes
+            .borrow()
+            .range((proposal.id, 0)..(proposal.id + 1, 0))
+            .map(|(_, record)| record)
+            .collect()
+    });
+    let cast_stake = voting_records.iter().map(|record| record.stake).sum::<f64>();
+    let approval_stake = voting_records.iter()
+        .filter(|record| matches!(record.vote, VoteType::Approve))
+        .map(|record| record.stake).sum::<f64>();
+    let rejection_stake = voting_records.iter()
+        .filter(|record| matches!(record.vote, VoteType::Reject))
+        .map(|record| record.stake).sum::<f64>();
+
+    let quorum_reached = cast_stake > 0.0 && cast_stake >= total_dao_stake * config.quorum_ratio;
+    let passed = quorum_reached && approval_stake > cast_stake / 2.0;
+    let rejected_by_large_margin = cast_stake > 0.0 && rejection_stake / cast_stake >= config.burn_rejection_ratio;
+
+    proposal.status = if passed && proposal.action.is_some() {
+        proposal.executable_at = Some(time() + config.timelock_ns);
+        ProposalStatus::Queued
+    } else if passed {
+        ProposalStatus::Passed
+    } else if quorum_reached {
+        ProposalStatus::Rejected
+    } else {
+        ProposalStatus::Expired
+    };
+
+    // Missing the quorum is not held against the proposer, only a clear rejection is
+    proposal.deposit_status = if quorum_reached && rejected_by_large_margin {
+        DepositStatus::Burned
+    } else {
+        // The proposer may have been deleted in the meantime, in which case there is nobody to refund
+        let _ = apply_stake_change(
+            proposal.proposer_id,
+            proposal.deposit,
+            StakeCause::DepositRefund,
+            Some(proposal.id),
+            "Deposit refunded for governance proposal".to_string(),
+        );
+        DepositStatus::Refunded
+    };
+}
+
+#[ic_cdk::update]
+fn vote_on_proposal(user_id: u64, proposal_id: u64, vote: VoteType) -> Result<(), String> {
+    observe("vote_on_proposal", || {
+        ensure_caller_is(user_id).map_err(error_message)?;
+        // Retrieve the user profile and get the stake
+        let user_stake = USER_PROFILES.with(|profiles| {
+            profiles.borrow()
+                    .get(&user_id)
+                    .map(|profile| profile.stake_in_dao)
+                    .unwrap_or(0.0)
+        });
+
+        // Check if the user has sufficient stake to vote
+        if user_stake <= 0.0 {
+            return Err("User does not have sufficient stake to vote".to_string());
+        }
+
+        let proposal = GOVERNANCE_PROPOSALS.with(|proposals| {
+            proposals.borrow().get(&proposal_id)
+        }).ok_or("Proposal not found".to_string())?;
+
+        if proposal.status != ProposalStatus::Open || time() >= proposal.voting_deadline {
+            return Err("Proposal is not open for voting".to_string());
+        }
+        if VOTING_RECORDS.with(|votes| votes.borrow().contains_key(&(proposal_id, user_id))) {
+            return Err("User has already voted on this proposal".to_string());
+        }
+
+        let record = VotingRecord {
+            proposal_id,
+            user_id,
+            vote: vote.clone(),
+            stake: user_stake,
+        };
+        VOTING_RECORDS.with(|votes| votes.borrow_mut().insert((proposal_id, user_id), record));
+        record_reputation_event(user_id, ReputationComponent::VotingParticipation, 1.0);
+        record_event(EventKind::VoteCast {
+            proposal_id,
+            user_id,
+            vote,
+            stake: user_stake,
+        });
+
+        Ok(())
+    })
+}
+
+#[ic_cdk::query]
+fn get_proposal_votes(proposal_id: u64, offset: u64, limit: u64) -> VotingRecordPage {
+    VOTING_RECORDS.with(|votes| {
+        let votes = votes.borrow();
+        VotingRecordPage {
+            total: votes.range((proposal_id, 0)..(proposal_id + 1, 0)).count() as u64,
+            records: votes
+                .range((proposal_id, 0)..(proposal_id + 1, 0))
+                .skip(offset as usize)
+                .take(limit.min(MAX_PAGE_SIZE) as usize)
+                .map(|(_, record)| record)
+                .collect(),
+        }
+    })
+}
+
+#[ic_cdk::update]
+fn enact_proposal(proposal_id: u64) -> Result<(), String> {
+    observe("enact_proposal", || {
+        let proposal = GOVERNANCE_PROPOSALS.with(|proposals| proposals.borrow().get(&proposal_id))
+            .ok_or("Proposal not found".to_string())?;
+
+        if proposal.status != ProposalStatus::Open {
+            return Err("Proposal has already been finalised".to_string());
+        }
+        if time() < proposal.voting_deadline {
+            return Err("Voting period has not ended yet".to_string());
+        }
+
+        match process_proposal(proposal_id) {
+            Some(ProposalStatus::Passed | ProposalStatus::Queued | ProposalStatus::Executed) => Ok(()),
+            Some(ProposalStatus::Failed) => Err("Proposal passed but its action failed".to_string()),
+            _ => Err("Proposal not approved".to_string()),
+        }
+    })
+}
+
+// Arms a one-shot timer that moves the proposal forward once `at` is reached
+fn schedule_proposal_timer(proposal_id: u64, at: u64) {
+    let delay = Duration::from_nanos(at.saturating_sub(time()));
+    set_timer(delay, move || {
+        run_when_writable(move || {
+            process_proposal(proposal_id);
+        })
+    });
+}
+
+// Advances a proposal through its lifecycle: tallies it once voting has closed and
+// executes its action once the timelock has expired. Returns the resulting status.
+fn process_proposal(proposal_id: u64) -> Option<ProposalStatus> {
+    let mut proposal = GOVERNANCE_PROPOSALS.with(|proposals| proposals.borrow().get(&proposal_id))?;
+    let now = time();
+
+    if proposal.status == ProposalStatus::Open && now >= proposal.voting_deadline {
+        finalize_proposal(&mut proposal);
+    }
+
+    if proposal.status == ProposalStatus::Queued {
+        let executable_at = proposal.executable_at.unwrap_or(now);
+        if now >= executable_at {
+            let result = match &proposal.action {
+                Some(action) => execute_proposal_action(action),
+                None => Ok(()),
+            };
+            match result {
+                Ok(()) => proposal.status = ProposalStatus::Executed,
+                Err(msg) => {
+                    proposal.status = ProposalStatus::Failed;
+                    proposal.execution_error = Some(msg);
+                }
+            }
+            record_event(EventKind::ProposalExecuted {
+                proposal_id,
+                status: proposal.status.clone(),
+            });
+        } else {
+            schedule_proposal_timer(proposal_id, executable_at);
+        }
+    }
+
+    let status = proposal.status.clone();
+    store_proposal(&proposal);
+    Some(status)
+}
+
+fn validate_proposal_action(action: &ProposalAction) -> Result<(), String> {
+    match action {
+        ProposalAction::UpdateGovernanceConfig(config) => {
+            if !config.proposal_deposit.is_finite() || config.proposal_deposit < 0.0 {
+                return Err("Proposal deposit cannot be negative".to_string());
+            }
+            if !(0.0..=1.0).contains(&config.quorum_ratio) || !(0.0..=1.0).contains(&config.burn_rejection_ratio) {
+                return Err("Quorum and burn ratios must be between 0 and 1".to_string());
+            }
+            if config.voting_period_ns == 0 {
+                return Err("Voting period must be greater than zero".to_string());
+            }
+            Ok(())
+        }
+        ProposalAction::UpdateStakingConfig(config) => {
+            if config.max_unbonding_entries == 0 || config.max_unbonding_entries > 16 {
+                return Err("Maximum unbonding entries must be between 1 and 16".to_string());
+            }
+            Ok(())
+        }
+        ProposalAction::UpdateReputationConfig(config) => {
+            let weights = [
+                config.claim_outcomes_weight,
+                config.dispute_outcomes_weight,
+                config.contract_fulfilment_weight,
+                config.voting_participation_weight,
+                config.peer_attestations_weight,
+            ];
+            if weights.iter().any(|weight| !weight.is_finite() || *weight < 0.0) {
+                return Err("Reputation weights must be non-negative numbers".to_string());
+            }
+            if config.half_life_ns == 0 {
+                return Err("Reputation half-life must be greater than zero".to_string());
+            }
+            Ok(())
+        }
+        ProposalAction::AddCrop(crop) => validate_crop_payload(crop, None),
+        ProposalAction::UpdateCrop { crop_id, crop } => {
+            load_crop(*crop_id).map_err(error_message)?;
+            validate_crop_payload(crop, Some(*crop_id))
+        }
+        ProposalAction::RetireCrop(crop_id) => validate_crop_ids(&[*crop_id]).map_err(error_message),
+        ProposalAction::UpdateSlashingSchedule(schedule) => {
+            for rule in [&schedule.fraudulent_claim, &schedule.lost_dispute] {
+                if !(0.0..=1.0).contains(&rule.stake_fraction)
+                    || !rule.minimum_amount.is_finite()
+                    || rule.minimum_amount < 0.0
+                {
+                    return Err("Slashed fractions must be between 0 and 1 and minimums non-negative".to_string());
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+fn execute_proposal_action(action: &ProposalAction) -> Result<(), String> {
+    validate_proposal_action(action)?;
+    match action {
+        ProposalAction::UpdateGovernanceConfig(config) => {
+            GOVERNANCE_CONFIG
+                .with(|cell| cell.borrow_mut().set(config.clone()))
+                .map_err(|_| "Cannot store the governance config".to_string())?;
+        }
+        ProposalAction::UpdateStakingConfig(config) => {
+            STAKING_CONFIG
+                .with(|cell| cell.borrow_mut().set(config.clone()))
+                .map_err(|_| "Cannot store the staking config".to_string())?;
+        }
+        ProposalAction::UpdateReputationConfig(config) => {
+            REPUTATION_CONFIG
+                .with(|cell| cell.borrow_mut().set(config.clone()))
+                .map_err(|_| "Cannot store the reputation config".to_string())?;
+        }
+        ProposalAction::UpdateSlashingSchedule(schedule) => {
+            SLASHING_SCHEDULE
+                .with(|cell| cell.borrow_mut().set(schedule.clone()))
+                .map_err(|_| "Cannot store the slashing schedule".to_string())?;
+        }
+        ProposalAction::AddCrop(payload) => {
+            let id = ID_COUNTER.with(|c| {
+                let current_value = *c.borrow().get();
+                c.borrow_mut().set(current_value + 1).unwrap();
+                current_value
+            });
+            store_crop(id, payload.clone(), false);
+        }
+        ProposalAction::UpdateCrop { crop_id, crop } => {
+            let retired = load_crop(*crop_id).map_err(error_message)?.retired;
+            store_crop(*crop_id, crop.clone(), retired);
+        }
+        ProposalAction::RetireCrop(crop_id) => {
+            let crop = load_crop(*crop_id).map_err(error_message)?;
+            CROPS.with(|crops| crops.borrow_mut().insert(*crop_id, Crop { retired: true, ..crop }));
+        }
+    }
+    Ok(())
+}
+
+fn store_crop(id: u64, payload: CropPayload, retired: bool) {
+    let crop = Crop {
+        id,
+        name: payload.name.trim().to_string(),
+        aliases: payload.aliases.iter().map(|alias| alias.trim().to_string()).collect(),
+        growing_seasons: payload.growing_seasons,
+        yield_per_hectare: payload.yield_per_hectare,
+        eligible_perils: payload.eligible_perils,
+        retired,
+    };
+    CROPS.with(|crops| crops.borrow_mut().insert(id, crop));
+}
+
+// Names and aliases are compared case-insensitively and must be unique across the catalogue
+fn validate_crop_payload(crop: &CropPayload, crop_id: Option<u64>) -> Result<(), String> {
+    let names: Vec<String> = std::iter::once(&crop.name)
+        .chain(crop.aliases.iter())
+        .map(|name| normalize_crop_name(name))
+        .collect();
+    let labels = names.iter().chain(crop.growing_seasons.iter());
+    if labels.clone().any(|label| label.trim().is_empty() || label.len() > MAX_CROP_NAME_LENGTH) {
+        return Err(format!("Crop names, aliases and seasons must be between 1 and {} characters", MAX_CROP_NAME_LENGTH));
+    }
+    if crop.aliases.len() > MAX_CROP_ALIASES || crop.growing_seasons.len() > MAX_CROP_SEASONS {
+        return Err(format!(
+            "A crop can have at most {} aliases and {} growing seasons",
+            MAX_CROP_ALIASES, MAX_CROP_SEASONS
+        ));
+    }
+    if !crop.yield_per_hectare.is_finite() || crop.yield_per_hectare <= 0.0 {
+        return Err("The typical yield must be positive".to_string());
+    }
+    if crop.eligible_perils.is_empty() {
+        return Err("A crop must be eligible for at least one peril".to_string());
+    }
+    if names.iter().enumerate().any(|(index, name)| names[..index].contains(name)) {
+        return Err("A crop cannot repeat its name among its aliases".to_string());
+    }
+    for name in &names {
+        if let Some(existing) = find_crop_by_name(name).filter(|existing| Some(existing.id) != crop_id) {
+            return Err(format!("{} is already used by crop with id={}", name, existing.id));
+        }
+    }
+    Ok(())
+}
+
+fn normalize_crop_name(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+fn find_crop_by_name(name: &str) -> Option<Crop> {
+    let name = normalize_crop_name(name);
+    CROPS.with(|crops| {
+        crops.borrow().iter().map(|(_, crop)| crop).find(|crop| {
+            normalize_crop_name(&crop.name) == name || crop.aliases.iter().any(|alias| normalize_crop_name(alias) == name)
+        })
+    })
+}
+
+fn load_crop(crop_id: u64) -> Result<Crop, Error> {
+    CROPS.with(|crops| crops.borrow().get(&crop_id)).ok_or(Error::NotFound {
+        msg: format!("Crop with id={} not found", crop_id),
+    })
+}
+
+// Only crops still in the catalogue can be insured or planted
+fn validate_crop_ids(crop_ids: &[u64]) -> Result<(), Error> {
+    for crop_id in crop_ids {
+        if load_crop(*crop_id)?.retired {
+            return Err(Error::InvalidInput {
+                msg: format!("Crop with id={} has been retired", crop_id),
+            });
+        }
+    }
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_crop_catalogue() -> Vec<Crop> {
+    CROPS.with(|crops| crops.borrow().iter().map(|(_, crop)| crop).collect())
+}
+
+#[ic_cdk::query]
+fn get_crop(crop_id: u64) -> Result<Crop, Error> {
+    load_crop(crop_id)
+}
+
+// Resolves a crop from its name or any of its aliases, e.g. "corn" for maize
+#[ic_cdk::query]
+fn find_crop(name: String) -> Result<Crop, Error> {
+    find_crop_by_name(&name).ok_or(Error::NotFound {
+        msg: format!("No crop is known as {}", name),
+    })
+}
+
+#[ic_cdk::init]
+fn init() {
+    STORED_STATE_VERSION.with(|version| version.borrow_mut().set(STATE_VERSION).expect("Cannot store the state version"));
+    start_claim_history_job();
+}
+
+fn start_claim_history_job() {
+    set_timer_interval(Duration::from_nanos(CLAIM_HISTORY_EPOCH_NS), || {
+        run_when_writable(|| {
+            run_claim_history_epoch();
+        });
+    });
+}
+
+// Timers do not survive upgrades, so they are re-armed from the deadlines kept in stable memory
+#[ic_cdk::post_upgrade]
+fn post_upgrade() {
+    // Nothing may read the stores before their records have been brought to the current layout
+    migrate_stored_state();
+    start_claim_history_job();
+    restore_heap_state();
+}
+
+// Rebuilds the state derived from stable memory: the certified tree and the timers of pending
+// proposals, slashes and jury rounds
+fn restore_heap_state() {
+    rebuild_certified_records();
+    discard_incomplete_exports();
+
+    let pending = GOVERNANCE_PROPOSALS.with(|proposals| {
+        proposals
+            .borrow()
+            .iter()
+            .filter_map(|(id, proposal)| match proposal.status {
+                ProposalStatus::Open => Some((id, proposal.voting_deadline)),
+                ProposalStatus::Queued => proposal.executable_at.map(|at| (id, at)),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    });
+    for (proposal_id, at) in pending {
+        schedule_proposal_timer(proposal_id, at);
+    }
+
+    let pending_slashes = SLASHES.with(|slashes| {
+        slashes
+            .borrow()
+            .iter()
+            .filter(|(_, slash)| slash.status == SlashStatus::Pending)
+            .map(|(id, slash)| (id, slash.appeal_deadline))
+            .collect::<Vec<_>>()
+    });
+    for (slash_id, at) in pending_slashes {
+        schedule_slash_timer(slash_id, at);
+    }
+
+    let pending_rounds = JURY_ROUNDS.with(|rounds| {
+        rounds
+            .borrow()
+            .iter()
+            .filter_map(|(id, round)| match round.status {
+                JuryRoundStatus::Voting => Some((id, round.reveal_deadline)),
+                JuryRoundStatus::Decided => round.appeal_deadline.map(|at| (id, at)),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    });
+    for (round_id, at) in pending_rounds {
+        schedule_jury_timer(round_id, at);
+    }
+}
+
+// Exponentially decays every component of a reputation record to `now`
+fn decay_reputation(record: &mut ReputationRecord, half_life_ns: u64, now: u64) {
+    let elapsed = now.saturating_sub(record.updated_at) as f64;
+    let factor = 0.5_f64.powf(elapsed / half_life_ns as f64);
+    record.claim_outcomes *= factor;
+    record.dispute_outcomes *= factor;
+    record.contract_fulfilment *= factor;
+    record.voting_participation *= factor;
+    record.peer_attestations *= factor;
+    record.updated_at = now;
+}
+
+fn current_reputation(user_id: u64) -> ReputationRecord {
+    let half_life_ns = REPUTATION_CONFIG.with(|config| config.borrow().get().half_life_ns);
+    let now = time();
+    let mut record = REPUTATION_RECORDS
+        .with(|records| records.borrow().get(&user_id))
+        .unwrap_or(ReputationRecord { user_id, updated_at: now, ..Default::default() });
+    decay_reputation(&mut record, half_life_ns, now);
+    record
+}
+
+fn record_reputation_event(user_id: u64, component: ReputationComponent, delta: f64) {
+    let mut record = current_reputation(user_id);
+    match component {
+        ReputationComponent::ClaimOutcomes => record.claim_outcomes += delta,
+        ReputationComponent::DisputeOutcomes => record.dispute_outcomes += delta,
+        ReputationComponent::ContractFulfilment => record.contract_fulfilment += delta,
+        ReputationComponent::VotingParticipation => record.voting_participation += delta,
+        ReputationComponent::PeerAttestations => record.peer_attestations += delta,
+    }
+    REPUTATION_RECORDS.with(|records| records.borrow_mut().insert(user_id, record));
+}
+
+#[ic_cdk::update]
+fn attest_user(attester_id: u64, subject_id: u64, positive: bool) -> Result<Attestation, Error> {
+    observe("attest_user", || {
+        ensure_caller_is(attester_id)?;
+        if attester_id == subject_id {
+            return Err(Error::InvalidInput {
+                msg: "Users cannot attest to themselves".to_string(),
+            });
+        }
+        for user_id in [attester_id, subject_id] {
+            if !USER_PROFILES.with(|profiles| profiles.borrow().contains_key(&user_id)) {
+                return Err(Error::NotFound {
+                    msg: format!("User profile with id={} not found", user_id),
+                });
+            }
+        }
+        if ATTESTATIONS.with(|attestations| attestations.borrow().contains_key(&(attester_id, subject_id))) {
+            return Err(Error::InvalidInput {
+                msg: format!("User with id={} has already attested to user with id={}", attester_id, subject_id),
+            });
+        }
+
+        let attestation = Attestation {
+            attester_id,
+            subject_id,
+            positive,
+            timestamp: time(),
+        };
+        ATTESTATIONS.with(|attestations| attestations.borrow_mut().insert((attester_id, subject_id), attestation.clone()));
+        record_reputation_event(
+            subject_id,
+            ReputationComponent::PeerAttestations,
+            if positive { 1.0 } else { -1.0 },
+        );
+        Ok(attestation)
+    })
+}
+
+// Rewards the winner of a dispute, settles any claim appeal and opens a slash against the losing
+// party unless the ruling already takes their stake. Farmers who lose the appeal of a rejected
+// claim are not slashed for it
+fn apply_dispute_outcome(dispute: &Dispute, winner_id: u64) {
+    let loser_id = if winner_id == dispute.farmer_id {
+        dispute.consumer_id
+    } else {
+        dispute.farmer_id
+    };
+    record_reputation_event(winner_id, ReputationComponent::DisputeOutcomes, 1.0);
+    record_reputation_event(loser_id, ReputationComponent::DisputeOutcomes, -1.0);
+    settle_claim_appeal(dispute, Some(winner_id));
+
+    let loser_pays = dispute.ruling.as_ref().is_some_and(|ruling| {
+        ruling.outcomes.iter().any(|outcome| outcome.source == FundSource::Stake(loser_id))
+    });
+    let claim_appeal = matches!(dispute.subject, DisputeSubject::Claim(_));
+    if !loser_pays && !claim_appeal {
+        // A deleted profile has no stake left to slash
+        let _ = open_slash(loser_id, Offence::LostDispute, dispute.id, winner_id);
+    }
+}
+
+#[ic_cdk::query]
+fn explain_reputation(user_id: u64) -> Result<ReputationBreakdown, Error> {
+    if !USER_PROFILES.with(|profiles| profiles.borrow().contains_key(&user_id)) {
+        return Err(Error::NotFound {
+            msg: format!("User profile with id={} not found", user_id),
+        });
+    }
+    let config = REPUTATION_CONFIG.with(|config| config.borrow().get().clone());
+    let record = current_reputation(user_id);
+
+    let components: Vec<ReputationComponentScore> = [
+        (ReputationComponent::ClaimOutcomes, record.claim_outcomes, config.claim_outcomes_weight),
+        (ReputationComponent::DisputeOutcomes, record.dispute_outcomes, config.dispute_outcomes_weight),
+        (ReputationComponent::ContractFulfilment, record.contract_fulfilment, config.contract_fulfilment_weight),
+        (ReputationComponent::VotingParticipation, record.voting_participation, config.voting_participation_weight),
+        (ReputationComponent::PeerAttestations, record.peer_attestations, config.peer_attestations_weight),
+    ]
+    .into_iter()
+    .map(|(component, value, weight)| ReputationComponentScore {
+        component,
+        value,
+        weight,
+        contribution: value * weight,
+    })
+    .collect();
+
+    Ok(ReputationBreakdown {
+        user_id,
+        score: components.iter().map(|component| component.contribution).sum(),
+        components,
+        half_life_ns: config.half_life_ns,
+        as_of: record.updated_at,
+    })
+}
+
+#[ic_cdk::query]
+fn get_reputation_config() -> ReputationConfig {
+    REPUTATION_CONFIG.with(|config| config.borrow().get().clone())
+}
+
+// Opens a slash against `user_id` that becomes final once its appeal window has passed
+fn open_slash(user_id: u64, offence: Offence, source_id: u64, harmed_party_id: u64) -> Result<Slash, Error> {
+    let schedule = SLASHING_SCHEDULE.with(|schedule| schedule.borrow().get().clone());
+    let rule = match offence {
+        Offence::FraudulentClaim => schedule.fraudulent_claim,
+        Offence::LostDispute => schedule.lost_dispute,
+    };
+    let stake = USER_PROFILES
+        .with(|profiles| profiles.borrow().get(&user_id).map(|profile| profile.stake_in_dao))
+        .ok_or(Error::NotFound {
+            msg: format!("User profile with id={} not found", user_id),
+        })?;
+    let available_stake = (stake - pending_slash_amount(user_id)).max(0.0);
+
+    let id = ID_COUNTER.with(|counter| {
+        let current_value = *counter.borrow().get();
+        counter.borrow_mut().set(current_value + 1).unwrap();
+        current_value
+    });
+    let now = time();
+    let slash = Slash {
+        id,
+        user_id,
+        offence,
+        source_id,
+        amount: (stake * rule.stake_fraction).max(rule.minimum_amount).min(available_stake),
+        beneficiary: match rule.destination {
+            SlashDestination::RiskPool => SlashBeneficiary::RiskPool,
+            SlashDestination::HarmedParty => SlashBeneficiary::User(harmed_party_id),
+        },
+        status: SlashStatus::Pending,
+        created_at: now,
+        appeal_deadline: now + schedule.appeal_window_ns,
+        appeal_statement: None,
+        stake_adjustment_id: None,
+    };
+
+    SLASHES.with(|slashes| slashes.borrow_mut().insert(id, slash.clone()));
+    schedule_slash_timer(id, slash.appeal_deadline);
+    Ok(slash)
+}
+
+fn pending_slash_amount(user_id: u64) -> f64 {
+    SLASHES.with(|slashes| {
+        slashes
+            .borrow()
+            .iter()
+            .filter(|(_, slash)| {
+                slash.user_id == user_id && matches!(slash.status, SlashStatus::Pending | SlashStatus::Appealed)
+            })
+            .map(|(_, slash)| slash.amount)
+            .sum()
+    })
+}
+
+fn schedule_slash_timer(slash_id: u64, at: u64) {
+    let delay = Duration::from_nanos(at.saturating_sub(time()));
+    set_timer(delay, move || {
+        run_when_writable(move || {
+            let unappealed = SLASHES.with(|slashes| {
+                slashes
+                    .borrow()
+                    .get(&slash_id)
+                    .map(|slash| slash.status == SlashStatus::Pending)
+                    .unwrap_or(false)
+            });
+            if unappealed {
+                let _ = execute_slash(slash_id);
+            }
+        })
+    });
+}
+
+// Takes the slashed amount from the offender's bonded stake and routes it to the beneficiary
+fn execute_slash(slash_id: u64) -> Result<Slash, Error> {
+    let mut slash = SLASHES
+        .with(|slashes| slashes.borrow().get(&slash_id))
+        .ok_or(Error::NotFound {
+            msg: format!("Slash with id={} not found", slash_id),
+        })?;
+
+    let stake = USER_PROFILES
+        .with(|profiles| profiles.borrow().get(&slash.user_id).map(|profile| profile.stake_in_dao))
+        .unwrap_or(0.0);
+    let amount = slash.amount.min(stake);
+    if amount > 0.0 {
+        let adjustment_id = apply_stake_change(
+            slash.user_id,
+            -amount,
+            StakeCause::Slash,
+            Some(slash_id),
+            format!("Slashed {} for {:?}", amount, slash.offence),
+        )?;
+        slash.stake_adjustment_id = Some(adjustment_id);
+
+        match slash.beneficiary {
+            SlashBeneficiary::RiskPool => credit_risk_pool(amount),
+            SlashBeneficiary::User(user_id) => credit_available_balance(user_id, amount),
+        }
+    }
+    slash.amount = amount;
+    slash.status = SlashStatus::Executed;
+    SLASHES.with(|slashes| slashes.borrow_mut().insert(slash_id, slash.clone()));
+    Ok(slash)
+}
+
+fn credit_risk_pool(amount: f64) {
+    TREASURY.with(|treasury| {
+        let mut treasury = treasury.borrow_mut();
+        let mut balances = treasury.get().clone();
+        balances.risk_pool += amount;
+        treasury.set(balances).expect("Cannot store the treasury");
+    });
+}
+
+fn debit_risk_pool(amount: f64) -> Result<(), Error> {
+    TREASURY.with(|treasury| {
+        let mut treasury = treasury.borrow_mut();
+        let mut balances = treasury.get().clone();
+        if balances.risk_pool < amount {
+            return Err(Error::InvalidInput {
+                msg: format!("The risk pool only holds {}", balances.risk_pool),
+            });
+        }
+        balances.risk_pool -= amount;
+        treasury.set(balances).expect("Cannot store the treasury");
+        Ok(())
+    })
+}
+
+fn credit_available_balance(user_id: u64, amount: f64) {
+    STAKE_ACCOUNTS.with(|accounts| {
+        let mut accounts = accounts.borrow_mut();
+        let mut account = accounts
+            .get(&user_id)
+            .unwrap_or(StakeAccount { user_id, ..Default::default() });
+        account.available += amount;
+        accounts.insert(user_id, account);
+    });
+}
+
+#[ic_cdk::update]
+fn flag_fraudulent_claim(claim_id: u64) -> Result<Slash, Error> {
+    observe("flag_fraudulent_claim", || {
+        ensure_controller()?;
+        let mut claim = INSURANCE_CLAIMS
+            .with(|claims| claims.borrow().get(&claim_id))
+            .ok_or(Error::NotFound {
+                msg: format!("Insurance claim with id={} not found", claim_id),
+            })?;
+        if claim.status == ClaimStatus::Fraudulent {
+            return Err(Error::InvalidInput {
+                msg: "Claim has already been flagged as fraudulent".to_string(),
+            });
+        }
+        let consumer_id = INSURANCE_CONTRACTS
+            .with(|contracts| contracts.borrow().get(&claim.contract_id).map(|contract| contract.consumer_id))
+            .unwrap_or_default();
+
+        claim.status = ClaimStatus::Fraudulent;
+        store_claim(&claim);
+        record_reputation_event(claim.farmer_id, ReputationComponent::ClaimOutcomes, -1.0);
+        open_slash(claim.farmer_id, Offence::FraudulentClaim, claim_id, consumer_id)
+    })
+}
+
+#[ic_cdk::update]
+fn appeal_slash(slash_id: u64, statement: String) -> Result<Slash, Error> {
+    observe("appeal_slash", || {
+        validate_length("statement", &statement, MAX_STATEMENT_LENGTH)?;
+        let mut slash = SLASHES
+            .with(|slashes| slashes.borrow().get(&slash_id))
+            .ok_or(Error::NotFound {
+                msg: format!("Slash with id={} not found", slash_id),
+            })?;
+        ensure_caller_is(slash.user_id)?;
+        if slash.status != SlashStatus::Pending || time() >= slash.appeal_deadline {
+            return Err(Error::InvalidInput {
+                msg: "The appeal window for this slash has closed".to_string(),
+            });
+        }
+
+        slash.status = SlashStatus::Appealed;
+        slash.appeal_statement = Some(statement);
+        SLASHES.with(|slashes| slashes.borrow_mut().insert(slash_id, slash.clone()));
+        Ok(slash)
+    })
+}
+
+#[ic_cdk::update]
+fn resolve_slash_appeal(slash_id: u64, uphold: bool) -> Result<Slash, Error> {
+    observe("resolve_slash_appeal", || {
+        ensure_controller()?;
+        let mut slash = SLASHES
+            .with(|slashes| slashes.borrow().get(&slash_id))
+            .ok_or(Error::NotFound {
+                msg: format!("Slash with id={} not found", slash_id),
+            })?;
+        if slash.status != SlashStatus::Appealed {
+            return Err(Error::InvalidInput {
+                msg: "Only appealed slashes can be resolved".to_string(),
+            });
+        }
+
+        if uphold {
+            return execute_slash(slash_id);
+        }
+        slash.status = SlashStatus::Cancelled;
+        SLASHES.with(|slashes| slashes.borrow_mut().insert(slash_id, slash.clone()));
+        Ok(slash)
+    })
+}
+
+#[ic_cdk::query]
+fn get_slash(slash_id: u64) -> Result<Slash, Error> {
+    SLASHES
+        .with(|slashes| slashes.borrow().get(&slash_id))
+        .ok_or(Error::NotFound {
+            msg: format!("Slash with id={} not found", slash_id),
+        })
+}
+
+#[ic_cdk::query]
+fn get_user_slashes(user_id: u64) -> Vec<Slash> {
+    SLASHES.with(|slashes| {
+        slashes
+            .borrow()
+            .iter()
+            .filter(|(_, slash)| slash.user_id == user_id)
+            .map(|(_, slash)| slash)
+            .collect()
+    })
+}
+
+#[ic_cdk::query]
+fn get_slashing_schedule() -> SlashingSchedule {
+    SLASHING_SCHEDULE.with(|schedule| schedule.borrow().get().clone())
+}
+
+#[ic_cdk::update]
+fn fund_risk_pool(amount: f64) -> Result<Treasury, Error> {
+    observe("fund_risk_pool", || {
+        ensure_controller()?;
+        validate_stake_amount(amount)?;
+        credit_risk_pool(amount);
+        Ok(get_treasury())
+    })
+}
+
+#[ic_cdk::query]
+fn get_treasury() -> Treasury {
+    TREASURY.with(|treasury| treasury.borrow().get().clone())
+}
+
+#[ic_cdk::update]
+fn create_dispute(payload: DisputePayload) -> Result<Dispute, String> {
+    observe("create_dispute", || {
+        open_dispute(payload.subject, payload.raised_by, payload.reason).map_err(error_message)
+    })
+}
+
+fn open_dispute(subject: DisputeSubject, raised_by: u64, reason: String) -> Result<Dispute, Error> {
+    ensure_caller_is(raised_by)?;
+    validate_length("reason", &reason, MAX_REASON_LENGTH)?;
+    let (farmer_id, consumer_id) = dispute_parties(subject)?;
+    if raised_by != farmer_id && raised_by != consumer_id {
+        return Err(Error::Unauthorized {
+            msg: "Only a party to the contract can raise a dispute".to_string(),
+        });
+    }
+
+    let id = ID_COUNTER.with(|c| {
+        let current_value = *c.borrow().get();
+        c.borrow_mut().set(current_value + 1).unwrap();
+        current_value
+    });
+
+    let now = time();
+    let dispute = Dispute {
+        id,
+        farmer_id,
+        consumer_id,
+        reason,
+        status: DisputeStatus::Raised,
+        resolution: None,
+        subject,
+        raised_by,
+        created_at: now,
+        evidence_deadline: now + DISPUTE_EVIDENCE_PERIOD_NS,
+        arbitrator_id: None,
+        ruling: None,
+        jury_round_id: None,
+    };
+
+    store_dispute(&dispute);
+    Ok(dispute)
+}
+
+// Resolves the farmer and consumer of the contract a dispute is about
+fn dispute_parties(subject: DisputeSubject) -> Result<(u64, u64), Error> {
+    let contract_id = match subject {
+        DisputeSubject::Contract(contract_id) => contract_id,
+        DisputeSubject::Claim(claim_id) => INSURANCE_CLAIMS
+            .with(|claims| claims.borrow().get(&claim_id))
+            .map(|claim| claim.contract_id)
+            .ok_or(Error::NotFound {
+                msg: "Claim not found".to_string(),
+            })?,
+    };
+    INSURANCE_CONTRACTS
+        .with(|contracts| contracts.borrow().get(&contract_id))
+        .map(|contract| (contract.farmer_id, contract.consumer_id))
+        .ok_or(Error::NotFound {
+            msg: "Contract not found".to_string(),
+        })
+}
+#[ic_cdk::query]
+fn get_dispute(dispute_id: u64) -> Result<Dispute, String> {
+    DISPUTE_STORAGE.with(|s| {
+        match s.borrow().get(&dispute_id) {
+            Some(dispute) => Ok(dispute.clone()),
+            None => Err("Dispute not found".to_string()),
+        }
+    })
+}
+
+// Administrative status changes; resolving a dispute requires a ruling from its arbitrator
+#[ic_cdk::update]
+fn update_dispute(dispute_id: u64, status: DisputeStatus, resolution: Option<String>) -> Result<Dispute, String> {
+    observe("update_dispute", || {
+        ensure_controller().map_err(error_message)?;
+        if let Some(resolution) = &resolution {
+            validate_length("resolution", resolution, MAX_REASON_LENGTH).map_err(error_message)?;
+        }
+        let mut dispute = load_dispute(dispute_id).map_err(error_message)?;
+        if matches!(dispute.status, DisputeStatus::Resolved | DisputeStatus::Archived) {
+            return Err("Resolved disputes cannot be changed".to_string());
+        }
+        if matches!(status, DisputeStatus::Resolved | DisputeStatus::Archived) {
+            return Err("Disputes are resolved through a ruling and archived through archive_dispute".to_string());
+        }
+
+        dispute.status = status;
+        dispute.resolution = resolution;
+        store_dispute(&dispute);
+        Ok(dispute)
+    })
+}
+
+// Disputes are never deleted so that their history stays available
+#[ic_cdk::update]
+fn archive_dispute(dispute_id: u64) -> Result<Dispute, Error> {
+    observe("archive_dispute", || {
+        ensure_controller()?;
+        let mut dispute = load_dispute(dispute_id)?;
+        if matches!(dispute.status, DisputeStatus::Archived) {
+            return Err(Error::InvalidInput {
+                msg: "Dispute is already archived".to_string(),
+            });
+        }
+
+        dispute.status = DisputeStatus::Archived;
+        store_dispute(&dispute);
+        Ok(dispute)
+    })
+}
+
+// Saves a dispute and appends its current status to the dispute history
+fn store_dispute(dispute: &Dispute) {
+    DISPUTE_STORAGE.with(|s| s.borrow_mut().insert(dispute.id, dispute.clone()));
+    DISPUTE_HISTORY_STORAGE.with(|s| {
+        let mut storage = s.borrow_mut();
+        let mut history = storage.get(&dispute.id).unwrap_or(DisputeHistory {
+            dispute_id: dispute.id,
+            status_updates: Vec::new(),
+        });
+        history.status_updates.push(DisputeStatusUpdate {
+            status: dispute.status.clone(),
+            resolution: dispute.resolution.clone(),
+            timestamp: time(),
+            actor: caller(),
+        });
+        storage.insert(dispute.id, history);
+    });
+    record_event(EventKind::DisputeStatusChanged {
+        dispute_id: dispute.id,
+        status: dispute.status.clone(),
+    });
+}
+
+#[ic_cdk::query]
+fn get_dispute_history(dispute_id: u64) -> Result<DisputeHistory, String> {
+    DISPUTE_HISTORY_STORAGE
+        .with(|s| s.borrow().get(&dispute_id))
+        .ok_or("Dispute not found".to_string())
+}
+
+// Status changes, evidence and statements of a dispute in chronological order
+#[ic_cdk::query]
+fn get_dispute_timeline(dispute_id: u64, offset: u64, limit: u64) -> Result<DisputeTimelinePage, Error> {
+    let history = DISPUTE_HISTORY_STORAGE
+        .with(|s| s.borrow().get(&dispute_id))
+        .ok_or(Error::NotFound {
+            msg: "Dispute not found".to_string(),
+        })?;
+
+    let mut entries: Vec<DisputeTimelineEntry> = history
+        .status_updates
+        .into_iter()
+        .map(|update| DisputeTimelineEntry {
+            timestamp: update.timestamp,
+            event: DisputeTimelineEvent::StatusChanged(update),
+        })
+        .collect();
+    entries.extend(get_dispute_evidence(dispute_id).into_iter().map(|evidence| DisputeTimelineEntry {
+        timestamp: evidence.submitted_at,
+        event: DisputeTimelineEvent::EvidenceSubmitted {
+            evidence_id: evidence.id,
+            party_id: evidence.submitted_by,
+        },
+    }));
+    entries.extend(get_dispute_statements(dispute_id).into_iter().map(|statement| DisputeTimelineEntry {
+        timestamp: statement.submitted_at,
+        event: DisputeTimelineEvent::StatementSubmitted {
+            party_id: statement.party_id,
+        },
+    }));
+    entries.sort_by_key(|entry| entry.timestamp);
+
+    Ok(DisputeTimelinePage {
+        total: entries.len() as u64,
+        entries: entries
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit.min(MAX_PAGE_SIZE) as usize)
+            .collect(),
+    })
+}
+
+fn load_dispute(dispute_id: u64) -> Result<Dispute, Error> {
+    DISPUTE_STORAGE
+        .with(|s| s.borrow().get(&dispute_id))
+        .ok_or(Error::NotFound {
+            msg: "Dispute not found".to_string(),
+        })
+}
+
+fn ensure_evidence_open(dispute: &Dispute, party_id: u64) -> Result<(), Error> {
+    ensure_caller_is(party_id)?;
+    if party_id != dispute.farmer_id && party_id != dispute.consumer_id {
+        return Err(Error::Unauthorized {
+            msg: "Only parties to the dispute can submit evidence".to_string(),
+        });
+    }
+    if matches!(dispute.status, DisputeStatus::Resolved | DisputeStatus::Archived) || time() >= dispute.evidence_deadline {
+        return Err(Error::InvalidInput {
+            msg: "The evidence period of this dispute has ended".to_string(),
+        });
+    }
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn submit_dispute_evidence(
+    dispute_id: u64,
+    party_id: u64,
+    description: String,
+    content: String,
+    blob_hash: Option<Vec<u8>>,
+) -> Result<DisputeEvidence, Error> {
+    observe("submit_dispute_evidence", || {
+        validate_length("description", &description, MAX_EVIDENCE_DESCRIPTION_LENGTH)?;
+        validate_length("content", &content, MAX_EVIDENCE_CONTENT_LENGTH)?;
+        let dispute = load_dispute(dispute_id)?;
+        ensure_evidence_open(&dispute, party_id)?;
+        if let Some(hash) = &blob_hash {
+            load_readable_blob(hash)?;
+        }
+
+        let submitted = get_dispute_evidence(dispute_id)
+            .iter()
+            .filter(|evidence| evidence.submitted_by == party_id)
+            .count();
+        if submitted >= MAX_EVIDENCE_ITEMS_PER_PARTY {
+            return Err(Error::InvalidInput {
+                msg: format!("A party can submit at most {} evidence items", MAX_EVIDENCE_ITEMS_PER_PARTY),
+            });
+        }
+
+        let id = ID_COUNTER.with(|c| {
+            let current_value = *c.borrow().get();
+            c.borrow_mut().set(current_value + 1).unwrap();
+            current_value
+        });
+        let evidence = DisputeEvidence {
+            id,
+            dispute_id,
+            submitted_by: party_id,
+            description,
+            content,
+            submitted_at: time(),
+            blob_hash,
+        };
+        DISPUTE_EVIDENCE.with(|s| s.borrow_mut().insert((dispute_id, id), evidence.clone()));
+        Ok(evidence)
+    })
+}
+
+// Each party has a single statement, which can be revised until the evidence deadline
+#[ic_cdk::update]
+fn submit_dispute_statement(dispute_id: u64, party_id: u64, statement: String) -> Result<DisputeStatement, Error> {
+    observe("submit_dispute_statement", || {
+        validate_length("statement", &statement, MAX_STATEMENT_LENGTH)?;
+        let dispute = load_dispute(dispute_id)?;
+        ensure_evidence_open(&dispute, party_id)?;
+
+        let statement = DisputeStatement {
+            dispute_id,
+            party_id,
+            statement,
+            submitted_at: time(),
+        };
+        DISPUTE_STATEMENTS.with(|s| s.borrow_mut().insert((dispute_id, party_id), statement.clone()));
+        Ok(statement)
+    })
+}
+
+#[ic_cdk::query]
+fn get_dispute_evidence(dispute_id: u64) -> Vec<DisputeEvidence> {
+    DISPUTE_EVIDENCE.with(|s| {
+        s.borrow()
+            .range((dispute_id, 0)..(dispute_id + 1, 0))
+            .map(|(_, evidence)| evidence)
+            .collect()
+    })
+}
+
+#[ic_cdk::query]
+fn get_dispute_statements(dispute_id: u64) -> Vec<DisputeStatement> {
+    DISPUTE_STATEMENTS.with(|s| {
+        s.borrow()
+            .range((dispute_id, 0)..(dispute_id + 1, 0))
+            .map(|(_, statement)| statement)
+            .collect()
+    })
+}
+
+#[ic_cdk::update]
+fn add_arbitrator(user_id: u64) -> Result<(), Error> {
+    observe("add_arbitrator", || {
+        ensure_controller()?;
+        if !USER_PROFILES.with(|profiles| profiles.borrow().contains_key(&user_id)) {
+            return Err(Error::NotFound {
+                msg: "User profile not found".to_string(),
+            });
+        }
+        ARBITRATORS.with(|s| s.borrow_mut().insert(user_id, ()));
+        Ok(())
+    })
+}
+
+#[ic_cdk::update]
+fn remove_arbitrator(user_id: u64) -> Result<(), Error> {
+    observe("remove_arbitrator", || {
+        ensure_controller()?;
+        ARBITRATORS
+            .with(|s| s.borrow_mut().remove(&user_id))
+            .ok_or(Error::NotFound {
+                msg: "Arbitrator not found".to_string(),
+            })
+    })
+}
+
+#[ic_cdk::query]
+fn list_arbitrators() -> Vec<u64> {
+    ARBITRATORS.with(|s| s.borrow().iter().map(|(user_id, _)| user_id).collect())
+}
+
+// Picks an arbitrator uniformly at random from the pool, excluding the parties themselves
+#[ic_cdk::update]
+async fn assign_arbitrator(dispute_id: u64) -> Result<Dispute, Error> {
+    let outcome: Result<Dispute, Error> = async {
+        let dispute = load_dispute(dispute_id)?;
+        if !matches!(dispute.status, DisputeStatus::Raised) || dispute.arbitrator_id.is_some() {
+            return Err(Error::InvalidInput {
+                msg: "An arbitrator can only be assigned to a newly raised dispute".to_string(),
+            });
+        }
+
+        let (random_bytes,) = ic_cdk::api::management_canister::main::raw_rand()
+            .await
+            .map_err(|(_, msg)| Error::InvalidInput {
+                msg: format!("Cannot obtain randomness: {}", msg),
+            })?;
+
+        // State may have changed while waiting for the randomness, including an escalation to a jury
+        ensure_writable();
+        let mut dispute = load_dispute(dispute_id)?;
+        if !matches!(dispute.status, DisputeStatus::Raised)
+            || dispute.arbitrator_id.is_some()
+            || dispute.jury_round_id.is_some()
+        {
+            return Err(Error::InvalidInput {
+                msg: "This dispute has already been assigned".to_string(),
+            });
+        }
+        let candidates: Vec<u64> = list_arbitrators()
+            .into_iter()
+            .filter(|id| *id != dispute.farmer_id && *id != dispute.consumer_id)
+            .collect();
+        if candidates.is_empty() {
+            return Err(Error::InvalidInput {
+                msg: "No eligible arbitrators are available".to_string(),
+            });
+        }
+        let seed = u64::from_le_bytes(random_bytes[..8].try_into().unwrap());
+        dispute.arbitrator_id = Some(candidates[(seed % candidates.len() as u64) as usize]);
+        dispute.status = DisputeStatus::UnderReview;
+
+        store_dispute(&dispute);
+        Ok(dispute)
+    }
+    .await;
+    record_call("assign_arbitrator", &outcome);
+    outcome
+}
+
+// Records the arbitrator's ruling and executes the monetary outcomes it orders
+#[ic_cdk::update]
+fn submit_ruling(
+    dispute_id: u64,
+    arbitrator_id: u64,
+    winner_id: u64,
+    summary: String,
+    outcomes: Vec<MonetaryOutcome>,
+) -> Result<Dispute, Error> {
+    observe("submit_ruling", || {
+        ensure_caller_is(arbitrator_id)?;
+        validate_length("summary", &summary, MAX_STATEMENT_LENGTH)?;
+        let mut dispute = load_dispute(dispute_id)?;
+        if dispute.arbitrator_id != Some(arbitrator_id) || !matches!(dispute.status, DisputeStatus::UnderReview) {
+            return Err(Error::Unauthorized {
+                msg: "Only the assigned arbitrator can rule on a dispute under review".to_string(),
+            });
+        }
+        if time() < dispute.evidence_deadline {
+            return Err(Error::InvalidInput {
+                msg: "The evidence period of this dispute has not ended yet".to_string(),
+            });
+        }
+        if winner_id != dispute.farmer_id && winner_id != dispute.consumer_id {
+            return Err(Error::InvalidInput {
+                msg: "The winner must be a party to the dispute".to_string(),
+            });
+        }
+
+        validate_monetary_outcomes(&dispute, &outcomes)?;
+
+        execute_monetary_outcomes(&dispute, &outcomes);
+        dispute.status = DisputeStatus::Resolved;
+        dispute.resolution = Some(summary);
+        dispute.ruling = Some(Ruling {
+            winner_id,
+            outcomes,
+            ruled_at: time(),
+        });
+        store_dispute(&dispute);
+        apply_dispute_outcome(&dispute, winner_id);
+        Ok(dispute)
+    })
+}
+
+// Checks every transfer of a ruling before any of them is executed, so that a ruling is applied
+// entirely or not at all
+fn validate_monetary_outcomes(dispute: &Dispute, outcomes: &[MonetaryOutcome]) -> Result<(), Error> {
+    if outcomes.len() > MAX_RULING_OUTCOMES {
+        return Err(Error::InvalidInput {
+            msg: format!("A ruling can order at most {} transfers", MAX_RULING_OUTCOMES),
+        });
+    }
+    let parties = [dispute.farmer_id, dispute.consumer_id];
+    // Winning a claim appeal pays the claim itself, so the treasury must not pay it a second time
+    let claim_appeal = matches!(dispute.subject, DisputeSubject::Claim(_));
+    let mut from_treasury = 0.0;
+    let mut from_stake: Vec<(u64, f64)> = Vec::new();
+    for outcome in outcomes {
+        if !outcome.amount.is_finite() || outcome.amount <= 0.0 {
+            return Err(Error::InvalidInput {
+                msg: "Transfer amounts must be positive".to_string(),
+            });
+        }
+        if !parties.contains(&outcome.recipient_id) {
+            return Err(Error::InvalidInput {
+                msg: "Transfers can only be made to parties of the dispute".to_string(),
+            });
+        }
+        match outcome.source {
+            FundSource::Treasury if claim_appeal => {
+                return Err(Error::InvalidInput {
+                    msg: "Rulings on a claim cannot order transfers from the risk pool".to_string(),
+                })
+            }
+            FundSource::Treasury => from_treasury += outcome.amount,
+            FundSource::Stake(user_id) if parties.contains(&user_id) => {
+                match from_stake.iter_mut().find(|(id, _)| *id == user_id) {
+                    Some((_, total)) => *total += outcome.amount,
+                    None => from_stake.push((user_id, outcome.amount)),
+                }
+            }
+            FundSource::Stake(_) => {
+                return Err(Error::InvalidInput {
+                    msg: "Stake can only be taken from parties of the dispute".to_string(),
+                })
+            }
+        }
+    }
+
+    if from_treasury > get_treasury().risk_pool {
+        return Err(Error::InvalidInput {
+            msg: "The risk pool cannot cover the transfers of this ruling".to_string(),
+        });
+    }
+    for (user_id, total) in &from_stake {
+        let stake = USER_PROFILES
+            .with(|profiles| profiles.borrow().get(user_id).map(|profile| profile.stake_in_dao))
+            .unwrap_or(0.0);
+        if *total > stake {
+            return Err(Error::InvalidInput {
+                msg: format!("User with id={} does not have enough stake for this ruling", user_id),
+            });
+        }
+    }
+    Ok(())
+}
+
+// Executes transfers checked by validate_monetary_outcomes. A failure here traps, which rolls the
+// whole message back instead of leaving the ruling half applied
+fn execute_monetary_outcomes(dispute: &Dispute, outcomes: &[MonetaryOutcome]) {
+    for outcome in outcomes {
+        let executed = match outcome.source {
+            FundSource::Treasury => debit_risk_pool(outcome.amount),
+            FundSource::Stake(user_id) => apply_stake_change(
+                user_id,
+                -outcome.amount,
+                StakeCause::DisputeRuling,
+                Some(dispute.id),
+                format!("Transfer of {} ordered by dispute ruling", outcome.amount),
+            )
+            .map(|_| ()),
+        };
+        if let Err(error) = executed {
+            ic_cdk::trap(&error_message(error));
+        }
+        credit_available_balance(outcome.recipient_id, outcome.amount);
+    }
+}
+
+// Hands a newly raised dispute to a jury of stakers instead of a single arbitrator
+#[ic_cdk::update]
+async fn escalate_to_jury(dispute_id: u64) -> Result<JuryRound, Error> {
+    let outcome: Result<JuryRound, Error> = async {
+        let dispute = load_dispute(dispute_id)?;
+        if !matches!(dispute.status, DisputeStatus::Raised) || dispute.arbitrator_id.is_some() {
+            return Err(Error::InvalidInput {
+                msg: "Only newly raised disputes can be decided by a jury".to_string(),
+            });
+        }
+
+        let (seed,) = ic_cdk::api::management_canister::main::raw_rand()
+            .await
+            .map_err(|(_, msg)| Error::InvalidInput {
+                msg: format!("Cannot obtain randomness: {}", msg),
+            })?;
+
+        // State may have changed while waiting for the randomness
+        ensure_writable();
+        let mut dispute = load_dispute(dispute_id)?;
+        if !matches!(dispute.status, DisputeStatus::Raised)
+            || dispute.jury_round_id.is_some()
+            || dispute.arbitrator_id.is_some()
+        {
+            return Err(Error::InvalidInput {
+                msg: "This dispute has already been assigned".to_string(),
+            });
+        }
+        let round = open_jury_round(&dispute, 1, INITIAL_JURY_SIZE, &seed)?;
+        dispute.status = DisputeStatus::UnderReview;
+        dispute.jury_round_id = Some(round.id);
+        store_dispute(&dispute);
+        Ok(round)
+    }
+    .await;
+    record_call("escalate_to_jury", &outcome);
+    outcome
+}
+
+// Lets the losing party of a decided round ask for a new round with a larger jury
+#[ic_cdk::update]
+async fn appeal_jury_ruling(dispute_id: u64, party_id: u64) -> Result<JuryRound, Error> {
+    let outcome: Result<JuryRound, Error> = async {
+        ensure_caller_is(party_id)?;
+        let (seed,) = ic_cdk::api::management_canister::main::raw_rand()
+            .await
+            .map_err(|(_, msg)| Error::InvalidInput {
+                msg: format!("Cannot obtain randomness: {}", msg),
+            })?;
+
+        ensure_writable();
+        let mut dispute = load_dispute(dispute_id)?;
+        let mut previous = dispute
+            .jury_round_id
+            .and_then(|round_id| JURY_ROUNDS.with(|rounds| rounds.borrow().get(&round_id)))
+            .ok_or(Error::InvalidInput {
+                msg: "This dispute is not decided by a jury".to_string(),
+            })?;
+        if party_id != dispute.farmer_id && party_id != dispute.consumer_id {
+            return Err(Error::Unauthorized {
+                msg: "Only parties to the dispute can appeal".to_string(),
+            });
+        }
+        if previous.status != JuryRoundStatus::Decided || previous.appeal_deadline.is_none_or(|at| time() >= at) {
+            return Err(Error::InvalidInput {
+                msg: "The jury ruling cannot be appealed anymore".to_string(),
+            });
+        }
+        if previous.winner_id == Some(party_id) {
+            return Err(Error::InvalidInput {
+                msg: "Only the losing party can appeal".to_string(),
+            });
+        }
+        if previous.round >= MAX_JURY_ROUNDS {
+            return Err(Error::InvalidInput {
+                msg: "The maximum number of appeal rounds has been reached".to_string(),
+            });
+        }
+
+        let round = open_jury_round(&dispute, previous.round + 1, previous.jurors.len() * 2 + 1, &seed)?;
+        previous.status = JuryRoundStatus::Appealed;
+        JURY_ROUNDS.with(|rounds| rounds.borrow_mut().insert(previous.id, previous));
+        dispute.jury_round_id = Some(round.id);
+        store_dispute(&dispute);
+        Ok(round)
+    }
+    .await;
+    record_call("appeal_jury_ruling", &outcome);
+    outcome
+}
+
+fn open_jury_round(dispute: &Dispute, round: u32, size: usize, seed: &[u8]) -> Result<JuryRound, Error> {
+    let jurors = draw_jurors(seed, &[dispute.farmer_id, dispute.consumer_id], size);
+    if jurors.len() < size {
+        return Err(Error::InvalidInput {
+            msg: format!("At least {} eligible jurors are required", size),
+        });
+    }
+
+    let id = ID_COUNTER.with(|c| {
+        let current_value = *c.borrow().get();
+        c.borrow_mut().set(current_value + 1).unwrap();
+        current_value
+    });
+    let now = time();
+    let jury_round = JuryRound {
+        id,
+        dispute_id: dispute.id,
+        round,
+        jurors,
+        commit_deadline: now + JURY_COMMIT_PERIOD_NS,
+        reveal_deadline: now + JURY_COMMIT_PERIOD_NS + JURY_REVEAL_PERIOD_NS,
+        status: JuryRoundStatus::Voting,
+        winner_id: None,
+        appeal_deadline: None,
+    };
+    JURY_ROUNDS.with(|rounds| rounds.borrow_mut().insert(id, jury_round.clone()));
+    schedule_jury_timer(id, jury_round.reveal_deadline);
+    Ok(jury_round)
+}
+
+// Draws jurors without replacement, each with a probability proportional to their bonded stake
+fn draw_jurors(seed: &[u8], excluded: &[u64], count: usize) -> Vec<u64> {
+    let mut candidates: Vec<(u64, f64)> = USER_PROFILES.with(|profiles| {
+        profiles
+            .borrow()
+            .iter()
+            .filter(|(id, profile)| !excluded.contains(id) && profile.stake_in_dao >= JUROR_MIN_STAKE)
+            .map(|(id, profile)| (id, profile.stake_in_dao))
+            .collect()
+    });
+
+    let mut jurors = Vec::new();
+    for draw in 0..count as u64 {
+        if candidates.is_empty() {
+            break;
+        }
+        let total: f64 = candidates.iter().map(|(_, stake)| stake).sum();
+        let mut target = random_unit(seed, draw) * total;
+        let index = candidates
+            .iter()
+            .position(|(_, stake)| {
+                target -= stake;
+                target < 0.0
+            })
+            .unwrap_or(candidates.len() - 1);
+        jurors.push(candidates.remove(index).0);
+    }
+    jurors
+}
+
+// Deterministic number in [0, 1) derived from the seed and a counter
+fn random_unit(seed: &[u8], counter: u64) -> f64 {
+    let digest = Sha256::new().chain_update(seed).chain_update(counter.to_le_bytes()).finalize();
+    u64::from_le_bytes(digest[..8].try_into().unwrap()) as f64 / (u64::MAX as f64 + 1.0)
+}
+
+fn load_jury_round(round_id: u64) -> Result<JuryRound, Error> {
+    JURY_ROUNDS
+        .with(|rounds| rounds.borrow().get(&round_id))
+        .ok_or(Error::NotFound {
+            msg: "Jury round not found".to_string(),
+        })
+}
+
+#[ic_cdk::update]
+fn commit_jury_vote(round_id: u64, juror_id: u64, commitment: Vec<u8>) -> Result<(), Error> {
+    observe("commit_jury_vote", || {
+        ensure_caller_is(juror_id)?;
+        let round = load_jury_round(round_id)?;
+        if !round.jurors.contains(&juror_id) {
+            return Err(Error::Unauthorized {
+                msg: "User is not a juror of this round".to_string(),
+            });
+        }
+        if round.status != JuryRoundStatus::Voting || time() >= round.commit_deadline {
+            return Err(Error::InvalidInput {
+                msg: "The commit phase of this round has ended".to_string(),
+            });
+        }
+        if commitment.len() != 32 {
+            return Err(Error::InvalidInput {
+                msg: "The commitment must be a SHA-256 hash".to_string(),
+            });
+        }
+
+        let vote = JuryVote {
+            round_id,
+            juror_id,
+            commitment,
+            revealed_winner: None,
+        };
+        JURY_VOTES.with(|votes| votes.borrow_mut().insert((round_id, juror_id), vote));
+        Ok(())
+    })
+}
+
+#[ic_cdk::update]
+fn reveal_jury_vote(round_id: u64, juror_id: u64, winner_id: u64, salt: Vec<u8>) -> Result<(), Error> {
+    observe("reveal_jury_vote", || {
+        ensure_caller_is(juror_id)?;
+        let round = load_jury_round(round_id)?;
+        let now = time();
+        if round.status != JuryRoundStatus::Voting || now < round.commit_deadline || now >= round.reveal_deadline {
+            return Err(Error::InvalidInput {
+                msg: "This round is not in its reveal phase".to_string(),
+            });
+        }
+        let mut vote = JURY_VOTES
+            .with(|votes| votes.borrow().get(&(round_id, juror_id)))
+            .ok_or(Error::NotFound {
+                msg: "No vote was committed by this juror".to_string(),
+            })?;
+
+        let digest = Sha256::new().chain_update(winner_id.to_le_bytes()).chain_update(&salt).finalize();
+        if digest.as_slice() != vote.commitment.as_slice() {
+            return Err(Error::InvalidInput {
+                msg: "The revealed vote does not match the commitment".to_string(),
+            });
+        }
+        let dispute = load_dispute(round.dispute_id)?;
+        if winner_id != dispute.farmer_id && winner_id != dispute.consumer_id {
+            return Err(Error::InvalidInput {
+                msg: "Jurors can only vote for a party to the dispute".to_string(),
+            });
+        }
+
+        vote.revealed_winner = Some(winner_id);
+        JURY_VOTES.with(|votes| votes.borrow_mut().insert((round_id, juror_id), vote));
+        Ok(())
+    })
+}
+
+#[ic_cdk::query]
+fn get_jury_round(round_id: u64) -> Result<JuryRound, Error> {
+    load_jury_round(round_id)
+}
+
+fn schedule_jury_timer(round_id: u64, at: u64) {
+    let delay = Duration::from_nanos(at.saturating_sub(time()));
+    set_timer(delay, move || {
+        run_when_writable(move || {
+            let _ = advance_jury_round(round_id);
+        })
+    });
+}
+
+// Tallies a round once its reveal phase is over, and makes its ruling final once the appeal window closes
+#[ic_cdk::update]
+fn advance_jury_round(round_id: u64) -> Result<JuryRound, Error> {
+    observe("advance_jury_round", || {
+        let mut round = load_jury_round(round_id)?;
+        let mut dispute = load_dispute(round.dispute_id)?;
+        let now = time();
+
+        // Everything that can fail has been checked above, so the round is never left half advanced
+        match round.status {
+            JuryRoundStatus::Voting if now >= round.reveal_deadline => {
+                tally_jury_round(&mut round, &dispute);
+                let appeal_deadline = now + JURY_APPEAL_PERIOD_NS;
+                round.status = JuryRoundStatus::Decided;
+                round.appeal_deadline = Some(appeal_deadline);
+                schedule_jury_timer(round_id, appeal_deadline);
+            }
+            JuryRoundStatus::Decided if round.appeal_deadline.is_some_and(|at| now >= at) => {
+                round.status = JuryRoundStatus::Final;
+                dispute.status = DisputeStatus::Resolved;
+                match round.winner_id {
+                    Some(winner_id) => {
+                        dispute.resolution = Some(format!("Decided by the jury in round {}", round.round));
+                        dispute.ruling = Some(Ruling {
+                            winner_id,
+                            outcomes: Vec::new(),
+                            ruled_at: now,
+                        });
+                        store_dispute(&dispute);
+                        apply_dispute_outcome(&dispute, winner_id);
+                    }
+                    // Nobody prevails when the final jury is split, and no slash or transfer is ordered
+                    None => {
+                        dispute.resolution = Some(format!("The jury was split in round {}", round.round));
+                        store_dispute(&dispute);
+                        settle_claim_appeal(&dispute, None);
+                    }
+                }
+            }
+            _ => {
+                return Err(Error::InvalidInput {
+                    msg: "This jury round cannot advance yet".to_string(),
+                })
+            }
+        }
+
+        JURY_ROUNDS.with(|rounds| rounds.borrow_mut().insert(round_id, round.clone()));
+        Ok(round)
+    })
+}
+
+// Decides the round by simple majority of revealed votes; jurors who voted against the
+// majority or did not reveal lose part of their stake to the jurors who voted with it
+fn tally_jury_round(round: &mut JuryRound, dispute: &Dispute) {
+    let votes: Vec<(u64, Option<u64>)> = round
+        .jurors
+        .iter()
+        .map(|juror_id| {
+            let revealed = JURY_VOTES
+                .with(|votes| votes.borrow().get(&(round.id, *juror_id)))
+                .and_then(|vote| vote.revealed_winner);
+            (*juror_id, revealed)
+        })
+        .collect();
+
+    let farmer_votes = votes.iter().filter(|(_, vote)| *vote == Some(dispute.farmer_id)).count();
+    let consumer_votes = votes.iter().filter(|(_, vote)| *vote == Some(dispute.consumer_id)).count();
+    round.winner_id = match farmer_votes.cmp(&consumer_votes) {
+        std::cmp::Ordering::Greater => Some(dispute.farmer_id),
+        std::cmp::Ordering::Less => Some(dispute.consumer_id),
+        std::cmp::Ordering::Equal => None,
+    };
+
+    let mut penalties = 0.0;
+    let mut coherent = Vec::new();
+    for (juror_id, vote) in votes {
+        if vote.is_some() && vote == round.winner_id {
+            // Jurors whose profile has been deleted since cannot be rewarded anymore
+            if USER_PROFILES.with(|profiles| profiles.borrow().contains_key(&juror_id)) {
+                coherent.push(juror_id);
+            }
+            continue;
+        }
+        // A split jury only penalises jurors who failed to reveal
+        if round.winner_id.is_none() && vote.is_some() {
+            continue;
+        }
+        let stake = USER_PROFILES
+            .with(|profiles| profiles.borrow().get(&juror_id).map(|profile| profile.stake_in_dao))
+            .unwrap_or(0.0);
+        let penalty = stake * JUROR_PENALTY_RATIO;
+        if penalty > 0.0 {
+            apply_checked_stake_change(
+                juror_id,
+                -penalty,
+                StakeCause::JurorPenalty,
+                Some(round.id),
+                format!("Incoherent vote in jury round {}", round.round),
+            );
+            penalties += penalty;
+        }
+    }
+
+    if coherent.is_empty() {
+        credit_risk_pool(penalties);
+        return;
+    }
+    let reward = penalties / coherent.len() as f64;
+    if reward > 0.0 {
+        for juror_id in coherent {
+            apply_checked_stake_change(
+                juror_id,
+                reward,
+                StakeCause::JurorReward,
+                Some(round.id),
+                format!("Coherent vote in jury round {}", round.round),
+            );
+        }
+    }
+}
+
+// Starts a chunked upload to the evidence store and reserves its size against the owner's quota
+#[ic_cdk::update]
+fn begin_blob_upload(owner_id: u64, mime_type: String, total_size: u64) -> Result<UploadSession, Error> {
+    observe("begin_blob_upload", || {
+        ensure_caller_is(owner_id)?;
+        if mime_type.is_empty() || mime_type.len() > MAX_MIME_TYPE_LENGTH || !mime_type.contains('/') {
+            return Err(Error::InvalidInput {
+                msg: "A valid MIME type is required".to_string(),
+            });
+        }
+        if total_size == 0 || total_size > MAX_BLOB_SIZE {
+            return Err(Error::InvalidInput {
+                msg: format!("Blobs must be between 1 and {} bytes", MAX_BLOB_SIZE),
+            });
+        }
+        let used = BLOB_USAGE.with(|usage| usage.borrow().get(&owner_id)).unwrap_or(0);
+        if used + total_size > BLOB_QUOTA_BYTES {
+            return Err(Error::InvalidInput {
+                msg: format!("Storage quota of {} bytes exceeded", BLOB_QUOTA_BYTES),
+            });
+        }
+
+        let id = ID_COUNTER.with(|c| {
+            let current_value = *c.borrow().get();
+            c.borrow_mut().set(current_value + 1).unwrap();
+            current_value
+        });
+        let session = UploadSession {
+            id,
+            owner_id,
+            mime_type,
+            total_size,
+            created_at: time(),
+        };
+        BLOB_USAGE.with(|usage| usage.borrow_mut().insert(owner_id, used + total_size));
+        UPLOAD_SESSIONS.with(|sessions| sessions.borrow_mut().insert(id, session.clone()));
+        Ok(session)
+    })
+}
+
+fn load_upload_session(session_id: u64) -> Result<UploadSession, Error> {
+    let session = UPLOAD_SESSIONS
+        .with(|sessions| sessions.borrow().get(&session_id))
+        .ok_or(Error::NotFound {
+            msg: format!("Upload session with id={} not found", session_id),
+        })?;
+    ensure_caller_is(session.owner_id)?;
+    Ok(session)
+}
+
+// Chunks may arrive in any order and be re-sent; every chunk but the last must be exactly BLOB_CHUNK_SIZE bytes
+#[ic_cdk::update]
+fn upload_blob_chunk(session_id: u64, index: u32, data: ByteBuf) -> Result<(), Error> {
+    observe("upload_blob_chunk", || {
+        let session = load_upload_session(session_id)?;
+        let chunk_count = blob_chunk_count(session.total_size);
+        if index >= chunk_count {
+            return Err(Error::InvalidInput {
+                msg: format!("The upload only has {} chunks", chunk_count),
+            });
+        }
+        let expected = if index + 1 == chunk_count {
+            session.total_size as usize - (chunk_count as usize - 1) * BLOB_CHUNK_SIZE
+        } else {
+            BLOB_CHUNK_SIZE
+        };
+        if data.len() != expected {
+            return Err(Error::InvalidInput {
+                msg: format!("Chunk {} must be {} bytes", index, expected),
+            });
+        }
+
+        UPLOAD_CHUNKS.with(|chunks| chunks.borrow_mut().insert((session_id, index), BlobChunk(data.into_vec())));
+        Ok(())
+    })
+}
+
+// Hashes the uploaded chunks and moves them to the blob store; identical content is stored once
+#[ic_cdk::update]
+fn commit_blob_upload(session_id: u64) -> Result<BlobMeta, Error> {
+    observe("commit_blob_upload", || {
+        let session = load_upload_session(session_id)?;
+        let chunk_count = blob_chunk_count(session.total_size);
+        let received = UPLOAD_CHUNKS.with(|chunks| chunks.borrow().range((session_id, 0)..(session_id + 1, 0)).count());
+        if received != chunk_count as usize {
+            return Err(Error::InvalidInput {
+                msg: format!("Only {} of {} chunks have been uploaded", received, chunk_count),
+            });
+        }
+
+        let mut hasher = Sha256::new();
+        UPLOAD_CHUNKS.with(|chunks| {
+            for (_, chunk) in chunks.borrow().range((session_id, 0)..(session_id + 1, 0)) {
+                hasher.update(&chunk.0);
+            }
+        });
+        let hash: [u8; 32] = hasher.finalize().into();
+
+        let existing = BLOB_METAS.with(|metas| metas.borrow().get(&hash));
+        let meta = match existing {
+            Some(meta) => {
+                release_blob_quota(session.owner_id, session.total_size);
+                meta
+            }
+            None => {
+                for index in 0..chunk_count {
+                    let chunk = UPLOAD_CHUNKS.with(|chunks| chunks.borrow().get(&(session_id, index))).unwrap();
+                    BLOB_CHUNKS.with(|chunks| chunks.borrow_mut().insert((hash, index), chunk));
+                }
+                let meta = BlobMeta {
+                    hash: hash.to_vec(),
+                    owner_id: session.owner_id,
+                    mime_type: session.mime_type.clone(),
+                    size: session.total_size,
+                    chunk_count,
+                    created_at: time(),
+                };
+                BLOB_METAS.with(|metas| metas.borrow_mut().insert(hash, meta.clone()));
+                meta
+            }
+        };
+
+        BLOB_HOLDERS.with(|holders| holders.borrow_mut().insert((hash, session.owner_id), ()));
+        discard_upload(session_id, chunk_count);
+        Ok(meta)
+    })
+}
+
+#[ic_cdk::update]
+fn abort_blob_upload(session_id: u64) -> Result<(), Error> {
+    observe("abort_blob_upload", || {
+        let session = load_upload_session(session_id)?;
+        discard_upload(session_id, blob_chunk_count(session.total_size));
+        release_blob_quota(session.owner_id, session.total_size);
+        Ok(())
+    })
+}
+
+fn discard_upload(session_id: u64, chunk_count: u32) {
+    UPLOAD_CHUNKS.with(|chunks| {
+        let mut chunks = chunks.borrow_mut();
+        for index in 0..chunk_count {
+            chunks.remove(&(session_id, index));
+        }
+    });
+    UPLOAD_SESSIONS.with(|sessions| sessions.borrow_mut().remove(&session_id));
+}
+
+fn release_blob_quota(user_id: u64, bytes: u64) {
+    BLOB_USAGE.with(|usage| {
+        let mut usage = usage.borrow_mut();
+        let used = usage.get(&user_id).unwrap_or(0);
+        usage.insert(user_id, used.saturating_sub(bytes));
+    });
+}
+
+fn blob_chunk_count(size: u64) -> u32 {
+    size.div_ceil(BLOB_CHUNK_SIZE as u64) as u32
+}
+
+fn blob_key(hash: &[u8]) -> Result<[u8; 32], Error> {
+    hash.try_into().map_err(|_| Error::InvalidInput {
+        msg: "Blob hashes are 32 byte SHA-256 digests".to_string(),
+    })
+}
+
+fn load_blob_meta(hash: &[u8]) -> Result<BlobMeta, Error> {
+    let key = blob_key(hash)?;
+    BLOB_METAS
+        .with(|metas| metas.borrow().get(&key))
+        .ok_or(Error::NotFound {
+            msg: format!("Blob {} not found", hex::encode(hash)),
+        })
+}
+
+// Loads a blob the caller may read, e.g. before citing it in a claim, report or dispute
+fn load_readable_blob(hash: &[u8]) -> Result<BlobMeta, Error> {
+    let meta = load_blob_meta(hash)?;
+    ensure_blob_reader(&meta.hash)?;
+    Ok(meta)
+}
+
+// Evidence includes identity documents and land titles, so a blob can only be read by
+// controllers, the users who uploaded it and the people involved in whatever cites it
+fn ensure_blob_reader(hash: &[u8]) -> Result<(), Error> {
+    if ensure_controller().is_ok() {
+        return Ok(());
+    }
+    let is_reader = blob_readers(hash).iter().any(|user_id| {
+        USER_PROFILES.with(|profiles| profiles.borrow().get(user_id)).is_some_and(|profile| is_profile_caller(&profile))
+    });
+    if is_reader {
+        Ok(())
+    } else {
+        Err(Error::Unauthorized {
+            msg: "Only the parties, assessors, arbitrators and jurors of the claims and disputes citing this blob can read it"
+                .to_string(),
+        })
+    }
+}
+
+fn blob_readers(hash: &[u8]) -> BTreeSet<u64> {
+    let mut readers = BTreeSet::new();
+    if let Ok(key) = blob_key(hash) {
+        readers.extend(BLOB_METAS.with(|metas| metas.borrow().get(&key)).map(|meta| meta.owner_id));
+        BLOB_HOLDERS.with(|holders| {
+            let holders = holders.borrow();
+            readers.extend(holders.range((key, 0)..=(key, u64::MAX)).map(|((_, user_id), _)| user_id))
+        });
+    }
+
+    let mut claim_ids: BTreeSet<u64> = INSURANCE_CLAIMS.with(|claims| {
+        claims
+            .borrow()
+            .iter()
+            .filter(|(_, claim)| claim.evidence_hashes.iter().any(|cited| cited == hash))
+            .map(|(id, _)| id)
+            .collect()
+    });
+    ASSESSMENTS.with(|assessments| {
+        claim_ids.extend(
+            assessments
+                .borrow()
+                .iter()
+                .filter(|(_, assessment)| assessment.photo_hashes.iter().any(|cited| cited == hash))
+                .map(|((claim_id, _), _)| claim_id),
+        )
+    });
+    for claim_id in claim_ids {
+        let Some(claim) = INSURANCE_CLAIMS.with(|claims| claims.borrow().get(&claim_id)) else {
+            continue;
+        };
+        readers.insert(claim.farmer_id);
+        readers.extend(
+            INSURANCE_CONTRACTS
+                .with(|contracts| contracts.borrow().get(&claim.contract_id))
+                .map(|contract| contract.consumer_id),
+        );
+        readers.extend(claim.assessor_ids.iter().copied());
+        if let Some(dispute_id) = claim.appeal_dispute_id {
+            readers.extend(dispute_readers(dispute_id));
+        }
+    }
+
+    let dispute_ids: BTreeSet<u64> = DISPUTE_EVIDENCE.with(|evidence| {
+        evidence
+            .borrow()
+            .iter()
+            .filter(|(_, evidence)| evidence.blob_hash.as_deref() == Some(hash))
+            .map(|((dispute_id, _), _)| dispute_id)
+            .collect()
+    });
+    for dispute_id in dispute_ids {
+        readers.extend(dispute_readers(dispute_id));
+    }
+    readers
+}
+
+fn dispute_readers(dispute_id: u64) -> Vec<u64> {
+    let Some(dispute) = DISPUTE_STORAGE.with(|disputes| disputes.borrow().get(&dispute_id)) else {
+        return Vec::new();
+    };
+    let mut readers = vec![dispute.farmer_id, dispute.consumer_id];
+    readers.extend(dispute.arbitrator_id);
+    JURY_ROUNDS.with(|rounds| {
+        for (_, round) in rounds.borrow().iter().filter(|(_, round)| round.dispute_id == dispute_id) {
+            readers.extend(round.jurors);
+        }
+    });
+    readers
+}
+
+// Hashes cited by claims, assessment reports or dispute evidence
+fn cited_blobs() -> BTreeSet<Vec<u8>> {
+    let mut cited = BTreeSet::new();
+    INSURANCE_CLAIMS.with(|claims| {
+        for (_, claim) in claims.borrow().iter() {
+            cited.extend(claim.evidence_hashes);
+        }
+    });
+    ASSESSMENTS.with(|assessments| {
+        for (_, assessment) in assessments.borrow().iter() {
+            cited.extend(assessment.photo_hashes);
+        }
+    });
+    DISPUTE_EVIDENCE.with(|evidence| {
+        for (_, evidence) in evidence.borrow().iter() {
+            cited.extend(evidence.blob_hash);
+        }
+    });
+    cited
+}
+
+fn remove_blob(meta: &BlobMeta) {
+    let key: [u8; 32] = meta.hash.as_slice().try_into().expect("Stored blob hashes are 32 bytes");
+    BLOB_CHUNKS.with(|chunks| {
+        let mut chunks = chunks.borrow_mut();
+        for index in 0..meta.chunk_count {
+            chunks.remove(&(key, index));
+        }
+    });
+    BLOB_HOLDERS.with(|holders| {
+        let mut holders = holders.borrow_mut();
+        let user_ids: Vec<u64> = holders.range((key, 0)..=(key, u64::MAX)).map(|((_, user_id), _)| user_id).collect();
+        for user_id in user_ids {
+            holders.remove(&(key, user_id));
+        }
+    });
+    BLOB_METAS.with(|metas| metas.borrow_mut().remove(&key));
+    release_blob_quota(meta.owner_id, meta.size);
+}
+
+#[ic_cdk::query]
+fn get_blob_meta(hash: Vec<u8>) -> Result<BlobMeta, Error> {
+    load_readable_blob(&hash)
+}
+
+#[ic_cdk::query]
+fn get_blob_chunk(hash: Vec<u8>, index: u32) -> Result<ByteBuf, Error> {
+    let key = blob_key(&hash)?;
+    ensure_blob_reader(&hash)?;
+    BLOB_CHUNKS
+        .with(|chunks| chunks.borrow().get(&(key, index)))
+        .map(|chunk| ByteBuf::from(chunk.0))
+        .ok_or(Error::NotFound {
+            msg: format!("Chunk {} of blob {} not found", index, hex::encode(&hash)),
+        })
+}
+
+// Blobs can only be deleted while nothing cites them; the owner gets the space back
+#[ic_cdk::update]
+fn delete_blob(hash: Vec<u8>) -> Result<(), Error> {
+    observe("delete_blob", || {
+        let meta = load_blob_meta(&hash)?;
+        ensure_controller().or_else(|_| ensure_caller_is(meta.owner_id))?;
+        if cited_blobs().contains(&meta.hash) {
+            return Err(Error::InvalidInput {
+                msg: "Blobs cited by a claim, assessment or dispute cannot be deleted".to_string(),
+            });
+        }
+        remove_blob(&meta);
+        Ok(())
+    })
+}
+
+// Removes up to `limit` blobs that nothing has cited within BLOB_ORPHAN_GRACE_NS of their upload.
+// Returns the number of blobs removed
+#[ic_cdk::update]
+fn prune_orphaned_blobs(limit: u64) -> Result<u64, Error> {
+    observe("prune_orphaned_blobs", || {
+        ensure_controller()?;
+        let cited = cited_blobs();
+        let cutoff = time().saturating_sub(BLOB_ORPHAN_GRACE_NS);
+        let orphans: Vec<BlobMeta> = BLOB_METAS.with(|metas| {
+            metas
+                .borrow()
+                .iter()
+                .map(|(_, meta)| meta)
+                .filter(|meta| meta.created_at < cutoff && !cited.contains(&meta.hash))
+                .take(limit as usize)
+                .collect()
+        });
+        for meta in &orphans {
+            remove_blob(meta);
+        }
+        Ok(orphans.len() as u64)
+    })
+}
+
+#[ic_cdk::query]
+fn get_blob_usage(user_id: u64) -> BlobUsage {
+    BlobUsage {
+        user_id,
+        used_bytes: BLOB_USAGE.with(|usage| usage.borrow().get(&user_id)).unwrap_or(0),
+        quota_bytes: BLOB_QUOTA_BYTES,
+    }
+}
+
+#[ic_cdk::update]
+fn attach_claim_evidence(claim_id: u64, hash: Vec<u8>) -> Result<InsuranceClaim, Error> {
+    observe("attach_claim_evidence", || {
+        let mut claim = INSURANCE_CLAIMS
+            .with(|claims| claims.borrow().get(&claim_id))
+            .ok_or(Error::NotFound {
+                msg: format!("Insurance claim with id={} not found", claim_id),
+            })?;
+        ensure_caller_is(claim.farmer_id)?;
+        if !matches!(
+            claim.status,
+            ClaimStatus::Submitted
+                | ClaimStatus::ManualReview
+                | ClaimStatus::UnderAssessment
+                | ClaimStatus::Verified
+                | ClaimStatus::Appealed
+        ) {
+            return Err(Error::InvalidInput {
+                msg: "Evidence can only be attached to claims under review".to_string(),
+            });
+        }
+        load_readable_blob(&hash)?;
+        if claim.evidence_hashes.contains(&hash) {
+            return Err(Error::InvalidInput {
+                msg: "This evidence is already attached to the claim".to_string(),
+            });
+        }
+        if claim.evidence_hashes.len() >= MAX_CLAIM_EVIDENCE_BLOBS {
+            return Err(Error::InvalidInput {
+                msg: format!("A claim can reference at most {} evidence blobs", MAX_CLAIM_EVIDENCE_BLOBS),
+            });
+        }
+
+        claim.evidence_hashes.push(hash);
+        screen_claim(&mut claim);
+        store_claim(&claim);
+        Ok(claim)
+    })
+}
+
+#[ic_cdk::query]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    let (path, query) = request.url.split_once('?').unwrap_or((&request.url, ""));
+    let mut response = match request.method.as_str() {
+        // CORS preflight
+        "OPTIONS" => HttpResponse {
+            status_code: 204,
+            headers: Vec::new(),
+            body: ByteBuf::new(),
+        },
+        "GET" if path.starts_with("/api/") => serve_api(path, &parse_query_string(query)),
+        "GET" if path == "/metrics" => serve_metrics(),
+        "GET" => http_error(404, "Not found"),
+        _ => http_error(405, "Method not allowed"),
+    };
+    response.headers.extend([
+        ("Access-Control-Allow-Origin".to_string(), "*".to_string()),
+        ("Access-Control-Allow-Methods".to_string(), "GET, OPTIONS".to_string()),
+        ("Access-Control-Allow-Headers".to_string(), "Content-Type".to_string()),
+    ]);
+    response
+}
+
+// Read-only JSON API for partners that do not speak Candid. Lists accept `offset` and `limit`
+// and claims and proposals can be filtered by `status`, e.g. /api/claims?status=Approved&limit=50
+fn serve_api(path: &str, params: &[(String, String)]) -> HttpResponse {
+    let segments: Vec<&str> = path.trim_start_matches("/api/").trim_end_matches('/').split('/').collect();
+    let result = match segments.as_slice() {
+        ["contracts"] => {
+            let filter = None::<fn(&InsuranceContract) -> bool>;
+            api_page(&INSURANCE_CONTRACTS, params, filter, |contract| contract)
+        }
+        ["contracts", id] => api_record(&INSURANCE_CONTRACTS, id, |contract| contract),
+        ["claims"] => api_status_filter::<ClaimStatus>(params).and_then(|status| {
+            let filter = status.map(|status| move |claim: &InsuranceClaim| claim.status == status);
+            api_page(&INSURANCE_CLAIMS, params, filter, PublicClaim::from)
+        }),
+        ["claims", id] => api_record(&INSURANCE_CLAIMS, id, PublicClaim::from),
+        ["proposals"] => api_status_filter::<ProposalStatus>(params).and_then(|status| {
+            let filter = status.map(|status| move |proposal: &GovernanceProposal| proposal.status == status);
+            api_page(&GOVERNANCE_PROPOSALS, params, filter, |proposal| proposal)
+        }),
+        ["proposals", id] => api_record(&GOVERNANCE_PROPOSALS, id, |proposal| proposal),
+        ["stats"] => Ok(json_response(200, &api_stats())),
+        _ => Ok(json_error(404, "Not found")),
+    };
+    result.unwrap_or_else(|msg| json_error(400, &msg))
+}
+
+#[derive(Serialize)]
+struct ApiPage<T> {
+    items: Vec<T>,
+    total: u64,
+    offset: u64,
+    limit: u64,
+}
+
+// Claim as published by the JSON API; evidence can hold personal documents and stays private
+#[derive(Serialize)]
+struct PublicClaim {
+    id: u64,
+    farmer_id: u64,
+    contract_id: u64,
+    claim_details: String,
+    affected_crops: Vec<AffectedCrop>,
+    peril: Peril,
+    status: ClaimStatus,
+    claimed_amount: f64,
+    submitted_at: u64,
+    rejected_at: Option<u64>,
+    appeal_dispute_id: Option<u64>,
+    paid_out: bool,
+    assessor_ids: Vec<u64>,
+    assessments_disagree: bool,
+    accepted_assessment_id: Option<u64>,
+    payout_amount: Option<f64>,
+}
+
+impl From<InsuranceClaim> for PublicClaim {
+    fn from(claim: InsuranceClaim) -> Self {
+        PublicClaim {
+            id: claim.id,
+            farmer_id: claim.farmer_id,
+            contract_id: claim.contract_id,
+            claim_details: claim.claim_details,
+            affected_crops: claim.affected_crops,
+            peril: claim.peril,
+            status: claim.status,
+            claimed_amount: claim.claimed_amount,
+            submitted_at: claim.submitted_at,
+            rejected_at: claim.rejected_at,
+            appeal_dispute_id: claim.appeal_dispute_id,
+            paid_out: claim.paid_out,
+            assessor_ids: claim.assessor_ids,
+            assessments_disagree: claim.assessments_disagree,
+            accepted_assessment_id: claim.accepted_assessment_id,
+            payout_amount: claim.payout_amount,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ApiStats {
+    users: u64,
+    contracts: u64,
+    active_contracts: u64,
+    claims: u64,
+    open_claims: u64,
+    approved_claims: u64,
+    paid_out: f64,
+    proposals: u64,
+    open_proposals: u64,
+    total_bonded_stake: f64,
+    risk_pool: f64,
+}
+
+type StableMap<V> = std::thread::LocalKey<RefCell<StableBTreeMap<u64, V, Memory>>>;
+
+// Only the records of the requested page are kept, and without a filter only they are decoded
+fn api_page<V: Storable, T: serde::Serialize>(
+    map: &'static StableMap<V>,
+    params: &[(String, String)],
+    filter: Option<impl Fn(&V) -> bool>,
+    view: impl Fn(V) -> T,
+) -> Result<HttpResponse, String> {
+    let offset = api_number_param(params, "offset")?.unwrap_or(0);
+    let limit = api_number_param(params, "limit")?
+        .unwrap_or(DEFAULT_API_PAGE_SIZE)
+        .min(MAX_PAGE_SIZE);
+
+    let (total, items) = map.with(|map| {
+        let map = map.borrow();
+        let Some(filter) = filter else {
+            let items = map
+                .keys()
+                .skip(offset as usize)
+                .take(limit as usize)
+                .filter_map(|id| map.get(&id))
+                .map(&view)
+                .collect();
+            return (map.len(), items);
+        };
+        let (mut total, mut items) = (0, Vec::new());
+        for (_, value) in map.iter().filter(|(_, value)| filter(value)) {
+            if total >= offset && (items.len() as u64) < limit {
+                items.push(view(value));
+            }
+            total += 1;
+        }
+        (total, items)
+    });
+    Ok(json_response(
+        200,
+        &ApiPage {
+            items,
+            total,
+            offset,
+            limit,
+        },
+    ))
+}
+
+fn api_record<V: Storable, T: serde::Serialize>(
+    map: &'static StableMap<V>,
+    id: &str,
+    view: impl Fn(V) -> T,
+) -> Result<HttpResponse, String> {
+    let id: u64 = id.parse().map_err(|_| "Ids must be unsigned integers".to_string())?;
+    Ok(match map.with(|map| map.borrow().get(&id)) {
+        Some(value) => json_response(200, &view(value)),
+        None => json_error(404, "Not found"),
+    })
+}
+
+fn api_number_param(params: &[(String, String)], name: &str) -> Result<Option<u64>, String> {
+    match params.iter().find(|(key, _)| key == name) {
+        Some((_, value)) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("{} must be an unsigned integer", name)),
+        None => Ok(None),
+    }
+}
+
+// Statuses are matched by their variant name, e.g. "Approved"
+fn api_status_filter<S: serde::de::DeserializeOwned>(params: &[(String, String)]) -> Result<Option<S>, String> {
+    match params.iter().find(|(key, _)| key == "status") {
+        Some((_, value)) => parse_variant(value)
+            .map(Some)
+            .map_err(|_| format!("Unknown status {}", value)),
+        None => Ok(None),
+    }
+}
+
+fn api_stats() -> ApiStats {
+    let (mut contracts, mut active_contracts) = (0, 0);
+    INSURANCE_CONTRACTS.with(|map| {
+        for (_, contract) in map.borrow().iter() {
+            contracts += 1;
+            if contract.status == ContractStatus::Active {
+                active_contracts += 1;
+            }
+        }
+    });
+    let (mut claims, mut open_claims, mut approved_claims, mut paid_out) = (0, 0, 0, 0.0);
+    INSURANCE_CLAIMS.with(|map| {
+        for (_, claim) in map.borrow().iter() {
+            claims += 1;
+            match claim.status {
+                ClaimStatus::Approved => approved_claims += 1,
+                ClaimStatus::Rejected | ClaimStatus::Fraudulent => {}
+                _ => open_claims += 1,
+            }
+            if claim.paid_out {
+                paid_out += claim.payout_amount.unwrap_or_default();
+            }
+        }
+    });
+    let (mut proposals, mut open_proposals) = (0, 0);
+    GOVERNANCE_PROPOSALS.with(|map| {
+        for (_, proposal) in map.borrow().iter() {
+            proposals += 1;
+            if proposal.status == ProposalStatus::Open {
+                open_proposals += 1;
+            }
+        }
+    });
+    let (users, total_bonded_stake) = USER_PROFILES.with(|map| {
+        map.borrow()
+            .iter()
+            .fold((0, 0.0), |(count, stake), (_, profile)| (count + 1, stake + profile.stake_in_dao))
+    });
+
+    ApiStats {
+        users,
+        contracts,
+        active_contracts,
+        claims,
+        open_claims,
+        approved_claims,
+        paid_out,
+        proposals,
+        open_proposals,
+        total_bonded_stake,
+        risk_pool: get_treasury().risk_pool,
+    }
+}
+
+// Splits `a=1&b=2` into pairs, decoding percent escapes and `+` as a space
+fn parse_query_string(query: &str) -> Vec<(String, String)> {
+    let decode = |text: &str| {
+        let bytes = text.as_bytes();
+        let mut decoded = Vec::with_capacity(bytes.len());
+        let mut index = 0;
+        while index < bytes.len() {
+            match bytes[index] {
+                b'%' => match text.get(index + 1..index + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        index += 3;
+                    }
+                    None => {
+                        decoded.push(b'%');
+                        index += 1;
+                    }
+                },
+                b'+' => {
+                    decoded.push(b' ');
+                    index += 1;
+                }
+                byte => {
+                    decoded.push(byte);
+                    index += 1;
+                }
+            }
+        }
+        String::from_utf8_lossy(&decoded).into_owned()
+    };
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(key), decode(value))
+        })
+        .collect()
+}
+
+fn json_response(status_code: u16, body: &impl serde::Serialize) -> HttpResponse {
+    HttpResponse {
+        status_code,
+        headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+        body: ByteBuf::from(serde_json::to_vec(body).expect("Cannot encode the response")),
+    }
+}
+
+fn json_error(status_code: u16, message: &str) -> HttpResponse {
+    json_response(status_code, &serde_json::json!({ "error": message }))
+}
+
+fn http_error(status_code: u16, message: &str) -> HttpResponse {
+    HttpResponse {
+        status_code,
+        headers: vec![("Content-Type".to_string(), "text/plain".to_string())],
+        body: ByteBuf::from(message.as_bytes().to_vec()),
+    }
+}
+
+#[ic_cdk::update]
+fn register_plot(farmer_id: u64, payload: PlotPayload) -> Result<Plot, Error> {
+    observe("register_plot", || {
+        ensure_caller_is(farmer_id)?;
+        let area_hectares = validate_plot_payload(&payload)?;
+
+        let id = ID_COUNTER.with(|c| {
+            let current_value = *c.borrow().get();
+            c.borrow_mut().set(current_value + 1).unwrap();
+            current_value
+        });
+        let plot = Plot {
+            id,
+            farmer_id,
+            name: payload.name,
+            geometry: payload.geometry,
+            area_hectares,
+            soil_type: payload.soil_type,
+            region_code: payload.region_code,
+            plantings: Vec::new(),
+            created_at: time(),
+        };
+        PLOTS.with(|plots| plots.borrow_mut().insert(id, plot.clone()));
+        Ok(plot)
+    })
+}
+
+#[ic_cdk::update]
+fn update_plot(plot_id: u64, payload: PlotPayload) -> Result<Plot, Error> {
+    observe("update_plot", || {
+        let mut plot = load_plot(plot_id)?;
+        ensure_caller_is(plot.farmer_id)?;
+        plot.area_hectares = validate_plot_payload(&payload)?;
+        plot.name = payload.name;
+        plot.geometry = payload.geometry;
+        plot.soil_type = payload.soil_type;
+        plot.region_code = payload.region_code;
+        PLOTS.with(|plots| plots.borrow_mut().insert(plot_id, plot.clone()));
+        Ok(plot)
+    })
+}
+
+// Records a crop planted on the plot for a season; several crops may share a season
+#[ic_cdk::update]
+fn record_plot_planting(plot_id: u64, season: String, crop_id: u64) -> Result<Plot, Error> {
+    observe("record_plot_planting", || {
+        let mut plot = load_plot(plot_id)?;
+        ensure_caller_is(plot.farmer_id)?;
+        if season.trim().is_empty() || season.len() > MAX_PLOT_LABEL_LENGTH {
+            return Err(Error::InvalidInput {
+                msg: format!("Seasons must be between 1 and {} characters", MAX_PLOT_LABEL_LENGTH),
+            });
+        }
+        validate_crop_ids(&[crop_id])?;
+        if plot.plantings.iter().any(|planting| planting.season == season && planting.crop_id == crop_id) {
+            return Err(Error::InvalidInput {
+                msg: format!("Crop with id={} is already recorded for season {}", crop_id, season),
+            });
+        }
+        if plot.plantings.len() >= MAX_PLANTINGS_PER_PLOT {
+            return Err(Error::InvalidInput {
+                msg: format!("A plot can record at most {} plantings", MAX_PLANTINGS_PER_PLOT),
+            });
+        }
+
+        plot.plantings.push(SeasonalPlanting {
+            season,
+            crop_id,
+            planted_at: time(),
+        });
+        PLOTS.with(|plots| plots.borrow_mut().insert(plot_id, plot.clone()));
+        Ok(plot)
+    })
+}
+
+// Checks the plot details and returns its area, computing it from the polygon when not given
+fn validate_plot_payload(payload: &PlotPayload) -> Result<f64, Error> {
+    if payload.name.trim().is_empty() || payload.name.len() > MAX_PLOT_LABEL_LENGTH {
+        return Err(Error::InvalidInput {
+            msg: format!("Plot names must be between 1 and {} characters", MAX_PLOT_LABEL_LENGTH),
+        });
+    }
+    if payload.region_code.is_empty() || payload.region_code.len() > MAX_REGION_CODE_LENGTH {
+        return Err(Error::InvalidInput {
+            msg: format!("Region codes must be between 1 and {} characters", MAX_REGION_CODE_LENGTH),
+        });
+    }
+    let points = match &payload.geometry {
+        PlotGeometry::Point(point) => vec![*point],
+        PlotGeometry::Polygon(vertices) if (3..=MAX_PLOT_VERTICES).contains(&vertices.len()) => vertices.clone(),
+        PlotGeometry::Polygon(_) => {
+            return Err(Error::InvalidInput {
+                msg: format!("Polygons must have between 3 and {} vertices", MAX_PLOT_VERTICES),
+            })
+        }
+    };
+    if points.iter().any(|point| !(-90.0..=90.0).contains(&point.lat) || !(-180.0..=180.0).contains(&point.lon)) {
+        return Err(Error::InvalidInput {
+            msg: "Coordinates must be valid WGS84 latitudes and longitudes".to_string(),
+        });
+    }
+
+    let area_hectares = match (&payload.geometry, payload.area_hectares) {
+        (_, Some(area)) => area,
+        (PlotGeometry::Polygon(vertices), None) => polygon_area_hectares(vertices),
+        (PlotGeometry::Point(_), None) => {
+            return Err(Error::InvalidInput {
+                msg: "The area is required for plots located by a single point".to_string(),
+            })
+        }
+    };
+    if !area_hectares.is_finite() || area_hectares <= 0.0 {
+        return Err(Error::InvalidInput {
+            msg: "The plot area must be positive".to_string(),
+        });
+    }
+    Ok(area_hectares)
+}
+
+// Shoelace formula on an equirectangular projection, accurate enough for field-sized polygons
+fn polygon_area_hectares(vertices: &[GeoPoint]) -> f64 {
+    let mean_lat = vertices.iter().map(|point| point.lat).sum::<f64>() / vertices.len() as f64;
+    let project = |point: &GeoPoint| {
+        (
+            point.lon.to_radians() * mean_lat.to_radians().cos() * EARTH_RADIUS_M,
+            point.lat.to_radians() * EARTH_RADIUS_M,
+        )
+    };
+    let doubled_area: f64 = vertices
+        .iter()
+        .zip(vertices.iter().cycle().skip(1))
+        .map(|(a, b)| {
+            let ((x1, y1), (x2, y2)) = (project(a), project(b));
+            x1 * y2 - x2 * y1
+        })
+        .sum();
+    doubled_area.abs() / 2.0 / 10_000.0
+}
+
+fn plot_bounds(plot: &Plot) -> BoundingBox {
+    let points = match &plot.geometry {
+        PlotGeometry::Point(point) => std::slice::from_ref(point),
+        PlotGeometry::Polygon(vertices) => vertices.as_slice(),
+    };
+    points.iter().fold(
+        BoundingBox {
+            min_lat: f64::MAX,
+            min_lon: f64::MAX,
+            max_lat: f64::MIN,
+            max_lon: f64::MIN,
+        },
+        |bounds, point| BoundingBox {
+            min_lat: bounds.min_lat.min(point.lat),
+            min_lon: bounds.min_lon.min(point.lon),
+            max_lat: bounds.max_lat.max(point.lat),
+            max_lon: bounds.max_lon.max(point.lon),
+        },
+    )
+}
+
+fn load_plot(plot_id: u64) -> Result<Plot, Error> {
+    PLOTS.with(|plots| plots.borrow().get(&plot_id)).ok_or(Error::NotFound {
+        msg: format!("Plot with id={} not found", plot_id),
+    })
+}
+
+#[ic_cdk::query]
+fn get_plot(plot_id: u64) -> Result<Plot, Error> {
+    load_plot(plot_id)
+}
+
+#[ic_cdk::query]
+fn get_farmer_plots(farmer_id: u64) -> Vec<Plot> {
+    PLOTS.with(|plots| {
+        plots
+            .borrow()
+            .iter()
+            .filter(|(_, plot)| plot.farmer_id == farmer_id)
+            .map(|(_, plot)| plot)
+            .collect()
+    })
+}
+
+// Plots whose extent intersects the box; boxes crossing the antimeridian are not supported
+#[ic_cdk::query]
+fn find_plots_in_bounding_box(bounds: BoundingBox, offset: u64, limit: u64) -> Result<PlotPage, Error> {
+    if bounds.min_lat > bounds.max_lat || bounds.min_lon > bounds.max_lon {
+        return Err(Error::InvalidInput {
+            msg: "The minimum corner of the box must not exceed its maximum corner".to_string(),
+        });
+    }
+    Ok(plot_page(
+        |plot| {
+            let extent = plot_bounds(plot);
+            extent.min_lat <= bounds.max_lat
+                && extent.max_lat >= bounds.min_lat
+                && extent.min_lon <= bounds.max_lon
+                && extent.max_lon >= bounds.min_lon
+        },
+        offset,
+        limit,
+    ))
+}
+
+#[ic_cdk::query]
+fn find_plots_in_region(region_code: String, offset: u64, limit: u64) -> PlotPage {
+    plot_page(|plot| plot.region_code == region_code, offset, limit)
+}
+
+fn plot_page(filter: impl Fn(&Plot) -> bool, offset: u64, limit: u64) -> PlotPage {
+    let matching: Vec<Plot> = PLOTS.with(|plots| {
+        plots
+            .borrow()
+            .iter()
+            .map(|(_, plot)| plot)
+            .filter(|plot| filter(plot))
+            .collect()
+    });
+    PlotPage {
+        total: matching.len() as u64,
+        plots: matching
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit.min(MAX_PAGE_SIZE) as usize)
+            .collect(),
+    }
+}
+
+// Scores the claim against the fraud rules, stores the report and holds suspicious claims for review
+fn screen_claim(claim: &mut InsuranceClaim) -> FraudReport {
+    let other_claims: Vec<InsuranceClaim> = INSURANCE_CLAIMS.with(|claims| {
+        claims
+            .borrow()
+            .iter()
+            .map(|(_, other)| other)
+            .filter(|other| other.id != claim.id && other.status != ClaimStatus::Rejected)
+            .collect()
+    });
+    let mut signals = Vec::new();
+
+    let plot_ids: Vec<u64> = claim.affected_crops.iter().map(|affected| affected.plot_id).collect();
+    if let Some(duplicate) = other_claims.iter().find(|other| {
+        other.peril == claim.peril
+            && claim.submitted_at.abs_diff(other.submitted_at) < FRAUD_DUPLICATE_WINDOW_NS
+            && other.affected_crops.iter().any(|affected| plot_ids.contains(&affected.plot_id))
+    }) {
+        signals.push(FraudSignal {
+            rule: FraudRule::DuplicateClaim,
+            score: FRAUD_DUPLICATE_SCORE,
+            detail: format!("Claim with id={} covers the same plot and peril", duplicate.id),
+        });
+    }
+
+    let activated_at = INSURANCE_CONTRACTS
+        .with(|contracts| contracts.borrow().get(&claim.contract_id))
+        .and_then(|contract| contract.activated_at);
+    if activated_at.is_some_and(|at| claim.submitted_at < at + FRAUD_EARLY_CLAIM_WINDOW_NS) {
+        signals.push(FraudSignal {
+            rule: FraudRule::EarlyClaim,
+            score: FRAUD_EARLY_CLAIM_SCORE,
+            detail: "Claim submitted shortly after the contract was activated".to_string(),
+        });
+    }
+
+    if let Some(region_code) = weather_mismatch(claim, &plot_ids) {
+        signals.push(FraudSignal {
+            rule: FraudRule::WeatherMismatch,
+            score: FRAUD_WEATHER_MISMATCH_SCORE,
+            detail: format!("No {:?} was observed in region {} before the claim", claim.peril, region_code),
+        });
+    }
+
+    let recent_claims = other_claims
+        .iter()
+        .filter(|other| {
+            other.farmer_id == claim.farmer_id
+                && claim.submitted_at.abs_diff(other.submitted_at) < FRAUD_FREQUENCY_WINDOW_NS
+        })
+        .count();
+    if recent_claims >= FRAUD_FREQUENCY_LIMIT {
+        signals.push(FraudSignal {
+            rule: FraudRule::HighFrequency,
+            score: FRAUD_FREQUENCY_SCORE,
+            detail: format!("The farmer filed {} other claims within a year", recent_claims),
+        });
+    }
+
+    if let Some(reused) = other_claims.iter().find(|other| {
+        other
+            .evidence_hashes
+            .iter()
+            .any(|hash| claim.evidence_hashes.contains(hash))
+    }) {
+        signals.push(FraudSignal {
+            rule: FraudRule::ReusedEvidence,
+            score: FRAUD_REUSED_EVIDENCE_SCORE,
+            detail: format!("Evidence is also attached to claim with id={}", reused.id),
+        });
+    }
+
+    let report = FraudReport {
+        claim_id: claim.id,
+        score: signals.iter().map(|signal| signal.score).sum(),
+        signals,
+        screened_at: time(),
+    };
+    if report.score >= FRAUD_REVIEW_THRESHOLD
+        && matches!(claim.status, ClaimStatus::Submitted | ClaimStatus::UnderAssessment)
+    {
+        claim.status = ClaimStatus::ManualReview;
+    }
+    FRAUD_REPORTS.with(|reports| reports.borrow_mut().insert(claim.id, report.clone()));
+    report
+}
+
+// Returns a region covered by the weather oracle in which the claimed weather peril was not observed
+// during the lookback period; pests and diseases are not weather events and are never checked
+fn weather_mismatch(claim: &InsuranceClaim, plot_ids: &[u64]) -> Option<String> {
+    if matches!(claim.peril, Peril::Pests | Peril::Disease) {
+        return None;
+    }
+    let since = claim.submitted_at.saturating_sub(FRAUD_WEATHER_LOOKBACK_NS);
+    let observations: Vec<WeatherObservation> =
+        WEATHER_OBSERVATIONS.with(|observations| observations.borrow().iter().map(|(_, observation)| observation).collect());
+
+    plot_ids
+        .iter()
+        .filter_map(|plot_id| load_plot(*plot_id).ok())
+        .map(|plot| plot.region_code)
+        .find(|region_code| {
+            let mut in_region = observations.iter().filter(|observation| &observation.region_code == region_code).peekable();
+            in_region.peek().is_some()
+                && !in_region.any(|observation| {
+                    observation.peril == claim.peril
+                        && observation.ended_at >= since
+                        && observation.started_at <= claim.submitted_at
+                })
+        })
+}
+
+// Weather events reported by the oracle operators, used to cross-check claimed perils
+#[ic_cdk::update]
+fn record_weather_observation(
+    region_code: String,
+    peril: Peril,
+    started_at: u64,
+    ended_at: u64,
+) -> Result<WeatherObservation, Error> {
+    observe("record_weather_observation", || {
+        ensure_controller()?;
+        if region_code.is_empty() || region_code.len() > MAX_REGION_CODE_LENGTH {
+            return Err(Error::InvalidInput {
+                msg: format!("Region codes must be between 1 and {} characters", MAX_REGION_CODE_LENGTH),
+            });
+        }
+        if started_at > ended_at {
+            return Err(Error::InvalidInput {
+                msg: "An observation cannot end before it starts".to_string(),
+            });
+        }
+
+        let id = ID_COUNTER.with(|c| {
+            let current_value = *c.borrow().get();
+            c.borrow_mut().set(current_value + 1).unwrap();
+            current_value
+        });
+        let observation = WeatherObservation {
+            id,
+            region_code,
+            peril,
+            started_at,
+            ended_at,
+            recorded_at: time(),
+        };
+        WEATHER_OBSERVATIONS.with(|observations| observations.borrow_mut().insert(id, observation.clone()));
+        Ok(observation)
+    })
+}
+
+#[ic_cdk::query]
+fn get_weather_observations(region_code: String) -> Vec<WeatherObservation> {
+    WEATHER_OBSERVATIONS.with(|observations| {
+        observations
+            .borrow()
+            .iter()
+            .map(|(_, observation)| observation)
+            .filter(|observation| observation.region_code == region_code)
+            .collect()
+    })
+}
+
+// Fraud scores are only shown to controllers and the claim's assessors
+#[ic_cdk::query]
+fn get_fraud_report(claim_id: u64) -> Result<FraudReport, Error> {
+    let claim = load_claim(claim_id)?;
+    if ensure_controller().is_err() {
+        let is_assessor = claim.assessor_ids.iter().any(|assessor_id| {
+            USER_PROFILES.with(|profiles| profiles.borrow().get(assessor_id)).is_some_and(|profile| is_profile_caller(&profile))
+        });
+        if !is_assessor {
+            return Err(Error::Unauthorized {
+                msg: "Only controllers and assessors of the claim can see its fraud report".to_string(),
+            });
+        }
+    }
+    FRAUD_REPORTS
+        .with(|reports| reports.borrow().get(&claim_id))
+        .ok_or(Error::NotFound {
+            msg: format!("No fraud report for claim with id={}", claim_id),
+        })
+}
+
+// Releases a claim held for review back into assessment; confirmed fraud goes through flag_fraudulent_claim
+#[ic_cdk::update]
+fn clear_fraud_review(claim_id: u64) -> Result<InsuranceClaim, Error> {
+    observe("clear_fraud_review", || {
+        ensure_controller()?;
+        let mut claim = load_claim(claim_id)?;
+        if claim.status != ClaimStatus::ManualReview {
+            return Err(Error::InvalidInput {
+                msg: "Only claims held for manual review can be cleared".to_string(),
+            });
+        }
+
+        claim.status = if claim.assessor_ids.is_empty() {
+            ClaimStatus::Submitted
+        } else {
+            ClaimStatus::UnderAssessment
+        };
+        store_claim(&claim);
+        Ok(claim)
+    })
+}
+
+// Appends an event to the log; sequence numbers are contiguous so indexers can detect gaps
+fn record_event(kind: EventKind) {
+    EVENT_LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        let seq = log.last_key_value().map_or(1, |(seq, _)| seq + 1);
+        log.insert(
+            seq,
+            Event {
+                seq,
+                timestamp: time(),
+                caller: caller(),
+                kind,
+            },
+        );
+    });
+}
+
+// Events with a sequence number of at least `from_seq`, oldest first
+#[ic_cdk::query]
+fn get_events(from_seq: u64, limit: u64) -> Vec<Event> {
+    EVENT_LOG.with(|log| {
+        log.borrow()
+            .range(from_seq..)
+            .take(limit.min(MAX_PAGE_SIZE) as usize)
+            .map(|(_, event)| event)
+            .collect()
+    })
+}
+
+fn store_user(profile: &UserProfile) {
+    USER_PROFILES.with(|profiles| profiles.borrow_mut().insert(profile.id, profile.clone()));
+    certify_record(CERTIFIED_USERS, profile.id, profile);
+}
+
+fn store_contract(contract: &InsuranceContract) {
+    INSURANCE_CONTRACTS.with(|contracts| contracts.borrow_mut().insert(contract.id, contract.clone()));
+    certify_record(CERTIFIED_CONTRACTS, contract.id, contract);
+}
+
+fn store_proposal(proposal: &GovernanceProposal) {
+    GOVERNANCE_PROPOSALS.with(|proposals| proposals.borrow_mut().insert(proposal.id, proposal.clone()));
+    certify_record(CERTIFIED_PROPOSALS, proposal.id, proposal);
+}
+
+// Updates the hash of a record in the certified tree and certifies the new root
+fn certify_record(kind: &'static [u8], id: u64, record: &impl Storable) {
+    let hash: Hash = Sha256::digest(record.to_bytes()).into();
+    CERTIFIED_RECORDS.with(|tree| {
+        let mut tree = tree.borrow_mut();
+        if tree.get(kind).is_none() {
+            tree.insert(kind, RbTree::new());
+        }
+        tree.modify(kind, |records| records.insert(id.to_be_bytes(), hash));
+        set_certified_data(&tree.root_hash());
+    });
+}
+
+fn uncertify_record(kind: &'static [u8], id: u64) {
+    CERTIFIED_RECORDS.with(|tree| {
+        let mut tree = tree.borrow_mut();
+        tree.modify(kind, |records| records.delete(&id.to_be_bytes()));
+        set_certified_data(&tree.root_hash());
+    });
+}
+
+// The certified tree lives on the heap, so it is recomputed from stable memory after an upgrade
+fn rebuild_certified_records() {
+    USER_PROFILES.with(|profiles| {
+        for (id, profile) in profiles.borrow().iter() {
+            certify_record(CERTIFIED_USERS, id, &profile);
+        }
+    });
+    INSURANCE_CONTRACTS.with(|contracts| {
+        for (id, contract) in contracts.borrow().iter() {
+            certify_record(CERTIFIED_CONTRACTS, id, &contract);
+        }
+    });
+    INSURANCE_CLAIMS.with(|claims| {
+        for (id, claim) in claims.borrow().iter() {
+            certify_record(CERTIFIED_CLAIMS, id, &claim);
+        }
+    });
+    GOVERNANCE_PROPOSALS.with(|proposals| {
+        for (id, proposal) in proposals.borrow().iter() {
+            certify_record(CERTIFIED_PROPOSALS, id, &proposal);
+        }
+    });
+}
+
+// Wraps a record with the subnet's certificate and a witness of its hash in the certified tree
+fn certify_response<T: Storable>(kind: &'static [u8], id: u64, value: T) -> Result<Certified<T>, Error> {
+    let certificate = data_certificate().ok_or(Error::InvalidInput {
+        msg: "Certified reads are only available through query calls".to_string(),
+    })?;
+    let witness = CERTIFIED_RECORDS.with(|tree| {
+        tree.borrow()
+            .nested_witness(kind, |records| records.witness(&id.to_be_bytes()))
+    });
+    let mut serializer = serde_cbor::Serializer::new(Vec::new());
+    serializer.self_describe().expect("Cannot encode the witness");
+    serde::Serialize::serialize(&witness, &mut serializer).expect("Cannot encode the witness");
+
+    Ok(Certified {
+        data: ByteBuf::from(value.to_bytes().into_owned()),
+        value,
+        certificate: ByteBuf::from(certificate),
+        witness: ByteBuf::from(serializer.into_inner()),
+    })
+}
+
+#[ic_cdk::query]
+fn read_certified_user_profile(user_id: u64) -> Result<Certified<UserProfile>, Error> {
+    certify_response(CERTIFIED_USERS, user_id, read_user_profile(user_id)?)
+}
+
+#[ic_cdk::query]
+fn read_certified_insurance_contract(contract_id: u64) -> Result<Certified<InsuranceContract>, Error> {
+    certify_response(CERTIFIED_CONTRACTS, contract_id, read_insurance_contract(contract_id)?)
+}
+
+#[ic_cdk::query]
+fn read_insurance_claim(claim_id: u64) -> Result<InsuranceClaim, Error> {
+    load_claim(claim_id)
+}
+
+#[ic_cdk::query]
+fn read_certified_insurance_claim(claim_id: u64) -> Result<Certified<InsuranceClaim>, Error> {
+    certify_response(CERTIFIED_CLAIMS, claim_id, load_claim(claim_id)?)
+}
+
+#[ic_cdk::query]
+fn read_certified_governance_proposal(proposal_id: u64) -> Result<Certified<GovernanceProposal>, Error> {
+    certify_response(CERTIFIED_PROPOSALS, proposal_id, read_governance_proposal(proposal_id)?)
+}
+
+#[derive(Clone, Copy, Default)]
+struct EndpointMetrics {
+    calls: u64,
+    errors: u64,
+}
+
+// Return values of update endpoints that tell whether the call failed
+trait CallOutcome {
+    fn failed(&self) -> bool;
+}
+
+impl<T, E> CallOutcome for Result<T, E> {
+    fn failed(&self) -> bool {
+        self.is_err()
+    }
+}
+
+impl<T> CallOutcome for Option<T> {
+    fn failed(&self) -> bool {
+        self.is_none()
+    }
+}
+
+impl CallOutcome for u64 {
+    fn failed(&self) -> bool {
+        false
+    }
+}
+
+// Runs the body of an update endpoint and counts the call. Query calls cannot change state,
+// so they are not counted
+fn observe<R: CallOutcome>(endpoint: &'static str, call: impl FnOnce() -> R) -> R {
+    ensure_writable();
+    observe_while_frozen(endpoint, call)
+}
+
+// Like observe, but also runs while writes are suspended. Only for endpoints that lift the suspension
+fn observe_while_frozen<R: CallOutcome>(endpoint: &'static str, call: impl FnOnce() -> R) -> R {
+    let outcome = call();
+    record_call(endpoint, &outcome);
+    outcome
+}
+
+fn record_call(endpoint: &'static str, outcome: &impl CallOutcome) {
+    ENDPOINT_METRICS.with(|metrics| {
+        let mut metrics = metrics.borrow_mut();
+        let entry = metrics.entry(endpoint).or_default();
+        entry.calls += 1;
+        if outcome.failed() {
+            entry.errors += 1;
+        }
+    });
+}
+
+// Operational metrics in the Prometheus text exposition format
+fn serve_metrics() -> HttpResponse {
+    let mut body = String::new();
+
+    let mut users = Samples::new();
+    let mut stake = Samples::new();
+    USER_PROFILES.with(|profiles| {
+        for (_, profile) in profiles.borrow().iter() {
+            *users.entry(metric_labels("role", &profile.role)).or_default() += 1.0;
+            *stake.entry("state=\"bonded\"".to_string()).or_default() += profile.stake_in_dao;
+        }
+    });
+    STAKE_ACCOUNTS.with(|accounts| {
+        for (_, account) in accounts.borrow().iter() {
+            *stake.entry("state=\"available\"".to_string()).or_default() += account.available;
+            *stake.entry("state=\"unbonding\"".to_string()).or_default() +=
+                account.unbonding.iter().map(|entry| entry.amount).sum::<f64>();
+        }
+    });
+    write_metric(&mut body, "agrisurance_users", "gauge", "Registered users by role", &users);
+    write_metric(&mut body, "agrisurance_stake", "gauge", "Staked tokens by state", &stake);
+
+    let mut contracts = Samples::new();
+    INSURANCE_CONTRACTS.with(|map| {
+        for (_, contract) in map.borrow().iter() {
+            *contracts.entry(metric_labels("status", &contract.status)).or_default() += 1.0;
+        }
+    });
+    write_metric(&mut body, "agrisurance_contracts", "gauge", "Insurance contracts by status", &contracts);
+
+    let mut claims = Samples::new();
+    INSURANCE_CLAIMS.with(|map| {
+        for (_, claim) in map.borrow().iter() {
+            *claims.entry(metric_labels("status", &claim.status)).or_default() += 1.0;
+        }
+    });
+    write_metric(&mut body, "agrisurance_claims", "gauge", "Insurance claims by status", &claims);
+
+    let open_proposals = GOVERNANCE_PROPOSALS.with(|map| {
+        map.borrow()
+            .iter()
+            .filter(|(_, proposal)| proposal.status == ProposalStatus::Open)
+            .count()
+    });
+    write_metric(
+        &mut body,
+        "agrisurance_open_proposals",
+        "gauge",
+        "Governance proposals open for voting",
+        &Samples::from([(String::new(), open_proposals as f64)]),
+    );
+
+    let mut disputes = Samples::new();
+    DISPUTE_STORAGE.with(|map| {
+        for (_, dispute) in map.borrow().iter() {
+            *disputes.entry(metric_labels("status", &dispute.status)).or_default() += 1.0;
+        }
+    });
+    write_metric(&mut body, "agrisurance_disputes", "gauge", "Disputes by status", &disputes);
+
+    write_metric(
+        &mut body,
+        "agrisurance_treasury_balance",
+        "gauge",
+        "Funds held by the DAO by account",
+        &Samples::from([("account=\"risk_pool\"".to_string(), get_treasury().risk_pool)]),
+    );
+    write_metric(
+        &mut body,
+        "agrisurance_cycle_balance",
+        "gauge",
+        "Cycles held by the canister",
+        &Samples::from([(String::new(), canister_balance128() as f64)]),
+    );
+
+    let memory_sizes = MEMORY_MANAGER.with(|manager| {
+        let manager = manager.borrow();
+        (0..MEMORY_ID_COUNT)
+            .map(|id| {
+                let pages = manager.get(MemoryId::new(id)).size();
+                (format!("memory_id=\"{}\"", id), (pages * WASM_PAGE_SIZE) as f64)
+            })
+            .collect()
+    });
+    write_metric(
+        &mut body,
+        "agrisurance_stable_memory_bytes",
+        "gauge",
+        "Stable memory used by each virtual memory",
+        &memory_sizes,
+    );
+
+    let (calls, errors): (Samples, Samples) = ENDPOINT_METRICS.with(|metrics| {
+        metrics
+            .borrow()
+            .iter()
+            .map(|(endpoint, metrics)| {
+                let labels = format!("endpoint=\"{}\"", endpoint);
+                ((labels.clone(), metrics.calls as f64), (labels, metrics.errors as f64))
+            })
+            .unzip()
+    });
+    write_metric(
+        &mut body,
+        "agrisurance_endpoint_calls_total",
+        "counter",
+        "Update calls per endpoint since the last upgrade",
+        &calls,
+    );
+    write_metric(
+        &mut body,
+        "agrisurance_endpoint_errors_total",
+        "counter",
+        "Update calls per endpoint that returned an error since the last upgrade",
+        &errors,
+    );
+
+    HttpResponse {
+        status_code: 200,
+        headers: vec![("Content-Type".to_string(), "text/plain; version=0.0.4".to_string())],
+        body: ByteBuf::from(body.into_bytes()),
+    }
+}
+
+fn write_metric(body: &mut String, name: &str, kind: &str, help: &str, samples: &Samples) {
+    body.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind));
+    for (labels, value) in samples {
+        if labels.is_empty() {
+            body.push_str(&format!("{} {}\n", name, value));
+        } else {
+            body.push_str(&format!("{}{{{}}} {}\n", name, labels, value));
+        }
+    }
+}
+
+// Renders an enum as a label using its variant name, e.g. status="Breached"
+fn metric_labels(label: &str, value: &impl serde::Serialize) -> String {
+    let variant = match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        Ok(serde_json::Value::Object(fields)) => fields.keys().next().cloned().unwrap_or_default(),
+        _ => String::new(),
+    };
+    format!("{}=\"{}\"", label, variant)
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+enum ExportFormat {
+    Csv, // One header row with the field names in alphabetical order; nested values are JSON encoded
+    JsonLines,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+enum ExportDataset {
+    Users,
+    Contracts,
+    Claims,
+    Transactions,
+    Proposals,
+    Votes,
+    StakeAdjustments,
+    Disputes,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct ExportFile {
+    dataset: ExportDataset,
+    rows: u64,
+    size: u64,
+    chunk_count: u32,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct ExportManifest {
+    snapshot_id: u64,
+    event_seq: u64, // Last event recorded before the snapshot was taken
+    created_at: u64,
+    format: ExportFormat,
+    files: Vec<ExportFile>, // Grows as the datasets are exported
+    complete: bool,
+}
+
+// Exported bytes are stored raw, split at EXPORT_CHUNK_SIZE
+struct ExportChunk(Vec<u8>);
+
+// Progress of the snapshot being built; the rows of each dataset are appended in key order
+struct ExportBuild {
+    snapshot_id: u64,
+    format: ExportFormat,
+    dataset: usize,          // Index into EXPORT_DATASETS
+    cursor: Option<Vec<u8>>, // Key of the last row exported from the dataset
+    rows: u64,
+    chunk_count: u32,
+    pending: Vec<u8>, // Rows that do not fill a chunk yet
+}
+
+enum WriteFreeze {
+    Export { snapshot_id: u64 },
+    Backup { expires_at: u64 },
+    Restore,
+}
+
+const EXPORT_DATASETS: [ExportDataset; 8] = [
+    ExportDataset::Users,
+    ExportDataset::Contracts,
+    ExportDataset::Claims,
+    ExportDataset::Transactions,
+    ExportDataset::Proposals,
+    ExportDataset::Votes,
+    ExportDataset::StakeAdjustments,
+    ExportDataset::Disputes,
+];
+
+// Update calls trap while writes are suspended, so that a snapshot taken or restored over several
+// calls cannot be torn by concurrent writes
+fn ensure_writable() {
+    if writes_frozen() {
+        ic_cdk::trap("Writes are suspended while an export, backup or restore is in progress, try again later");
+    }
+}
+
+fn writes_frozen() -> bool {
+    WRITE_FREEZE.with(|freeze| match *freeze.borrow() {
+        Some(WriteFreeze::Export { .. } | WriteFreeze::Restore) => true,
+        Some(WriteFreeze::Backup { expires_at }) => time() < expires_at,
+        None => false,
+    })
+}
+
+// Runs a timer job now, or once writes are no longer suspended
+fn run_when_writable(job: impl FnOnce() + 'static) {
+    if writes_frozen() {
+        set_timer(WRITE_FREEZE_RETRY_DELAY, move || run_when_writable(job));
+    } else {
+        job();
+    }
+}
+
+// Starts a snapshot of all auditable stores. It is built over as many calls as it takes, with
+// writes suspended until it is complete; the chunks can be fetched once the manifest says so
+#[ic_cdk::update]
+fn begin_export(format: ExportFormat) -> Result<ExportManifest, Error> {
+    observe("begin_export", || {
+        ensure_controller()?;
+        let id = ID_COUNTER.with(|c| {
+            let current_value = *c.borrow().get();
+            c.borrow_mut().set(current_value + 1).unwrap();
+            current_value
+        });
+        let manifest = ExportManifest {
+            snapshot_id: id,
+            event_seq: EVENT_LOG.with(|log| log.borrow().last_key_value().map_or(0, |(seq, _)| seq)),
+            created_at: time(),
+            format,
+            files: Vec::new(),
+            complete: false,
+        };
+
+        let snapshot_ids: Vec<u64> = EXPORT_MANIFESTS.with(|manifests| manifests.borrow().iter().map(|(id, _)| id).collect());
+        let excess = (snapshot_ids.len() + 1).saturating_sub(MAX_EXPORT_SNAPSHOTS);
+        for snapshot_id in &snapshot_ids[..excess] {
+            remove_export(*snapshot_id);
+        }
+        EXPORT_MANIFESTS.with(|manifests| manifests.borrow_mut().insert(id, manifest));
+        EXPORT_BUILD.with(|build| {
+            *build.borrow_mut() = Some(ExportBuild {
+                snapshot_id: id,
+                format,
+                dataset: 0,
+                cursor: None,
+                rows: 0,
+                chunk_count: 0,
+                pending: Vec::new(),
+            })
+        });
+        WRITE_FREEZE.with(|freeze| *freeze.borrow_mut() = Some(WriteFreeze::Export { snapshot_id: id }));
+
+        continue_export();
+        load_export_manifest(id)
+    })
+}
+
+// Exports rows for as long as the instruction budget of the call lasts and schedules another
+// call until every dataset is exported, then lifts the write suspension
+fn continue_export() {
+    let Some(mut build) = EXPORT_BUILD.with(|build| build.borrow_mut().take()) else {
+        return;
+    };
+    while let Some(dataset) = EXPORT_DATASETS.get(build.dataset).copied() {
+        let exhausted = match dataset {
+            ExportDataset::Users => export_rows(&USER_PROFILES, &mut build),
+            ExportDataset::Contracts => export_rows(&INSURANCE_CONTRACTS, &mut build),
+            ExportDataset::Claims => export_rows(&INSURANCE_CLAIMS, &mut build),
+            ExportDataset::Transactions => export_rows(&TRANSACTION_RECORDS, &mut build),
+            ExportDataset::Proposals => export_rows(&GOVERNANCE_PROPOSALS, &mut build),
+            ExportDataset::Votes => export_rows(&VOTING_RECORDS, &mut build),
+            ExportDataset::StakeAdjustments => export_rows(&STAKE_ADJUSTMENTS, &mut build),
+            ExportDataset::Disputes => export_rows(&DISPUTE_STORAGE, &mut build),
+        };
+        if !exhausted {
+            EXPORT_BUILD.with(|slot| *slot.borrow_mut() = Some(build));
+            set_timer(Duration::ZERO, continue_export);
+            return;
+        }
+        finish_export_file(&mut build, dataset);
+    }
+
+    update_export_manifest(build.snapshot_id, |manifest| manifest.complete = true);
+    WRITE_FREEZE.with(|freeze| *freeze.borrow_mut() = None);
+}
+
+// Appends the rows after the cursor, returning false if the instruction budget ran out first
+fn export_rows<K: Storable + Ord + Clone, V: Storable + serde::Serialize>(
+    map: &'static std::thread::LocalKey<RefCell<StableBTreeMap<K, V, Memory>>>,
+    build: &mut ExportBuild,
+) -> bool {
+    map.with(|map| {
+        let map = map.borrow();
+        let start = match &build.cursor {
+            Some(key) => std::ops::Bound::Excluded(K::from_bytes(Cow::Borrowed(key))),
+            None => std::ops::Bound::Unbounded,
+        };
+        for (key, record) in map.range((start, std::ops::Bound::Unbounded)) {
+            if instruction_counter() > EXPORT_STEP_INSTRUCTIONS {
+                return false;
+            }
+            let value = serde_json::to_value(&record).expect("Cannot encode the record");
+            match (build.format, value) {
+                (ExportFormat::JsonLines, value) => {
+                    serde_json::to_writer(&mut build.pending, &value).expect("Cannot encode the record");
+                    build.pending.push(b'\n');
+                }
+                (ExportFormat::Csv, serde_json::Value::Object(fields)) => {
+                    if build.rows == 0 {
+                        write_csv_row(&mut build.pending, fields.keys().cloned());
+                    }
+                    write_csv_row(&mut build.pending, fields.values().map(csv_cell));
+                }
+                (ExportFormat::Csv, value) => write_csv_row(&mut build.pending, std::iter::once(csv_cell(&value))),
+            }
+            build.rows += 1;
+            build.cursor = Some(key.to_bytes().into_owned());
+            while build.pending.len() >= EXPORT_CHUNK_SIZE {
+                let rest = build.pending.split_off(EXPORT_CHUNK_SIZE);
+                let chunk = std::mem::replace(&mut build.pending, rest);
+                store_export_chunk(build, chunk);
+            }
+        }
+        true
+    })
+}
+
+fn store_export_chunk(build: &mut ExportBuild, data: Vec<u8>) {
+    let key = (build.snapshot_id, EXPORT_DATASETS[build.dataset] as u8, build.chunk_count);
+    EXPORT_CHUNKS.with(|chunks| chunks.borrow_mut().insert(key, ExportChunk(data)));
+    build.chunk_count += 1;
+}
+
+fn finish_export_file(build: &mut ExportBuild, dataset: ExportDataset) {
+    let pending = std::mem::take(&mut build.pending);
+    let size = build.chunk_count as u64 * EXPORT_CHUNK_SIZE as u64 + pending.len() as u64;
+    if !pending.is_empty() {
+        store_export_chunk(build, pending);
+    }
+    let file = ExportFile {
+        dataset,
+        rows: build.rows,
+        size,
+        chunk_count: build.chunk_count,
+    };
+    update_export_manifest(build.snapshot_id, |manifest| manifest.files.push(file));
+
+    build.dataset += 1;
+    build.cursor = None;
+    build.rows = 0;
+    build.chunk_count = 0;
+}
+
+fn load_export_manifest(snapshot_id: u64) -> Result<ExportManifest, Error> {
+    EXPORT_MANIFESTS
+        .with(|manifests| manifests.borrow().get(&snapshot_id))
+        .ok_or(Error::NotFound {
+            msg: format!("Export snapshot with id={} not found", snapshot_id),
+        })
+}
+
+fn update_export_manifest(snapshot_id: u64, update: impl FnOnce(&mut ExportManifest)) {
+    EXPORT_MANIFESTS.with(|manifests| {
+        let mut manifests = manifests.borrow_mut();
+        if let Some(mut manifest) = manifests.get(&snapshot_id) {
+            update(&mut manifest);
+            manifests.insert(snapshot_id, manifest);
+        }
+    });
+}
+
+fn remove_export(snapshot_id: u64) {
+    EXPORT_MANIFESTS.with(|manifests| manifests.borrow_mut().remove(&snapshot_id));
+    EXPORT_CHUNKS.with(|chunks| {
+        let mut chunks = chunks.borrow_mut();
+        let keys: Vec<(u64, u8, u32)> = chunks
+            .range((snapshot_id, 0, 0)..(snapshot_id + 1, 0, 0))
+            .map(|(key, _)| key)
+            .collect();
+        for key in keys {
+            chunks.remove(&key);
+        }
+    });
+}
+
+// An upgrade loses the progress of the export being built, so its partial files are dropped
+fn discard_incomplete_exports() {
+    let incomplete: Vec<u64> = EXPORT_MANIFESTS.with(|manifests| {
+        manifests
+            .borrow()
+            .iter()
+            .filter(|(_, manifest)| !manifest.complete)
+            .map(|(id, _)| id)
+            .collect()
+    });
+    for snapshot_id in incomplete {
+        remove_export(snapshot_id);
+    }
+}
+
+// Chunks of one dataset are concatenated by the caller; chunk boundaries may fall inside a row
+#[ic_cdk::query]
+fn get_export_chunk(snapshot_id: u64, dataset: ExportDataset, index: u32) -> Result<ByteBuf, Error> {
+    ensure_controller()?;
+    if !load_export_manifest(snapshot_id)?.complete {
+        return Err(Error::InvalidInput {
+            msg: "The export is still being built".to_string(),
+        });
+    }
+    EXPORT_CHUNKS
+        .with(|chunks| chunks.borrow().get(&(snapshot_id, dataset as u8, index)))
+        .map(|chunk| ByteBuf::from(chunk.0))
+        .ok_or(Error::NotFound {
+            msg: format!("Chunk {} is out of range", index),
+        })
+}
+
+#[ic_cdk::query]
+fn get_export_manifests() -> Result<Vec<ExportManifest>, Error> {
+    ensure_controller()?;
+    Ok(EXPORT_MANIFESTS.with(|manifests| manifests.borrow().iter().map(|(_, manifest)| manifest).collect()))
+}
+
+// Also cancels the export if it is still being built
+#[ic_cdk::update]
+fn discard_export(snapshot_id: u64) -> Result<(), Error> {
+    observe_while_frozen("discard_export", || {
+        ensure_controller()?;
+        load_export_manifest(snapshot_id)?;
+        let building = WRITE_FREEZE.with(|freeze| {
+            matches!(*freeze.borrow(), Some(WriteFreeze::Export { snapshot_id: id }) if id == snapshot_id)
+        });
+        if building {
+            EXPORT_BUILD.with(|build| *build.borrow_mut() = None);
+            WRITE_FREEZE.with(|freeze| *freeze.borrow_mut() = None);
+        }
+        remove_export(snapshot_id);
+        Ok(())
+    })
+}
+
+fn csv_cell(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Null => String::new(),
+        serde_json::Value::String(text) => text.clone(),
+        value => value.to_string(),
+    }
+}
+
+// Writes a row as in RFC 4180, quoting cells that contain separators, quotes or line breaks
+fn write_csv_row(data: &mut Vec<u8>, cells: impl Iterator<Item = String>) {
+    let cells: Vec<String> = cells
+        .map(|cell| {
+            if cell.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", cell.replace('"', "\"\""))
+            } else {
+                cell
+            }
+        })
+        .collect();
+    data.extend_from_slice(cells.join(",").as_bytes());
+    data.extend_from_slice(b"\r\n");
+}
+
+// Parses a unit enum variant from its name, e.g. "Approved"
+fn parse_variant<S: serde::de::DeserializeOwned>(name: &str) -> Result<S, serde_json::Error> {
+    serde_json::from_value(serde_json::Value::String(name.to_string()))
+}
+
+// References from imported rows either name a row by its external reference, in this batch or an
+// earlier one, or point at a record created outside of imports by its id
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+enum ImportTarget {
+    ExternalRef(String),
+    Id(u64),
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct UserImport {
+    external_ref: String,
+    name: String,
+    role: UserRole,
+    principal: Principal,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct PlotImport {
+    external_ref: String,
+    farmer: ImportTarget,
+    plot: PlotPayload,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct ContractImport {
+    external_ref: String,
+    farmer: ImportTarget,
+    consumer: ImportTarget,
+    terms: String,
+    conditions: String,
+    payout_criteria: String,
+    plots: Vec<ImportTarget>,
+    crop_ids: Vec<u64>,
+    sum_insured: f64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct ImportBatch {
+    users: Vec<UserImport>,
+    plots: Vec<PlotImport>,
+    contracts: Vec<ContractImport>,
+}
+
+// CSV files with a header row; any of them may be empty. Columns:
+//   users: external_ref, name, role, principal
+//   plots: external_ref, farmer, name, soil_type, region_code, area_hectares, geometry
+//   contracts: external_ref, farmer, consumer, terms, conditions, payout_criteria, plots, crop_ids, sum_insured
+// References are external refs or `id:<id>`, lists are separated by `;` and geometries are
+// `lat lon` pairs separated by `;`, a single pair being a point
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct CsvImport {
+    users: String,
+    plots: String,
+    contracts: String,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+enum ImportPayload {
+    Records(ImportBatch),
+    Csv(CsvImport),
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+enum ImportKind {
+    User,
+    Plot,
+    Contract,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+enum ImportOutcome {
+    Created(u64),
+    Existing(u64), // Imported before under the same external reference and left untouched
+    Valid, // Would be created, but the batch was not applied
+    Invalid(String),
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct ImportRowResult {
+    kind: ImportKind,
+    row: u32, // Numbered from 1 within each list, not counting the CSV header
+    external_ref: String,
+    outcome: ImportOutcome,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct ImportReport {
+    applied: bool,
+    rows: Vec<ImportRowResult>,
+}
+
+// A record referenced during an import, either already stored or created by the batch itself
+#[derive(Clone, PartialEq)]
+enum ImportedId {
+    Stored(u64),
+    New(String),
+}
+
+struct PlannedPlot {
+    report_index: usize,
+    import: PlotImport,
+    farmer: ImportedId,
+    area_hectares: f64,
+}
+
+struct PlannedContract {
+    report_index: usize,
+    import: ContractImport,
+    farmer: ImportedId,
+    consumer: ImportedId,
+    plots: Vec<ImportedId>,
+}
+
+#[derive(Default)]
+struct ImportPlan {
+    users: Vec<(usize, UserImport)>,
+    plots: Vec<PlannedPlot>,
+    contracts: Vec<PlannedContract>,
+}
+
+// Onboards a cooperative in one go. Every row is validated before anything is written and the batch
+// is only applied when all rows are valid; rows imported before are reported as existing, so a
+// batch can be re-run safely
+#[ic_cdk::update]
+fn bulk_import(payload: ImportPayload, dry_run: bool) -> Result<ImportReport, Error> {
+    observe("bulk_import", || {
+        ensure_controller()?;
+        let (users, plots, contracts) = match payload {
+            ImportPayload::Records(batch) => (
+                batch.users.into_iter().map(Ok).collect(),
+                batch.plots.into_iter().map(Ok).collect(),
+                batch.contracts.into_iter().map(Ok).collect(),
+            ),
+            ImportPayload::Csv(csv) => (
+                parse_csv_rows(&csv.users, parse_user_row),
+                parse_csv_rows(&csv.plots, parse_plot_row),
+                parse_csv_rows(&csv.contracts, parse_contract_row),
+            ),
+        };
+        if users.len() + plots.len() + contracts.len() > MAX_IMPORT_ROWS {
+            return Err(Error::InvalidInput {
+                msg: format!("A batch can import at most {} rows", MAX_IMPORT_ROWS),
+            });
+        }
+
+        let (mut rows, plan) = plan_import(users, plots, contracts);
+        let valid = rows.iter().all(|row| !matches!(row.outcome, ImportOutcome::Invalid(_)));
+        if valid && !dry_run {
+            apply_import(plan, &mut rows);
+        }
+        Ok(ImportReport {
+            applied: valid && !dry_run,
+            rows,
+        })
+    })
+}
+
+fn plan_import(
+    users: Vec<Result<UserImport, String>>,
+    plots: Vec<Result<PlotImport, String>>,
+    contracts: Vec<Result<ContractImport, String>>,
+) -> (Vec<ImportRowResult>, ImportPlan) {
+    let mut rows = Vec::new();
+    let mut plan = ImportPlan::default();
+    let mut seen: std::collections::BTreeSet<(u8, String)> = Default::default();
+    let mut report = |kind: ImportKind, row: usize, external_ref: &str, outcome: ImportOutcome| {
+        rows.push(ImportRowResult {
+            kind,
+            row: row as u32 + 1,
+            external_ref: external_ref.to_string(),
+            outcome,
+        });
+        rows.len() - 1
+    };
+    // Returns the stored id of a row imported before, or an error for malformed or repeated references
+    let mut check_ref = |kind: ImportKind, external_ref: &str| -> Result<Option<u64>, String> {
+        if external_ref.is_empty() || external_ref.len() > MAX_EXTERNAL_REF_LENGTH {
+            return Err(format!(
+                "External references must be between 1 and {} characters",
+                MAX_EXTERNAL_REF_LENGTH
+            ));
+        }
+        if !seen.insert((kind as u8, external_ref.to_string())) {
+            return Err(format!("Duplicate external reference {}", external_ref));
+        }
+        Ok(stored_external_ref(kind, external_ref))
+    };
+
+    let mut new_users = std::collections::BTreeSet::new();
+    for (row, user) in users.into_iter().enumerate() {
+        let user = match user {
+            Ok(user) => user,
+            Err(msg) => {
+                report(ImportKind::User, row, "", ImportOutcome::Invalid(msg));
+                continue;
+            }
+        };
+        match check_ref(ImportKind::User, &user.external_ref) {
+            Err(msg) => {
+                report(ImportKind::User, row, &user.external_ref, ImportOutcome::Invalid(msg));
+            }
+            Ok(Some(id)) => {
+                report(ImportKind::User, row, &user.external_ref, ImportOutcome::Existing(id));
+            }
+            Ok(None) => {
+                let checked = if user.name.trim().is_empty() {
+                    Err("Names cannot be empty".to_string())
+                } else {
+                    validate_length("name", &user.name, MAX_USER_NAME_LENGTH).map_err(error_message)
+                };
+                match checked {
+                    Err(msg) => {
+                        report(ImportKind::User, row, &user.external_ref, ImportOutcome::Invalid(msg));
+                    }
+                    Ok(()) => {
+                        let index = report(ImportKind::User, row, &user.external_ref, ImportOutcome::Valid);
+                        new_users.insert(user.external_ref.clone());
+                        plan.users.push((index, user));
+                    }
+                }
+            }
+        }
+    }
+    let resolve_user = |target: &ImportTarget| -> Result<ImportedId, String> {
+        match target {
+            ImportTarget::Id(id) => read_user_profile(*id)
+                .map(|_| ImportedId::Stored(*id))
+                .map_err(error_message),
+            ImportTarget::ExternalRef(external_ref) => match stored_external_ref(ImportKind::User, external_ref) {
+                Some(id) => Ok(ImportedId::Stored(id)),
+                None if new_users.contains(external_ref) => Ok(ImportedId::New(external_ref.clone())),
+                None => Err(format!("Unknown user {}", external_ref)),
+            },
+        }
+    };
+
+    // Owners of the plots created by this batch, used to check the plots of imported contracts
+    let mut new_plots = BTreeMap::new();
+    for (row, plot) in plots.into_iter().enumerate() {
+        let plot = match plot {
+            Ok(plot) => plot,
+            Err(msg) => {
+                report(ImportKind::Plot, row, "", ImportOutcome::Invalid(msg));
+                continue;
+            }
+        };
+        let checked = check_ref(ImportKind::Plot, &plot.external_ref).and_then(|existing| match existing {
+            Some(id) => Ok(Err(id)),
+            None => {
+                let farmer = resolve_user(&plot.farmer)?;
+                let area_hectares = validate_plot_payload(&plot.plot).map_err(error_message)?;
+                Ok(Ok((farmer, area_hectares)))
+            }
+        });
+        match checked {
+            Err(msg) => {
+                report(ImportKind::Plot, row, &plot.external_ref, ImportOutcome::Invalid(msg));
+            }
+            Ok(Err(id)) => {
+                report(ImportKind::Plot, row, &plot.external_ref, ImportOutcome::Existing(id));
+            }
+            Ok(Ok((farmer, area_hectares))) => {
+                let report_index = report(ImportKind::Plot, row, &plot.external_ref, ImportOutcome::Valid);
+                new_plots.insert(plot.external_ref.clone(), farmer.clone());
+                plan.plots.push(PlannedPlot {
+                    report_index,
+                    import: plot,
+                    farmer,
+                    area_hectares,
+                });
+            }
+        }
+    }
+    // Resolves a plot together with its owner
+    let resolve_plot = |target: &ImportTarget| -> Result<(ImportedId, ImportedId), String> {
+        let stored = match target {
+            ImportTarget::Id(id) => *id,
+            ImportTarget::ExternalRef(external_ref) => match stored_external_ref(ImportKind::Plot, external_ref) {
+                Some(id) => id,
+                None => {
+                    return new_plots
+                        .get(external_ref)
+                        .map(|farmer| (ImportedId::New(external_ref.clone()), farmer.clone()))
+                        .ok_or(format!("Unknown plot {}", external_ref))
+                }
+            },
+        };
+        let plot = load_plot(stored).map_err(error_message)?;
+        Ok((ImportedId::Stored(stored), ImportedId::Stored(plot.farmer_id)))
+    };
+
+    for (row, contract) in contracts.into_iter().enumerate() {
+        let contract = match contract {
+            Ok(contract) => contract,
+            Err(msg) => {
+                report(ImportKind::Contract, row, "", ImportOutcome::Invalid(msg));
+                continue;
+            }
+        };
+        let checked = check_ref(ImportKind::Contract, &contract.external_ref).and_then(|existing| match existing {
+            Some(id) => Ok(Err(id)),
+            None => {
+                validate_contract_fields(
+                    &contract.terms,
+                    &contract.conditions,
+                    &contract.payout_criteria,
+                    contract.plots.len(),
+                    contract.crop_ids.len(),
+                )
+                .map_err(error_message)?;
+                let farmer = resolve_user(&contract.farmer)?;
+                let consumer = resolve_user(&contract.consumer)?;
+                let mut plots = Vec::new();
+                for target in &contract.plots {
+                    let (plot, owner) = resolve_plot(target)?;
+                    if owner != farmer {
+                        return Err("Contracts can only insure plots of their farmer".to_string());
+                    }
+                    plots.push(plot);
+                }
+                validate_crop_ids(&contract.crop_ids).map_err(error_message)?;
+                validate_stake_amount(contract.sum_insured).map_err(error_message)?;
+                Ok(Ok((farmer, consumer, plots)))
+            }
+        });
+        match checked {
+            Err(msg) => {
+                report(ImportKind::Contract, row, &contract.external_ref, ImportOutcome::Invalid(msg));
+            }
+            Ok(Err(id)) => {
+                report(ImportKind::Contract, row, &contract.external_ref, ImportOutcome::Existing(id));
+            }
+            Ok(Ok((farmer, consumer, plots))) => {
+                let report_index = report(ImportKind::Contract, row, &contract.external_ref, ImportOutcome::Valid);
+                plan.contracts.push(PlannedContract {
+                    report_index,
+                    import: contract,
+                    farmer,
+                    consumer,
+                    plots,
+                });
+            }
+        }
+    }
+    (rows, plan)
+}
+
+// Writes a fully validated plan; nothing in here can fail, so a batch is applied either completely or not at all
+fn apply_import(plan: ImportPlan, rows: &mut [ImportRowResult]) {
+    let next_id = || {
+        ID_COUNTER.with(|c| {
+            let current_value = *c.borrow().get();
+            c.borrow_mut().set(current_value + 1).unwrap();
+            current_value
+        })
+    };
+    // Ids of the records created so far, keyed by kind and external reference
+    let mut created = BTreeMap::new();
+    let resolve = |kind: ImportKind, id: &ImportedId, created: &BTreeMap<(u8, String), u64>| match id {
+        ImportedId::Stored(id) => *id,
+        ImportedId::New(external_ref) => created[&(kind as u8, external_ref.clone())],
+    };
+    let mut register = |created: &mut BTreeMap<_, _>, kind: ImportKind, external_ref: String, id: u64, report_index: usize| {
+        EXTERNAL_REFS.with(|refs| refs.borrow_mut().insert(external_ref_key(kind, &external_ref), id));
+        rows[report_index].outcome = ImportOutcome::Created(id);
+        created.insert((kind as u8, external_ref), id);
+    };
+
+    for (report_index, user) in plan.users {
+        let profile = UserProfile {
+            id: next_id(),
+            name: user.name,
+            role: user.role,
+            stake_in_dao: 0.0,
+            principal: user.principal,
+        };
+        store_user(&profile);
+        record_event(EventKind::UserCreated {
+            user_id: profile.id,
+            role: profile.role.clone(),
+        });
+        register(&mut created, ImportKind::User, user.external_ref, profile.id, report_index);
+    }
+
+    for planned in plan.plots {
+        let payload = planned.import.plot;
+        let plot = Plot {
+            id: next_id(),
+            farmer_id: resolve(ImportKind::User, &planned.farmer, &created),
+            name: payload.name,
+            geometry: payload.geometry,
+            area_hectares: planned.area_hectares,
+            soil_type: payload.soil_type,
+            region_code: payload.region_code,
+            plantings: Vec::new(),
+            created_at: time(),
+        };
+        PLOTS.with(|plots| plots.borrow_mut().insert(plot.id, plot.clone()));
+        register(&mut created, ImportKind::Plot, planned.import.external_ref, plot.id, planned.report_index);
+    }
+
+    for planned in plan.contracts {
+        let import = planned.import;
+        let contract = InsuranceContract {
+            id: next_id(),
+            farmer_id: resolve(ImportKind::User, &planned.farmer, &created),
+            consumer_id: resolve(ImportKind::User, &planned.consumer, &created),
+            terms: import.terms,
+            conditions: import.conditions,
+            payout_criteria: import.payout_criteria,
+            status: ContractStatus::Pending,
+            activated_at: None,
+            plot_ids: planned
+                .plots
+                .iter()
+                .map(|plot| resolve(ImportKind::Plot, plot, &created))
+                .collect(),
+            crop_ids: import.crop_ids,
+            sum_insured: import.sum_insured,
+        };
+        store_contract(&contract);
+        record_event(EventKind::ContractCreated {
+            contract_id: contract.id,
+            farmer_id: contract.farmer_id,
+            consumer_id: contract.consumer_id,
+        });
+        register(&mut created, ImportKind::Contract, import.external_ref, contract.id, planned.report_index);
+    }
+}
+
+fn external_ref_key(kind: ImportKind, external_ref: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([kind as u8]);
+    hasher.update(external_ref.as_bytes());
+    hasher.finalize().into()
+}
+
+fn stored_external_ref(kind: ImportKind, external_ref: &str) -> Option<u64> {
+    EXTERNAL_REFS.with(|refs| refs.borrow().get(&external_ref_key(kind, external_ref)))
+}
+
+// Parses a CSV file with a header row; rows that cannot be parsed are reported with the reason
+fn parse_csv_rows<T>(text: &str, parse_row: fn(&CsvRow) -> Result<T, String>) -> Vec<Result<T, String>> {
+    let mut records = parse_csv(text).into_iter();
+    let Some(header) = records.next() else {
+        return Vec::new();
+    };
+    records
+        .map(|cells| {
+            if cells.len() != header.len() {
+                return Err(format!("Expected {} columns but found {}", header.len(), cells.len()));
+            }
+            parse_row(&header.iter().cloned().zip(cells).collect())
+        })
+        .collect()
+}
+
+// Splits RFC 4180 CSV into records of cells, skipping blank lines
+fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                cell.push('"');
+                chars.next();
+            }
+            (true, '"') => quoted = false,
+            (true, c) => cell.push(c),
+            (false, '"') => quoted = true,
+            (false, ',') => record.push(std::mem::take(&mut cell)),
+            (false, '\r') => {}
+            (false, '\n') => {
+                record.push(std::mem::take(&mut cell));
+                if record.iter().any(|cell| !cell.is_empty()) {
+                    records.push(std::mem::take(&mut record));
+                } else {
+                    record.clear();
+                }
+            }
+            (false, c) => cell.push(c),
+        }
+    }
+    record.push(cell);
+    if record.iter().any(|cell| !cell.is_empty()) {
+        records.push(record);
+    }
+    records
+}
+
+fn csv_field<'a>(row: &'a CsvRow, column: &str) -> Result<&'a str, String> {
+    row.get(column)
+        .map(|value| value.trim())
+        .ok_or(format!("Missing column {}", column))
+}
+
+fn csv_target(value: &str) -> Result<ImportTarget, String> {
+    match value.strip_prefix("id:") {
+        Some(id) => id.parse().map(ImportTarget::Id).map_err(|_| format!("Invalid id {}", id)),
+        None if value.is_empty() => Err("References cannot be empty".to_string()),
+        None => Ok(ImportTarget::ExternalRef(value.to_string())),
+    }
+}
+
+fn csv_list(value: &str) -> impl Iterator<Item = &str> {
+    value.split(';').map(str::trim).filter(|item| !item.is_empty())
+}
+
+fn csv_number(value: &str, column: &str) -> Result<f64, String> {
+    value.parse().map_err(|_| format!("{} must be a number", column))
+}
+
+fn parse_user_row(row: &CsvRow) -> Result<UserImport, String> {
+    let role = csv_field(row, "role")?;
+    let principal = csv_field(row, "principal")?;
+    Ok(UserImport {
+        external_ref: csv_field(row, "external_ref")?.to_string(),
+        name: csv_field(row, "name")?.to_string(),
+        role: parse_variant(role).map_err(|_| format!("Unknown role {}", role))?,
+        principal: Principal::from_text(principal).map_err(|_| format!("Invalid principal {}", principal))?,
+    })
+}
+
+fn parse_plot_row(row: &CsvRow) -> Result<PlotImport, String> {
+    let soil_type = csv_field(row, "soil_type")?;
+    let area_hectares = csv_field(row, "area_hectares")?;
+    let points = csv_list(csv_field(row, "geometry")?)
+        .map(|pair| match pair.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [lat, lon] => Ok(GeoPoint {
+                lat: csv_number(lat, "Latitudes")?,
+                lon: csv_number(lon, "Longitudes")?,
+            }),
+            _ => Err(format!("Invalid coordinates {}", pair)),
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let geometry = match points.as_slice() {
+        [] => return Err("The geometry cannot be empty".to_string()),
+        [point] => PlotGeometry::Point(*point),
+        _ => PlotGeometry::Polygon(points),
+    };
+    Ok(PlotImport {
+        external_ref: csv_field(row, "external_ref")?.to_string(),
+        farmer: csv_target(csv_field(row, "farmer")?)?,
+        plot: PlotPayload {
+            name: csv_field(row, "name")?.to_string(),
+            geometry,
+            area_hectares: match area_hectares {
+                "" => None,
+                area => Some(csv_number(area, "area_hectares")?),
+            },
+            soil_type: parse_variant(soil_type).map_err(|_| format!("Unknown soil type {}", soil_type))?,
+            region_code: csv_field(row, "region_code")?.to_string(),
+        },
+    })
+}
+
+fn parse_contract_row(row: &CsvRow) -> Result<ContractImport, String> {
+    Ok(ContractImport {
+        external_ref: csv_field(row, "external_ref")?.to_string(),
+        farmer: csv_target(csv_field(row, "farmer")?)?,
+        consumer: csv_target(csv_field(row, "consumer")?)?,
+        terms: csv_field(row, "terms")?.to_string(),
+        conditions: csv_field(row, "conditions")?.to_string(),
+        payout_criteria: csv_field(row, "payout_criteria")?.to_string(),
+        plots: csv_list(csv_field(row, "plots")?).map(csv_target).collect::<Result<_, _>>()?,
+        crop_ids: csv_list(csv_field(row, "crop_ids")?)
+            .map(|id| id.parse().map_err(|_| format!("Invalid crop id {}", id)))
+            .collect::<Result<_, String>>()?,
+        sum_insured: csv_number(csv_field(row, "sum_insured")?, "sum_insured")?,
+    })
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct BackupMemory {
+    memory_id: u8,
+    size: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct BackupManifest {
+    version: u32,
+    created_at: u64,
+    event_seq: u64, // Last event recorded before the backup was taken
+    size: u64,
+    chunk_count: u32,
+    sha256: ByteBuf, // Checksum of everything after the header, also stored in the header
+    memories: Vec<BackupMemory>,
+    complete: bool,          // Set once the checksum is computed and chunks can be downloaded
+    expires_at: Option<u64>, // End of the download window; None for uploaded backups
+}
+
+// Chunks are read straight from stable memory, which stays unchanged while writes are suspended
+struct Backup {
+    manifest: BackupManifest,
+    header: Vec<u8>, // Header and memory table, known once the checksum is
+    hasher: Sha256,
+    memory: usize, // Index into manifest.memories of the memory being hashed
+    offset: u64,
+}
+
+// The memories' bytes are written into place as they arrive, so only the header is held on the heap
+struct RestoreUpload {
+    size: u64,
+    received: u64,
+    header: Vec<u8>,                  // Header and memory table, buffered until complete
+    manifest: Option<BackupManifest>, // Parsed once the memory table has been uploaded
+    hasher: Sha256,
+}
+
+// Starts a backup of every stable memory in use, downloaded as a single file with get_backup_chunk.
+// Writes are suspended until the backup is discarded or BACKUP_WINDOW_NS has passed, and the
+// checksum is computed over as many calls as it takes before the first chunk can be fetched.
+// Layout: an 8 byte magic and little-endian version (u32), creation time (u64), event seq (u64),
+// memory count (u32) and SHA-256 of the rest, zero padded to BACKUP_HEADER_SIZE; then an entry of
+// memory id (u32) and size (u64) per memory, followed by the memories' bytes in the same order
+#[ic_cdk::update]
+fn begin_backup() -> Result<BackupManifest, Error> {
+    observe("begin_backup", || {
+        ensure_controller()?;
+        let memories: Vec<BackupMemory> = (0..MEMORY_ID_COUNT)
+            .map(|id| BackupMemory {
+                memory_id: id,
+                size: virtual_memory(id).size() * WASM_PAGE_SIZE,
+            })
+            .collect();
+        let table_end = (BACKUP_HEADER_SIZE + memories.len() * 12) as u64;
+        let size = table_end + memories.iter().map(|memory| memory.size).sum::<u64>();
+        let created_at = time();
+        let manifest = BackupManifest {
+            version: BACKUP_FORMAT_VERSION,
+            created_at,
+            event_seq: EVENT_LOG.with(|log| log.borrow().last_key_value().map_or(0, |(seq, _)| seq)),
+            size,
+            chunk_count: size.div_ceil(BACKUP_CHUNK_SIZE as u64) as u32,
+            sha256: ByteBuf::new(),
+            memories,
+            complete: false,
+            expires_at: Some(created_at + BACKUP_WINDOW_NS),
+        };
+
+        let mut hasher = Sha256::new();
+        hasher.update(backup_memory_table(&manifest.memories));
+        BACKUP.with(|backup| {
+            *backup.borrow_mut() = Some(Backup {
+                manifest,
+                header: Vec::new(),
+                hasher,
+                memory: 0,
+                offset: 0,
+            })
+        });
+        WRITE_FREEZE.with(|freeze| {
+            *freeze.borrow_mut() = Some(WriteFreeze::Backup {
+                expires_at: created_at + BACKUP_WINDOW_NS,
+            })
+        });
+
+        continue_backup();
+        load_backup_manifest()
+    })
+}
+
+// Hashes the memories for as long as the instruction budget of the call lasts and schedules
+// another call until the checksum is known
+fn continue_backup() {
+    let hashed = BACKUP.with(|backup| {
+        let mut backup = backup.borrow_mut();
+        let Some(backup) = backup.as_mut().filter(|backup| !backup.manifest.complete) else {
+            return true;
+        };
+        while let Some(entry) = backup.manifest.memories.get(backup.memory) {
+            if instruction_counter() > BACKUP_STEP_INSTRUCTIONS {
+                return false;
+            }
+            let mut bytes = vec![0; (entry.size - backup.offset).min(BACKUP_CHUNK_SIZE as u64) as usize];
+            virtual_memory(entry.memory_id).read(backup.offset, &mut bytes);
+            backup.hasher.update(&bytes);
+            backup.offset += bytes.len() as u64;
+            if backup.offset == entry.size {
+                backup.memory += 1;
+                backup.offset = 0;
+            }
+        }
+
+        backup.manifest.sha256 = ByteBuf::from(std::mem::take(&mut backup.hasher).finalize().to_vec());
+        backup.manifest.complete = true;
+        backup.header = backup_header(&backup.manifest);
+        true
+    });
+    if !hashed {
+        set_timer(Duration::ZERO, continue_backup);
+    }
+}
+
+fn backup_header(manifest: &BackupManifest) -> Vec<u8> {
+    let mut header = Vec::with_capacity(BACKUP_HEADER_SIZE + manifest.memories.len() * 12);
+    header.extend_from_slice(BACKUP_MAGIC);
+    header.extend_from_slice(&manifest.version.to_le_bytes());
+    header.extend_from_slice(&manifest.created_at.to_le_bytes());
+    header.extend_from_slice(&manifest.event_seq.to_le_bytes());
+    header.extend_from_slice(&(manifest.memories.len() as u32).to_le_bytes());
+    header.extend_from_slice(&manifest.sha256);
+    header.resize(BACKUP_HEADER_SIZE, 0);
+    header.extend(backup_memory_table(&manifest.memories));
+    header
+}
+
+fn backup_memory_table(memories: &[BackupMemory]) -> Vec<u8> {
+    let mut table = Vec::with_capacity(memories.len() * 12);
+    for memory in memories {
+        table.extend_from_slice(&u32::from(memory.memory_id).to_le_bytes());
+        table.extend_from_slice(&memory.size.to_le_bytes());
+    }
+    table
+}
+
+fn load_backup_manifest() -> Result<BackupManifest, Error> {
+    BACKUP
+        .with(|backup| backup.borrow().as_ref().map(|backup| backup.manifest.clone()))
+        .ok_or(Error::NotFound {
+            msg: "No backup has been taken".to_string(),
+        })
+}
+
+#[ic_cdk::query]
+fn get_backup_manifest() -> Result<BackupManifest, Error> {
+    ensure_controller()?;
+    load_backup_manifest()
+}
+
+#[ic_cdk::query]
+fn get_backup_chunk(index: u32) -> Result<ByteBuf, Error> {
+    ensure_controller()?;
+    BACKUP.with(|backup| {
+        let backup = backup.borrow();
+        let backup = backup.as_ref().ok_or(Error::NotFound {
+            msg: "No backup has been taken".to_string(),
+        })?;
+        if !backup.manifest.complete {
+            return Err(Error::InvalidInput {
+                msg: "The checksum of the backup is still being computed".to_string(),
+            });
+        }
+        if backup.manifest.expires_at.is_some_and(|at| time() >= at) {
+            return Err(Error::InvalidInput {
+                msg: "The download window of the backup has closed, take a new one".to_string(),
+            });
+        }
+        if index >= backup.manifest.chunk_count {
+            return Err(Error::NotFound {
+                msg: format!("Chunk {} is out of range", index),
+            });
+        }
+        let start = index as u64 * BACKUP_CHUNK_SIZE as u64;
+        let end = (start + BACKUP_CHUNK_SIZE as u64).min(backup.manifest.size);
+        Ok(ByteBuf::from(read_backup_range(backup, start, end)))
+    })
+}
+
+// Reads bytes of the backup file, taking the memories' bytes from stable memory
+fn read_backup_range(backup: &Backup, start: u64, end: u64) -> Vec<u8> {
+    let mut data = Vec::with_capacity((end - start) as usize);
+    let header_size = backup.header.len() as u64;
+    if start < header_size {
+        data.extend_from_slice(&backup.header[start as usize..end.min(header_size) as usize]);
+    }
+    let mut memory_start = header_size;
+    for entry in &backup.manifest.memories {
+        let memory_end = memory_start + entry.size;
+        let from = start.max(memory_start);
+        let to = end.min(memory_end);
+        if from < to {
+            let mut bytes = vec![0; (to - from) as usize];
+            virtual_memory(entry.memory_id).read(from - memory_start, &mut bytes);
+            data.extend_from_slice(&bytes);
+        }
+        memory_start = memory_end;
+    }
+    data
+}
+
+// Also lifts the write suspension of the backup
+#[ic_cdk::update]
+fn discard_backup() -> Result<(), Error> {
+    observe_while_frozen("discard_backup", || {
+        ensure_controller()?;
+        BACKUP.with(|backup| backup.borrow_mut().take()).ok_or(Error::NotFound {
+            msg: "No backup has been taken".to_string(),
+        })?;
+        WRITE_FREEZE.with(|freeze| {
+            let mut freeze = freeze.borrow_mut();
+            if matches!(*freeze, Some(WriteFreeze::Backup { .. })) {
+                *freeze = None;
+            }
+        });
+        Ok(())
+    })
+}
+
+// Restores only go into a freshly installed canister, so no record can be lost by overwriting it.
+// Writes are suspended until the restore is committed; starting again drops the bytes uploaded so far
+#[ic_cdk::update]
+fn begin_restore(size: u64) -> Result<(), Error> {
+    observe_while_frozen("begin_restore", || {
+        ensure_controller()?;
+        ensure_fresh_canister()?;
+        if RESTORE_UPLOAD.with(|upload| upload.borrow().is_none()) {
+            ensure_writable();
+        }
+        if size < BACKUP_HEADER_SIZE as u64 {
+            return Err(Error::InvalidInput {
+                msg: "The backup is too small to contain a header".to_string(),
+            });
+        }
+        RESTORE_UPLOAD.with(|upload| {
+            *upload.borrow_mut() = Some(RestoreUpload {
+                size,
+                received: 0,
+                header: Vec::new(),
+                manifest: None,
+                hasher: Sha256::new(),
+            })
+        });
+        WRITE_FREEZE.with(|freeze| *freeze.borrow_mut() = Some(WriteFreeze::Restore));
+        Ok(())
+    })
+}
+
+// Chunks are uploaded in order, with the same size as the ones handed out by get_backup_chunk
+#[ic_cdk::update]
+fn upload_restore_chunk(index: u32, data: ByteBuf) -> Result<(), Error> {
+    observe_while_frozen("upload_restore_chunk", || {
+        ensure_controller()?;
+        RESTORE_UPLOAD.with(|slot| {
+            let mut slot = slot.borrow_mut();
+            let upload = slot.as_mut().ok_or(Error::NotFound {
+                msg: "No restore is in progress".to_string(),
+            })?;
+            let expected = upload.received / BACKUP_CHUNK_SIZE as u64;
+            if upload.received % BACKUP_CHUNK_SIZE as u64 != 0 || index as u64 != expected {
+                return Err(Error::InvalidInput {
+                    msg: format!("Expected chunk {}", expected),
+                });
+            }
+            if data.len() > BACKUP_CHUNK_SIZE || upload.received + data.len() as u64 > upload.size {
+                return Err(Error::InvalidInput {
+                    msg: "The chunk does not fit the announced backup size".to_string(),
+                });
+            }
+            let written = write_restore_bytes(upload, &data);
+            // Only the header can be rejected, before anything has been written to the memories
+            if written.is_err() {
+                *slot = None;
+                WRITE_FREEZE.with(|freeze| *freeze.borrow_mut() = None);
+            }
+            written
+        })
+    })
+}
+
+// Buffers the header and memory table, then writes the memories' bytes into place. Everything
+// after the header is hashed on the way
+fn write_restore_bytes(upload: &mut RestoreUpload, mut data: &[u8]) -> Result<(), Error> {
+    loop {
+        let Some(manifest) = &upload.manifest else {
+            let table_end = match upload.header.len() < BACKUP_HEADER_SIZE {
+                true => BACKUP_HEADER_SIZE,
+                false => backup_table_end(&upload.header)?,
+            };
+            if upload.header.len() == table_end {
+                upload.manifest = Some(read_backup_header(&upload.header, upload.size)?);
+                continue;
+            }
+            if data.is_empty() {
+                return Ok(());
+            }
+            let (bytes, rest) = data.split_at((table_end - upload.header.len()).min(data.len()));
+            if upload.header.len() >= BACKUP_HEADER_SIZE {
+                upload.hasher.update(bytes);
+            }
+            upload.header.extend_from_slice(bytes);
+            upload.received += bytes.len() as u64;
+            data = rest;
+            continue;
+        };
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        // The announced size matches the memory table, so every byte left belongs to a memory
+        let mut start = upload.header.len() as u64;
+        let entry = manifest
+            .memories
+            .iter()
+            .find(|entry| {
+                let found = upload.received < start + entry.size;
+                if !found {
+                    start += entry.size;
+                }
+                found
+            })
+            .expect("The memory table covers the announced size");
+        let offset = upload.received - start;
+        let (bytes, rest) = data.split_at(((entry.size - offset) as usize).min(data.len()));
+        let memory = virtual_memory(entry.memory_id);
+        let pages = (offset + bytes.len() as u64).div_ceil(WASM_PAGE_SIZE);
+        if pages > memory.size() && memory.grow(pages - memory.size()) < 0 {
+            ic_cdk::trap("Cannot grow stable memory to restore the backup");
+        }
+        memory.write(offset, bytes);
+        upload.hasher.update(bytes);
+        upload.received += bytes.len() as u64;
+        data = rest;
+    }
+}
+
+// Verifies the checksum of the uploaded backup and reloads the stores from the restored memories.
+// A backup that fails verification is wiped again, leaving the canister as freshly installed
+#[ic_cdk::update]
+fn commit_restore() -> Result<BackupManifest, Error> {
+    observe_while_frozen("commit_restore", || {
+        ensure_controller()?;
+        ensure_fresh_canister()?;
+        RESTORE_UPLOAD.with(|upload| match upload.borrow().as_ref() {
+            Some(upload) if upload.received == upload.size => Ok(()),
+            Some(upload) => Err(Error::InvalidInput {
+                msg: format!("Only {} of {} bytes have been uploaded", upload.received, upload.size),
+            }),
+            None => Err(Error::NotFound {
+                msg: "No restore is in progress".to_string(),
+            }),
+        })?;
+        let upload = RESTORE_UPLOAD
+            .with(|upload| upload.borrow_mut().take())
+            .expect("The upload was checked above");
+        WRITE_FREEZE.with(|freeze| *freeze.borrow_mut() = None);
+        let manifest = upload.manifest.expect("The header is part of every complete upload");
+
+        if upload.hasher.finalize().as_slice() != manifest.sha256.as_slice() {
+            for id in 0..MEMORY_ID_COUNT {
+                clear_memory_from(id, 0);
+            }
+            reload_stable_structures();
+            return Err(Error::InvalidInput {
+                msg: "The checksum of the backup does not match, the restore has to be started again".to_string(),
+            });
+        }
+        // Memories missing from backups of older versions are restored as empty, and anything the
+        // fresh canister wrote beyond the restored bytes is cleared
+        for id in 0..MEMORY_ID_COUNT {
+            let entry = manifest.memories.iter().find(|entry| entry.memory_id == id);
+            clear_memory_from(id, entry.map_or(0, |entry| entry.size));
+        }
+
+        reload_stable_structures();
+        migrate_stored_state();
+        restore_heap_state();
+        Ok(manifest)
+    })
+}
+
+fn clear_memory_from(id: u8, offset: u64) {
+    let memory = virtual_memory(id);
+    let stale = (memory.size() * WASM_PAGE_SIZE).saturating_sub(offset);
+    memory.write(offset, &vec![0; stale as usize]);
+}
+
+// Validates the fixed size header of a backup and returns where its memory table ends
+fn backup_table_end(header: &[u8]) -> Result<usize, Error> {
+    let invalid = |msg: &str| Error::InvalidInput { msg: msg.to_string() };
+    if header.len() < BACKUP_HEADER_SIZE || &header[..8] != BACKUP_MAGIC {
+        return Err(invalid("The file is not a backup"));
+    }
+    let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+    if version != BACKUP_FORMAT_VERSION {
+        return Err(invalid(&format!("Unsupported backup version {}", version)));
+    }
+    let memory_count = u32::from_le_bytes(header[28..32].try_into().unwrap());
+    if memory_count > u32::from(MEMORY_ID_COUNT) {
+        return Err(invalid("The backup contains memories this version does not know about"));
+    }
+    Ok(BACKUP_HEADER_SIZE + memory_count as usize * 12)
+}
+
+// Parses the header and memory table of a backup of the given size. The checksum it carries is
+// verified against the bytes after the header once they have all been read
+fn read_backup_header(header: &[u8], size: u64) -> Result<BackupManifest, Error> {
+    let invalid = |msg: &str| Error::InvalidInput { msg: msg.to_string() };
+    let table_end = backup_table_end(header)?;
+    if header.len() < table_end {
+        return Err(invalid("The memory table is truncated"));
+    }
+    let u32_at = |offset: usize| u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap());
+    let u64_at = |offset: usize| u64::from_le_bytes(header[offset..offset + 8].try_into().unwrap());
+
+    let mut memories: Vec<BackupMemory> = Vec::new();
+    for offset in (BACKUP_HEADER_SIZE..table_end).step_by(12) {
+        let memory_id = u8::try_from(u32_at(offset)).map_err(|_| invalid("Invalid memory id"))?;
+        if memory_id >= MEMORY_ID_COUNT {
+            return Err(invalid("The backup contains memories this version does not know about"));
+        }
+        if memories.iter().any(|memory| memory.memory_id == memory_id) {
+            return Err(invalid("The backup contains a memory twice"));
+        }
+        memories.push(BackupMemory {
+            memory_id,
+            size: u64_at(offset + 4),
+        });
+    }
+    let memories_size = memories.iter().try_fold(0u64, |total, memory| total.checked_add(memory.size));
+    if memories_size.and_then(|total| total.checked_add(table_end as u64)) != Some(size) {
+        return Err(invalid("The memory sizes do not match the size of the backup"));
+    }
+
+    Ok(BackupManifest {
+        version: u32_at(8),
+        created_at: u64_at(12),
+        event_seq: u64_at(20),
+        size,
+        chunk_count: size.div_ceil(BACKUP_CHUNK_SIZE as u64) as u32,
+        sha256: ByteBuf::from(header[32..64].to_vec()),
+        memories,
+        complete: true,
+        expires_at: None,
+    })
+}
+
+fn ensure_fresh_canister() -> Result<(), Error> {
+    let allocated_ids = ID_COUNTER.with(|counter| *counter.borrow().get());
+    if allocated_ids > 0 || EVENT_LOG.with(|log| !log.borrow().is_empty()) {
+        return Err(Error::InvalidInput {
+            msg: "Backups can only be restored into a freshly installed canister".to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn virtual_memory(id: u8) -> Memory {
+    MEMORY_MANAGER.with(|manager| manager.borrow().get(MemoryId::new(id)))
+}
+
+// The stores cache their headers on the heap, so they are initialised again after their memory
+// has been overwritten
+fn reload_stable_structures() {
+    ID_COUNTER.with(|cell| {
+        *cell.borrow_mut() = IdCell::init(virtual_memory(0), 0).expect("Cannot restore the id counter")
+    });
+    USER_PROFILES.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(1)));
+    TRANSACTION_RECORDS.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(2)));
+    INSURANCE_CONTRACTS.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(3)));
+    GOVERNANCE_PROPOSALS.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(4)));
+    STAKE_ADJUSTMENTS.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(5)));
+    INSURANCE_CLAIMS.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(6)));
+    DISPUTE_STORAGE.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(7)));
+    DISPUTE_HISTORY_STORAGE.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(8)));
+    GOVERNANCE_CONFIG.with(|cell| {
+        *cell.borrow_mut() = Cell::init(virtual_memory(9), GovernanceConfig::default()).expect("Cannot restore the governance config")
+    });
+    STAKE_ACCOUNTS.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(10)));
+    STAKING_CONFIG.with(|cell| {
+        *cell.borrow_mut() = Cell::init(virtual_memory(11), StakingConfig::default()).expect("Cannot restore the staking config")
+    });
+    REPUTATION_RECORDS.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(12)));
+    REPUTATION_CONFIG.with(|cell| {
+        *cell.borrow_mut() = Cell::init(virtual_memory(13), ReputationConfig::default()).expect("Cannot restore the reputation config")
+    });
+    ATTESTATIONS.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(14)));
+    SLASHES.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(15)));
+    SLASHING_SCHEDULE.with(|cell| {
+        *cell.borrow_mut() = Cell::init(virtual_memory(16), SlashingSchedule::default()).expect("Cannot restore the slashing schedule")
+    });
+    TREASURY.with(|cell| {
+        *cell.borrow_mut() = Cell::init(virtual_memory(17), Treasury::default()).expect("Cannot restore the treasury")
+    });
+    CLAIM_HISTORY_EPOCHS.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(18)));
+    ARBITRATORS.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(19)));
+    DISPUTE_EVIDENCE.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(20)));
+    DISPUTE_STATEMENTS.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(21)));
+    JURY_ROUNDS.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(22)));
+    JURY_VOTES.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(23)));
+    UPLOAD_SESSIONS.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(24)));
+    UPLOAD_CHUNKS.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(25)));
+    BLOB_METAS.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(26)));
+    BLOB_CHUNKS.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(27)));
+    BLOB_USAGE.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(28)));
+    PLOTS.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(29)));
+    CROPS.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(30)));
+    ASSESSMENTS.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(31)));
+    WEATHER_OBSERVATIONS.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(32)));
+    FRAUD_REPORTS.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(33)));
+    EVENT_LOG.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(34)));
+    EXTERNAL_REFS.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(35)));
+    VOTING_RECORDS.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(36)));
+    USER_TRANSACTIONS.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(37)));
+    STORED_STATE_VERSION.with(|cell| {
+        *cell.borrow_mut() = Cell::init(virtual_memory(38), 0).expect("Cannot restore the state version")
+    });
+    BLOB_HOLDERS.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(39)));
+    EXPORT_MANIFESTS.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(40)));
+    EXPORT_CHUNKS.with(|map| *map.borrow_mut() = StableBTreeMap::init(virtual_memory(41)));
+}
+
+// Records as stored by any version before state version 1, starting with the first release. Fields
+// added since then are optional, and so are the inline transactions and votes that were dropped
+// later, so that every layout can be read back. Candid skips undeclared fields and reads a value of
+// another type into an optional field as null
+#[derive(candid::CandidType, Deserialize)]
+struct LegacyUserProfile {
+    id: u64,
+    name: String,
+    role: UserRole,
+    transaction_history: Option<Vec<TransactionRecord>>,
+    stake_in_dao: f64,
+    principal: Option<Principal>,
+}
+
+#[derive(candid::CandidType, Deserialize)]
+struct LegacyInsuranceContract {
+    id: u64,
+    farmer_id: u64,
+    consumer_id: u64,
+    terms: String,
+    conditions: String,
+    payout_criteria: String,
+    status: Option<ContractStatus>,
+    activated_at: Option<u64>,
+    plot_ids: Option<Vec<u64>>,
+    crop_ids: Option<Vec<u64>>,
+    sum_insured: Option<f64>,
+}
+
+#[derive(candid::CandidType, Deserialize)]
+struct LegacyGovernanceProposal {
+    id: u64,
+    proposal_details: String,
+    proposer_id: u64,
+    voting_records: Option<Vec<LegacyVotingRecord>>,
+    status: Option<ProposalStatus>,
+    deposit: Option<f64>,
+    deposit_status: Option<DepositStatus>,
+    created_at: Option<u64>,
+    voting_deadline: Option<u64>,
+    action: Option<ProposalAction>,
+    executable_at: Option<u64>,
+    execution_error: Option<String>,
+}
+
+#[derive(candid::CandidType, Deserialize)]
+struct LegacyVotingRecord {
+    user_id: u64,
+    vote: VoteType,
+    stake: f64,
+}
+
+// Causes that no longer exist, like the early Manual one, are read as null
+#[derive(candid::CandidType, Deserialize)]
+struct LegacyStakeAdjustment {
+    id: Option<u64>,
+    user_id: u64,
+    old_stake: f64,
+    new_stake: f64,
+    reason: String,
+    cause: Option<StakeCause>,
+    source_id: Option<u64>,
+    actor: Option<Principal>,
+    timestamp: Option<u64>,
+}
+
+// Affected crops were first stored as crop names and then as named crops on plots, both of which
+// are read as null here and through LegacyCropNames instead
+#[derive(candid::CandidType, Deserialize)]
+struct LegacyInsuranceClaim {
+    id: u64,
+    farmer_id: u64,
+    contract_id: u64,
+    claim_details: String,
+    affected_crops: Option<Vec<AffectedCrop>>,
+    peril: Option<Peril>,
+    evidence: String,
+    status: ClaimStatus,
+    claimed_amount: Option<f64>,
+    submitted_at: Option<u64>,
+    rejected_at: Option<u64>,
+    appeal_dispute_id: Option<u64>,
+    paid_out: Option<bool>,
+    evidence_hashes: Option<Vec<Vec<u8>>>,
+    assessor_ids: Option<Vec<u64>>,
+    assessments_disagree: Option<bool>,
+    accepted_assessment_id: Option<u64>,
+    payout_amount: Option<f64>,
+}
+
+#[derive(candid::CandidType, Deserialize)]
+struct LegacyCropNames {
+    affected_crops: Option<Vec<String>>,
+}
+
+#[derive(candid::CandidType, Deserialize)]
+struct LegacyPlotCrops {
+    affected_crops: Option<Vec<LegacyPlotCrop>>,
+}
+
+#[derive(candid::CandidType, Deserialize)]
+struct LegacyPlotCrop {
+    plot_id: u64,
+    crop: String,
+}
+
+#[derive(candid::CandidType, Deserialize)]
+struct LegacyDispute {
+    id: u64,
+    farmer_id: u64,
+    consumer_id: u64,
+    reason: String,
+    status: DisputeStatus,
+    resolution: Option<String>,
+    subject: Option<DisputeSubject>,
+    raised_by: Option<u64>,
+    created_at: Option<u64>,
+    evidence_deadline: Option<u64>,
+    arbitrator_id: Option<u64>,
+    ruling: Option<Ruling>,
+    jury_round_id: Option<u64>,
+}
+
+#[derive(candid::CandidType, Deserialize)]
+struct LegacyDisputeHistory {
+    dispute_id: u64,
+    status_updates: Vec<LegacyDisputeStatusUpdate>,
+}
+
+#[derive(candid::CandidType, Deserialize)]
+struct LegacyDisputeStatusUpdate {
+    status: DisputeStatus,
+    resolution: Option<String>,
+    timestamp: u64,
+    actor: Option<Principal>,
+}
+
+// A stored record kept as its raw encoding, so that it can be rewritten without decoding it as
+// the current type
+struct RawRecord(Vec<u8>);
+
+impl Storable for RawRecord {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        RawRecord(bytes.into_owned())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Brings records written by earlier versions up to STATE_VERSION
+fn migrate_stored_state() {
+    let version = STORED_STATE_VERSION.with(|version| *version.borrow().get());
+    if version < 1 {
+        upgrade_legacy_records(time());
+    }
+    STORED_STATE_VERSION.with(|version| version.borrow_mut().set(STATE_VERSION).expect("Cannot store the state version"));
+}
+
+// Rewrites every record of the first layouts in the current one and moves inline transaction
+// histories and voting records into their own maps. Contracts go first, since disputes are
+// matched to them
+fn upgrade_legacy_records(now: u64) {
+    let transactions: Vec<TransactionRecord> =
+        TRANSACTION_RECORDS.with(|records| records.borrow().iter().map(|(_, record)| record).collect());
+    for record in &transactions {
+        store_transaction_record(record);
+    }
+
+    upgrade_records(1, |user_id, bytes| {
+        let legacy = Decode!(bytes, LegacyUserProfile).expect("Cannot read a stored user profile");
+        for record in legacy.transaction_history.iter().flatten() {
+            if TRANSACTION_RECORDS.with(|records| !records.borrow().contains_key(&record.id)) {
+                store_transaction_record(record);
+            }
+            USER_TRANSACTIONS.with(|index| index.borrow_mut().insert((user_id, record.id), ()));
+        }
+        upgrade_user(legacy)
+    });
+    upgrade_records(3, |_, bytes| {
+        upgrade_contract(Decode!(bytes, LegacyInsuranceContract).expect("Cannot read a stored contract"))
+    });
+    upgrade_records(4, |proposal_id, bytes| {
+        let mut legacy = Decode!(bytes, LegacyGovernanceProposal).expect("Cannot read a stored proposal");
+        for vote in legacy.voting_records.take().unwrap_or_default() {
+            let record = VotingRecord {
+                proposal_id,
+                user_id: vote.user_id,
+                vote: vote.vote,
+                stake: vote.stake,
+            };
+            VOTING_RECORDS.with(|votes| votes.borrow_mut().insert((proposal_id, vote.user_id), record));
+        }
+        upgrade_proposal(legacy, now)
+    });
+    upgrade_records(5, |adjustment_id, bytes| {
+        let legacy = Decode!(bytes, LegacyStakeAdjustment).expect("Cannot read a stored stake adjustment");
+        upgrade_stake_adjustment(adjustment_id, legacy)
+    });
+    upgrade_records(6, |_, bytes| {
+        let legacy = Decode!(bytes, LegacyInsuranceClaim).expect("Cannot read a stored claim");
+        let mut crops: Vec<String> = Decode!(bytes, LegacyCropNames)
+            .ok()
+            .and_then(|view| view.affected_crops)
+            .unwrap_or_default();
+        if let Some(plot_crops) = Decode!(bytes, LegacyPlotCrops).ok().and_then(|view| view.affected_crops) {
+            crops.extend(plot_crops.into_iter().map(|affected| format!("{} on plot {}", affected.crop, affected.plot_id)));
+        }
+        upgrade_claim(legacy, &crops, now)
+    });
+    let contracts: Vec<InsuranceContract> =
+        INSURANCE_CONTRACTS.with(|contracts| contracts.borrow().iter().map(|(_, contract)| contract).collect());
+    upgrade_records(7, |_, bytes| {
+        upgrade_dispute(Decode!(bytes, LegacyDispute).expect("Cannot read a stored dispute"), &contracts, now)
+    });
+    upgrade_records(8, |_, bytes| {
+        upgrade_dispute_history(Decode!(bytes, LegacyDisputeHistory).expect("Cannot read a stored dispute history"))
+    });
+}
+
+// Rewrites every record of the map in the given memory with the result of `upgrade`, which gets
+// the key and the stored bytes. `upgrade` must not touch the map being rewritten
+fn upgrade_records<V: Storable>(memory_id: u8, upgrade: impl Fn(u64, &[u8]) -> V) {
+    let mut map = StableBTreeMap::<u64, RawRecord, Memory>::init(virtual_memory(memory_id));
+    let keys: Vec<u64> = map.iter().map(|(key, _)| key).collect();
+    for key in keys {
+        let bytes = map.get(&key).map(|record| record.0).unwrap_or_default();
+        let record = upgrade(key, &bytes);
+        map.insert(key, RawRecord(record.to_bytes().into_owned()));
+    }
+    // The typed stores cache the header of the map that was just written to
+    reload_stable_structures();
+}
+
+fn upgrade_user(legacy: LegacyUserProfile) -> UserProfile {
+    UserProfile {
+        id: legacy.id,
+        name: legacy.name,
+        role: legacy.role,
+        stake_in_dao: legacy.stake_in_dao,
+        principal: legacy.principal.unwrap_or(Principal::anonymous()),
+    }
+}
+
+// Contracts from before activation existed stay pending until both parties confirm them again
+fn upgrade_contract(legacy: LegacyInsuranceContract) -> InsuranceContract {
+    InsuranceContract {
+        id: legacy.id,
+        farmer_id: legacy.farmer_id,
+        consumer_id: legacy.consumer_id,
+        terms: legacy.terms,
+        conditions: legacy.conditions,
+        payout_criteria: legacy.payout_criteria,
+        status: legacy.status.unwrap_or_default(),
+        activated_at: legacy.activated_at,
+        plot_ids: legacy.plot_ids.unwrap_or_default(),
+        crop_ids: legacy.crop_ids.unwrap_or_default(),
+        sum_insured: legacy.sum_insured.unwrap_or(0.0),
+    }
+}
+
+// Proposals from before deposits and voting deadlines existed could never be settled, so they are
+// closed as expired with nothing held
+fn upgrade_proposal(legacy: LegacyGovernanceProposal, now: u64) -> GovernanceProposal {
+    GovernanceProposal {
+        id: legacy.id,
+        proposal_details: legacy.proposal_details,
+        proposer_id: legacy.proposer_id,
+        status: legacy.status.unwrap_or(ProposalStatus::Expired),
+        deposit: legacy.deposit.unwrap_or(0.0),
+        deposit_status: legacy.deposit_status.unwrap_or(DepositStatus::Refunded),
+        created_at: legacy.created_at.unwrap_or(now),
+        voting_deadline: legacy.voting_deadline.unwrap_or(now),
+        action: legacy.action,
+        executable_at: legacy.executable_at,
+        execution_error: legacy.execution_error,
+    }
+}
+
+// Adjustments were first keyed by their id without storing it; when they were made is unknown
+fn upgrade_stake_adjustment(adjustment_id: u64, legacy: LegacyStakeAdjustment) -> StakeAdjustment {
+    StakeAdjustment {
+        id: legacy.id.unwrap_or(adjustment_id),
+        user_id: legacy.user_id,
+        old_stake: legacy.old_stake,
+        new_stake: legacy.new_stake,
+        reason: legacy.reason,
+        cause: legacy.cause.unwrap_or(StakeCause::Legacy),
+        source_id: legacy.source_id,
+        actor: legacy.actor.unwrap_or(Principal::anonymous()),
+        timestamp: legacy.timestamp.unwrap_or(0),
+    }
+}
+
+// Crops named in free text cannot be matched to the catalogue, so they are kept in the details
+fn upgrade_claim(legacy: LegacyInsuranceClaim, crops: &[String], now: u64) -> InsuranceClaim {
+    let mut claim_details = legacy.claim_details;
+    if !crops.is_empty() {
+        claim_details = format!("{}\nAffected crops: {}", claim_details, crops.join(", "));
+    }
+    InsuranceClaim {
+        id: legacy.id,
+        farmer_id: legacy.farmer_id,
+        contract_id: legacy.contract_id,
+        claim_details,
+        affected_crops: legacy.affected_crops.unwrap_or_default(),
+        peril: legacy.peril.unwrap_or_default(),
+        evidence: legacy.evidence,
+        status: legacy.status,
+        claimed_amount: legacy.claimed_amount.unwrap_or(0.0),
+        submitted_at: legacy.submitted_at.unwrap_or(now),
+        rejected_at: legacy.rejected_at,
+        appeal_dispute_id: legacy.appeal_dispute_id,
+        paid_out: legacy.paid_out.unwrap_or(false),
+        evidence_hashes: legacy.evidence_hashes.unwrap_or_default(),
+        assessor_ids: legacy.assessor_ids.unwrap_or_default(),
+        assessments_disagree: legacy.assessments_disagree.unwrap_or(false),
+        accepted_assessment_id: legacy.accepted_assessment_id,
+        payout_amount: legacy.payout_amount,
+    }
+}
+
+// Disputes from before subjects existed are about a contract between the two parties. Without
+// one there is nothing to rule on, so the dispute is archived
+fn upgrade_dispute(legacy: LegacyDispute, contracts: &[InsuranceContract], now: u64) -> Dispute {
+    let contract_id = contracts
+        .iter()
+        .find(|contract| contract.farmer_id == legacy.farmer_id && contract.consumer_id == legacy.consumer_id)
+        .map(|contract| contract.id);
+    let (subject, status) = match (legacy.subject, contract_id) {
+        (Some(subject), _) => (subject, legacy.status),
+        (None, Some(contract_id)) => (DisputeSubject::Contract(contract_id), legacy.status),
+        (None, None) => (DisputeSubject::Contract(0), DisputeStatus::Archived),
+    };
+    Dispute {
+        id: legacy.id,
+        farmer_id: legacy.farmer_id,
+        consumer_id: legacy.consumer_id,
+        reason: legacy.reason,
+        status,
+        resolution: legacy.resolution,
+        subject,
+        raised_by: legacy.raised_by.unwrap_or(legacy.farmer_id),
+        created_at: legacy.created_at.unwrap_or(now),
+        evidence_deadline: legacy.evidence_deadline.unwrap_or(now + DISPUTE_EVIDENCE_PERIOD_NS),
+        arbitrator_id: legacy.arbitrator_id,
+        ruling: legacy.ruling,
+        jury_round_id: legacy.jury_round_id,
+    }
+}
+
+fn upgrade_dispute_history(legacy: LegacyDisputeHistory) -> DisputeHistory {
+    DisputeHistory {
+        dispute_id: legacy.dispute_id,
+        status_updates: legacy
+            .status_updates
+            .into_iter()
+            .map(|update| DisputeStatusUpdate {
+                status: update.status,
+                resolution: update.resolution,
+                timestamp: update.timestamp,
+                actor: update.actor.unwrap_or(Principal::anonymous()),
+            })
+            .collect(),
+    }
+}
+
+// need this to generate candid
+ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use system::{act_as, advance_time, controller, drop_timers, run_due_timers, set_instruction_counter};
+
+    // Stands in for the system API of the replica. Every test runs on its own thread, so it starts
+    // with empty stores and its own clock
+    pub(super) mod system {
+        use candid::Principal;
+        use std::{
+            cell::{Cell, RefCell},
+            time::Duration,
+        };
+
+        const START: u64 = 1_700_000_000_000_000_000;
+
+        type Timer = (u64, Box<dyn FnOnce()>); // Due time and callback
+
+        thread_local! {
+            static NOW: Cell<u64> = const { Cell::new(START) };
+            static CALLER: Cell<Principal> = const { Cell::new(Principal::anonymous()) };
+            static TIMERS: RefCell<Vec<Timer>> = RefCell::new(Vec::new());
+            static CERTIFIED_DATA: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+            static INSTRUCTIONS: Cell<u64> = const { Cell::new(0) };
+        }
+
+        pub(crate) fn controller() -> Principal {
+            Principal::from_slice(&[0xff])
+        }
+
+        pub(crate) fn act_as(principal: Principal) {
+            CALLER.with(|caller| caller.set(principal));
+        }
+
+        pub(crate) fn advance_time(ns: u64) {
+            NOW.with(|now| now.set(now.get() + ns));
+        }
+
+        pub(crate) fn time() -> u64 {
+            NOW.with(Cell::get)
+        }
+
+        pub(crate) fn caller() -> Principal {
+            CALLER.with(Cell::get)
+        }
+
+        pub(crate) fn is_controller(principal: &Principal) -> bool {
+            *principal == controller()
+        }
+
+        pub(crate) fn set_certified_data(data: &[u8]) {
+            CERTIFIED_DATA.with(|certified| *certified.borrow_mut() = data.to_vec());
+        }
+
+        // Stands in for a certificate by returning the certified data it would sign
+        pub(crate) fn data_certificate() -> Option<Vec<u8>> {
+            Some(CERTIFIED_DATA.with(|certified| certified.borrow().clone()))
+        }
+
+        // Batched work finishes within the first call unless a test uses up the budget
+        pub(crate) fn instruction_counter() -> u64 {
+            INSTRUCTIONS.with(Cell::get)
+        }
+
+        pub(crate) fn set_instruction_counter(instructions: u64) {
+            INSTRUCTIONS.with(|counter| counter.set(instructions));
+        }
+
+        pub(crate) fn canister_balance128() -> u128 {
+            1_000_000_000_000
+        }
+
+        // Timers only fire through run_due_timers
+        pub(crate) fn set_timer(delay: Duration, func: impl FnOnce() + 'static) {
+            let at = time() + delay.as_nanos() as u64;
+            TIMERS.with(|timers| timers.borrow_mut().push((at, Box::new(func))));
+        }
+
+        // Runs the timers that are due in the order they are due, including the ones they arm
+        pub(crate) fn run_due_timers() {
+            loop {
+                let due = TIMERS.with(|timers| {
+                    let mut timers = timers.borrow_mut();
+                    let (index, _) = timers
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, (at, _))| *at <= time())
+                        .min_by_key(|(_, (at, _))| *at)?;
+                    Some(timers.remove(index).1)
+                });
+                match due {
+                    Some(func) => func(),
+                    None => break,
+                }
+            }
+        }
+
+        // Timers do not survive an upgrade
+        pub(crate) fn drop_timers() {
+            TIMERS.with(|timers| timers.borrow_mut().clear());
+        }
+
+        pub(crate) fn set_timer_interval(_interval: Duration, _func: impl FnMut() + 'static) {}
+    }
+
+    fn ok<T>(result: Result<T, Error>) -> T {
+        result.unwrap_or_else(|error| panic!("{}", error_message(error)))
+    }
+
+    // A single byte of 4 would be the anonymous principal
+    fn principal(n: u8) -> Principal {
+        Principal::from_slice(&[0x10, n])
+    }
+
+    // Creates a profile owned by principal `n` with `stake` funded and bonded
+    fn user(n: u8, role: UserRole, stake: f64) -> u64 {
+        act_as(principal(n));
+        let user_id = ok(create_user_profile(format!("User {}", n), role, 0.0)).id;
+        if stake > 0.0 {
+            act_as(controller());
+            ok(fund_stake_account(user_id, stake));
+            act_as(principal(n));
+            ok(bond_stake(user_id, stake));
+        }
+        user_id
+    }
+
+    fn stake_of(user_id: u64) -> f64 {
+        ok(get_stake_balance(user_id)).bonded
+    }
+
+    fn close_voting(proposal: &GovernanceProposal) -> GovernanceProposal {
+        advance_time(proposal.voting_deadline - time());
+        process_proposal(proposal.id);
+        ok(read_governance_proposal(proposal.id))
+    }
+
+    #[test]
+    fn deposit_is_refunded_when_quorum_is_missed() {
+        let proposer_id = user(1, UserRole::Farmer, 100.0);
+        let proposal = ok(submit_governance_proposal(proposer_id, "Lower fees".to_string(), None));
+        assert_eq!(stake_of(proposer_id), 90.0);
+
+        let proposal = close_voting(&proposal);
+        assert!(proposal.status == ProposalStatus::Expired);
+        assert!(proposal.deposit_status == DepositStatus::Refunded);
+        assert_eq!(stake_of(proposer_id), 100.0);
+    }
+
+    #[test]
+    fn deposit_is_refunded_when_proposal_passes() {
+        let proposer_id = user(1, UserRole::Farmer, 100.0);
+        let voter_id = user(2, UserRole::Consumer, 100.0);
+        act_as(principal(1));
+        let proposal = ok(submit_governance_proposal(proposer_id, "Lower fees".to_string(), None));
+        act_as(principal(2));
+        vote_on_proposal(voter_id, proposal.id, VoteType::Approve).unwrap();
+
+        let proposal = close_voting(&proposal);
+        assert!(proposal.status == ProposalStatus::Passed);
+        assert!(proposal.deposit_status == DepositStatus::Refunded);
+        assert_eq!(stake_of(proposer_id), 100.0);
+    }
+
+    #[test]
+    fn deposit_is_burned_on_clear_rejection() {
+        let proposer_id = user(1, UserRole::Farmer, 100.0);
+        let voter_id = user(2, UserRole::Consumer, 100.0);
+        act_as(principal(1));
+        let proposal = ok(submit_governance_proposal(proposer_id, "Raise fees".to_string(), None));
+        act_as(principal(2));
+        vote_on_proposal(voter_id, proposal.id, VoteType::Reject).unwrap();
+
+        let proposal = close_voting(&proposal);
+        assert!(proposal.status == ProposalStatus::Rejected);
+        assert!(proposal.deposit_status == DepositStatus::Burned);
+        assert_eq!(stake_of(proposer_id), 90.0);
+    }
+
+    #[test]
+    fn proposals_need_a_stake_covering_the_deposit() {
+        let proposer_id = user(1, UserRole::Farmer, 5.0);
+        let result = submit_governance_proposal(proposer_id, "Lower fees".to_string(), None);
+        assert!(matches!(result, Err(Error::InvalidInput { .. })));
+        assert_eq!(stake_of(proposer_id), 5.0);
+    }
+
+
+    #[test]
+    fn deposit_is_refunded_when_a_rejection_misses_quorum() {
+        let proposer_id = user(1, UserRole::Farmer, 100.0);
+        let voter_id = user(2, UserRole::Consumer, 10.0);
+        user(3, UserRole::Consumer, 200.0);
+        act_as(principal(1));
+        let proposal = ok(submit_governance_proposal(proposer_id, "Raise fees".to_string(), None));
+        act_as(principal(2));
+        vote_on_proposal(voter_id, proposal.id, VoteType::Reject).unwrap();
+
+        let proposal = close_voting(&proposal);
+        assert!(proposal.status == ProposalStatus::Expired);
+        assert!(proposal.deposit_status == DepositStatus::Refunded);
+        assert_eq!(stake_of(proposer_id), 100.0);
+    }
+
+    #[test]
+    fn only_settled_proposals_can_be_deleted() {
+        let proposer_id = user(1, UserRole::Farmer, 100.0);
+        let voter_id = user(2, UserRole::Consumer, 100.0);
+        act_as(principal(1));
+        let passed = ok(submit_governance_proposal(proposer_id, "Lower fees".to_string(), None));
+        let expired = ok(submit_governance_proposal(proposer_id, "Raise fees".to_string(), None));
+        assert!(matches!(delete_governance_proposal(expired.id), Err(Error::InvalidInput { .. })));
+        act_as(principal(2));
+        vote_on_proposal(voter_id, passed.id, VoteType::Approve).unwrap();
+
+        let passed = close_voting(&passed);
+        process_proposal(expired.id);
+        assert!(passed.status == ProposalStatus::Passed);
+        act_as(principal(1));
+        assert!(matches!(delete_governance_proposal(passed.id), Err(Error::InvalidInput { .. })));
+        ok(delete_governance_proposal(expired.id));
+        assert!(read_governance_proposal(expired.id).is_err());
+    }
+
+    #[test]
+    fn proposals_are_finalised_when_voting_closes() {
+        let proposer_id = user(1, UserRole::Farmer, 100.0);
+        let proposal = ok(submit_governance_proposal(proposer_id, "Lower fees".to_string(), None));
+
+        advance_time(proposal.voting_deadline - time() - 1);
+        run_due_timers();
+        assert!(ok(read_governance_proposal(proposal.id)).status == ProposalStatus::Open);
+        assert!(enact_proposal(proposal.id).is_err());
+
+        advance_time(1);
+        run_due_timers();
+        let proposal = ok(read_governance_proposal(proposal.id));
+        assert!(proposal.status == ProposalStatus::Expired);
+        assert_eq!(stake_of(proposer_id), 100.0);
+    }
+
+    #[test]
+    fn passed_actions_are_executed_after_the_timelock() {
+        let proposer_id = user(1, UserRole::Farmer, 100.0);
+        let voter_id = user(2, UserRole::Consumer, 100.0);
+        let config = StakingConfig {
+            unbonding_period_ns: 1,
+            max_unbonding_entries: 4,
+        };
+        act_as(principal(1));
+        let action = Some(ProposalAction::UpdateStakingConfig(config));
+        let proposal = ok(submit_governance_proposal(proposer_id, "Faster unbonding".to_string(), action));
+        act_as(principal(2));
+        vote_on_proposal(voter_id, proposal.id, VoteType::Approve).unwrap();
+
+        advance_time(proposal.voting_deadline - time());
+        run_due_timers();
+        let proposal = ok(read_governance_proposal(proposal.id));
+        assert!(proposal.status == ProposalStatus::Queued);
+        assert_eq!(get_staking_config().max_unbonding_entries, 16);
+
+        advance_time(proposal.executable_at.unwrap() - time());
+        run_due_timers();
+        assert!(ok(read_governance_proposal(proposal.id)).status == ProposalStatus::Executed);
+        assert_eq!(get_staking_config().max_unbonding_entries, 4);
+    }
+
+    #[test]
+    fn proposal_timers_are_armed_again_after_an_upgrade() {
+        let proposer_id = user(1, UserRole::Farmer, 100.0);
+        let proposal = ok(submit_governance_proposal(proposer_id, "Lower fees".to_string(), None));
+
+        drop_timers();
+        post_upgrade();
+        advance_time(proposal.voting_deadline - time());
+        run_due_timers();
+        assert!(ok(read_governance_proposal(proposal.id)).status == ProposalStatus::Expired);
+    }
+
+    #[test]
+    fn bonding_is_limited_to_the_funded_balance() {
+        let user_id = user(1, UserRole::Farmer, 0.0);
+        act_as(controller());
+        ok(fund_stake_account(user_id, 50.0));
+
+        act_as(principal(1));
+        assert!(matches!(bond_stake(user_id, 60.0), Err(Error::InvalidInput { .. })));
+        let balance = ok(bond_stake(user_id, 30.0));
+        assert_eq!((balance.bonded, balance.available), (30.0, 20.0));
+
+        act_as(principal(2));
+        assert!(matches!(bond_stake(user_id, 10.0), Err(Error::Unauthorized { .. })));
+        assert_eq!(stake_of(user_id), 30.0);
+    }
+
+    #[test]
+    fn profiles_with_stake_cannot_be_deleted() {
+        let user_id = user(1, UserRole::Farmer, 20.0);
+
+        act_as(principal(2));
+        assert!(matches!(update_user_profile(user_id, "Mallory".to_string()), Err(Error::Unauthorized { .. })));
+        assert!(matches!(delete_user_profile(user_id), Err(Error::Unauthorized { .. })));
+
+        act_as(principal(1));
+        assert!(matches!(delete_user_profile(user_id), Err(Error::InvalidInput { .. })));
+        ok(unbond_stake(user_id, 20.0));
+        assert!(matches!(delete_user_profile(user_id), Err(Error::InvalidInput { .. })));
+
+        advance_time(14 * 24 * 60 * 60 * 1_000_000_000);
+        ok(withdraw_stake(user_id, 20.0));
+        ok(delete_user_profile(user_id));
+        assert!(matches!(get_stake_balance(user_id), Err(Error::NotFound { .. })));
+    }
+
+    #[test]
+    fn unbonded_stake_is_withdrawable_after_the_cooldown() {
+        let user_id = user(1, UserRole::Farmer, 100.0);
+        let balance = ok(unbond_stake(user_id, 40.0));
+        assert_eq!((balance.bonded, balance.unbonding, balance.available), (60.0, 40.0, 0.0));
+        assert!(matches!(withdraw_stake(user_id, 40.0), Err(Error::InvalidInput { .. })));
+
+        advance_time(get_staking_config().unbonding_period_ns);
+        let balance = ok(withdraw_stake(user_id, 30.0));
+        assert_eq!((balance.bonded, balance.unbonding, balance.available), (60.0, 0.0, 10.0));
+        assert!(matches!(withdraw_stake(user_id, 20.0), Err(Error::InvalidInput { .. })));
+    }
+
+    #[test]
+    fn every_stake_change_is_recorded_in_the_ledger() {
+        let user_id = user(1, UserRole::Farmer, 20.0);
+        act_as(controller());
+        adjust_stake_dao_participation(user_id, 2).unwrap();
+
+        let history = get_stake_history(user_id);
+        assert_eq!(history.len(), 2);
+        assert!(history[0].cause == StakeCause::Bond && history[0].actor == principal(1));
+        assert!(history[1].cause == StakeCause::DaoParticipation && history[1].actor == controller());
+        assert_eq!((history[1].old_stake, history[1].new_stake), (20.0, 30.0));
+    }
+
+    #[test]
+    fn ledger_entries_are_reversed_by_compensation() {
+        let user_id = user(1, UserRole::Farmer, 20.0);
+        act_as(controller());
+        adjust_stake_dao_participation(user_id, 2).unwrap();
+        let reward = get_stake_history(user_id).pop().unwrap();
+
+        act_as(principal(1));
+        let result = compensate_stake_adjustment(reward.id, "Granted twice".to_string());
+        assert!(matches!(result, Err(Error::Unauthorized { .. })));
+
+        act_as(controller());
+        let compensation = ok(compensate_stake_adjustment(reward.id, "Granted twice".to_string()));
+        assert!(compensation.cause == StakeCause::Compensation);
+        assert_eq!(compensation.source_id, Some(reward.id));
+        assert_eq!(stake_of(user_id), 20.0);
+        assert_eq!(get_stake_history(user_id).len(), 3);
+    }
+
+    #[test]
+    fn attestations_count_towards_reputation_and_decay() {
+        let attester_id = user(1, UserRole::Consumer, 0.0);
+        let subject_id = user(2, UserRole::Farmer, 0.0);
+
+        act_as(principal(1));
+        assert!(matches!(attest_user(attester_id, attester_id, true), Err(Error::InvalidInput { .. })));
+        ok(attest_user(attester_id, subject_id, true));
+        assert!(matches!(attest_user(attester_id, subject_id, true), Err(Error::InvalidInput { .. })));
+
+        let breakdown = ok(explain_reputation(subject_id));
+        assert_eq!(breakdown.score, 1.0);
+        let attestations = breakdown
+            .components
+            .iter()
+            .find(|component| component.component == ReputationComponent::PeerAttestations)
+            .unwrap();
+        assert_eq!((attestations.value, attestations.weight), (1.0, 1.0));
+
+        advance_time(breakdown.half_life_ns);
+        assert!((ok(explain_reputation(subject_id)).score - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn stake_held_for_pending_slashes_cannot_be_unbonded() {
+        let farmer_id = user(1, UserRole::Farmer, 100.0);
+        let consumer_id = user(2, UserRole::Consumer, 0.0);
+        ok(open_slash(farmer_id, Offence::FraudulentClaim, 0, consumer_id));
+
+        act_as(principal(1));
+        assert!(matches!(unbond_stake(farmer_id, 60.0), Err(Error::InvalidInput { .. })));
+        ok(unbond_stake(farmer_id, 50.0));
+    }
+
+    #[test]
+    fn rejected_slash_appeal_keeps_the_stake() {
+        let farmer_id = user(1, UserRole::Farmer, 100.0);
+        let consumer_id = user(2, UserRole::Consumer, 0.0);
+        let slash = ok(open_slash(farmer_id, Offence::FraudulentClaim, 0, consumer_id));
+        assert_eq!(slash.amount, 50.0);
+
+        act_as(principal(2));
+        assert!(matches!(appeal_slash(slash.id, "Not me".to_string()), Err(Error::Unauthorized { .. })));
+        act_as(principal(1));
+        let slash = ok(appeal_slash(slash.id, "The hail was real".to_string()));
+        assert!(slash.status == SlashStatus::Appealed);
+
+        act_as(controller());
+        let slash = ok(resolve_slash_appeal(slash.id, false));
+        assert!(slash.status == SlashStatus::Cancelled);
+        assert_eq!(stake_of(farmer_id), 100.0);
+        assert!(matches!(resolve_slash_appeal(slash.id, true), Err(Error::InvalidInput { .. })));
+    }
+
+    #[test]
+    fn upheld_slash_appeal_takes_the_stake() {
+        let farmer_id = user(1, UserRole::Farmer, 100.0);
+        let consumer_id = user(2, UserRole::Consumer, 0.0);
+        let slash = ok(open_slash(farmer_id, Offence::FraudulentClaim, 0, consumer_id));
+        act_as(principal(1));
+        ok(appeal_slash(slash.id, "The hail was real".to_string()));
+
+        act_as(controller());
+        let slash = ok(resolve_slash_appeal(slash.id, true));
+        assert!(slash.status == SlashStatus::Executed);
+        assert!(slash.stake_adjustment_id.is_some());
+        assert_eq!(stake_of(farmer_id), 50.0);
+        assert_eq!(get_treasury().risk_pool, 50.0);
+    }
+
+    #[test]
+    fn slashes_cannot_be_appealed_after_the_window() {
+        let farmer_id = user(1, UserRole::Farmer, 100.0);
+        let consumer_id = user(2, UserRole::Consumer, 0.0);
+        let slash = ok(open_slash(farmer_id, Offence::FraudulentClaim, 0, consumer_id));
+
+        advance_time(slash.appeal_deadline - time());
+        act_as(principal(1));
+        assert!(matches!(appeal_slash(slash.id, "Too late".to_string()), Err(Error::InvalidInput { .. })));
+    }
+
+    #[test]
+    fn unappealed_slashes_are_executed_when_the_window_closes() {
+        let farmer_id = user(1, UserRole::Farmer, 100.0);
+        let consumer_id = user(2, UserRole::Consumer, 0.0);
+        let slash = ok(open_slash(farmer_id, Offence::FraudulentClaim, 0, consumer_id));
+
+        advance_time(slash.appeal_deadline - time());
+        run_due_timers();
+        assert_eq!(stake_of(farmer_id), 50.0);
+        assert_eq!(get_treasury().risk_pool, 50.0);
+    }
+
+    fn contract(farmer_id: u64, consumer_id: u64) -> InsuranceContract {
+        ok(create_insurance_contract(
+            farmer_id,
+            consumer_id,
+            "Terms".to_string(),
+            "Conditions".to_string(),
+            "Payout criteria".to_string(),
+            ContractCoverage {
+                plot_ids: Vec::new(),
+                crop_ids: Vec::new(),
+                sum_insured: 1_000.0,
+            },
+        ))
+    }
+
+    #[test]
+    fn claim_history_rewards_insured_farmers_once_per_epoch() {
+        let insured_id = user(1, UserRole::Farmer, 0.0);
+        let uninsured_id = user(2, UserRole::Farmer, 0.0);
+        let consumer_id = user(3, UserRole::Consumer, 0.0);
+        let contract = contract(insured_id, consumer_id);
+        ok(activate_insurance_contract(contract.id));
+
+        assert_eq!(run_claim_history_epoch(), 2);
+        assert_eq!(stake_of(insured_id), 10.0);
+        assert_eq!(stake_of(uninsured_id), 0.0);
+        assert!(adjust_stake_claim_history(insured_id).is_err());
+        assert_eq!(run_claim_history_epoch(), 0);
+
+        advance_time(CLAIM_HISTORY_EPOCH_NS);
+        adjust_stake_claim_history(insured_id).unwrap();
+        assert_eq!(stake_of(insured_id), 20.0);
+    }
+
+    fn dispute(subject: DisputeSubject, raised_by: u64) -> Dispute {
+        create_dispute(DisputePayload {
+            subject,
+            raised_by,
+            reason: "Hail damage".to_string(),
+        })
+        .unwrap()
+    }
+
+    // Assigns the arbitrator the way assign_arbitrator does once it has its randomness
+    fn assign(dispute_id: u64, arbitrator_id: u64) {
+        let mut dispute = ok(load_dispute(dispute_id));
+        dispute.arbitrator_id = Some(arbitrator_id);
+        dispute.status = DisputeStatus::UnderReview;
+        store_dispute(&dispute);
+    }
+
+    #[test]
+    fn only_parties_submit_evidence_before_the_deadline() {
+        let farmer_id = user(1, UserRole::Farmer, 0.0);
+        let consumer_id = user(2, UserRole::Consumer, 0.0);
+        let outsider_id = user(3, UserRole::Consumer, 0.0);
+        let contract = contract(farmer_id, consumer_id);
+        act_as(principal(3));
+        let payload = DisputePayload {
+            subject: DisputeSubject::Contract(contract.id),
+            raised_by: outsider_id,
+            reason: "Not mine".to_string(),
+        };
+        assert!(create_dispute(payload).is_err());
+
+        act_as(principal(1));
+        let dispute = dispute(DisputeSubject::Contract(contract.id), farmer_id);
+        ok(submit_dispute_evidence(dispute.id, farmer_id, "Photo".to_string(), "Hail".to_string(), None));
+        ok(submit_dispute_statement(dispute.id, farmer_id, "The crop was lost".to_string()));
+        act_as(principal(3));
+        let result = submit_dispute_evidence(dispute.id, outsider_id, "Photo".to_string(), "Sun".to_string(), None);
+        assert!(matches!(result, Err(Error::Unauthorized { .. })));
+
+        advance_time(dispute.evidence_deadline - time());
+        act_as(principal(2));
+        let result = submit_dispute_statement(dispute.id, consumer_id, "Too late".to_string());
+        assert!(matches!(result, Err(Error::InvalidInput { .. })));
+        assert_eq!(get_dispute_evidence(dispute.id).len(), 1);
+    }
+
+    #[test]
+    fn rulings_transfer_stake_between_the_parties() {
+        let farmer_id = user(1, UserRole::Farmer, 0.0);
+        let consumer_id = user(2, UserRole::Consumer, 100.0);
+        let arbitrator_id = user(3, UserRole::Consumer, 0.0);
+        let contract = contract(farmer_id, consumer_id);
+        act_as(principal(1));
+        let dispute = dispute(DisputeSubject::Contract(contract.id), farmer_id);
+        assign(dispute.id, arbitrator_id);
+
+        let transfer = |amount| MonetaryOutcome {
+            source: FundSource::Stake(consumer_id),
+            recipient_id: farmer_id,
+            amount,
+        };
+        act_as(principal(3));
+        let result = submit_ruling(dispute.id, arbitrator_id, farmer_id, "Pay".to_string(), vec![transfer(40.0)]);
+        assert!(matches!(result, Err(Error::InvalidInput { .. })));
+
+        advance_time(dispute.evidence_deadline - time());
+        act_as(principal(1));
+        let result = submit_ruling(dispute.id, farmer_id, farmer_id, "Pay".to_string(), vec![transfer(40.0)]);
+        assert!(matches!(result, Err(Error::Unauthorized { .. })));
+        act_as(principal(3));
+        let result = submit_ruling(dispute.id, arbitrator_id, farmer_id, "Pay".to_string(), vec![transfer(140.0)]);
+        assert!(matches!(result, Err(Error::InvalidInput { .. })));
+
+        let dispute = ok(submit_ruling(dispute.id, arbitrator_id, farmer_id, "Pay".to_string(), vec![transfer(40.0)]));
+        assert!(matches!(dispute.status, DisputeStatus::Resolved));
+        assert_eq!(stake_of(consumer_id), 60.0);
+        assert_eq!(ok(get_stake_balance(farmer_id)).available, 40.0);
+    }
+
+    #[test]
+    fn dispute_changes_are_recorded_in_the_history() {
+        let farmer_id = user(1, UserRole::Farmer, 0.0);
+        let consumer_id = user(2, UserRole::Consumer, 0.0);
+        let contract = contract(farmer_id, consumer_id);
+        act_as(principal(1));
+        let dispute = dispute(DisputeSubject::Contract(contract.id), farmer_id);
+        advance_time(1_000);
+        ok(submit_dispute_statement(dispute.id, farmer_id, "The crop was lost".to_string()));
+
+        act_as(principal(2));
+        assert!(matches!(archive_dispute(dispute.id), Err(Error::Unauthorized { .. })));
+        advance_time(1_000);
+        act_as(controller());
+        ok(archive_dispute(dispute.id));
+
+        let updates = get_dispute_history(dispute.id).unwrap().status_updates;
+        assert_eq!(updates.len(), 2);
+        assert!(matches!(updates[0].status, DisputeStatus::Raised) && updates[0].actor == principal(1));
+        assert!(matches!(updates[1].status, DisputeStatus::Archived) && updates[1].actor == controller());
+
+        let timeline = ok(get_dispute_timeline(dispute.id, 1, 10));
+        assert_eq!((timeline.total, timeline.entries.len()), (3, 2));
+        let first = &timeline.entries[0].event;
+        assert!(matches!(first, DisputeTimelineEvent::StatementSubmitted { party_id } if *party_id == farmer_id));
+    }
+
+    fn commitment(winner_id: u64, salt: &[u8]) -> Vec<u8> {
+        Sha256::new().chain_update(winner_id.to_le_bytes()).chain_update(salt).finalize().to_vec()
+    }
 
-#[ic_cdk::update]
-fn update_governance_proposal(
-    proposal_id: u64,
-    proposal_details: String,
-    proposer_id: u64,
-    voting_records: Vec<VotingRecord>,
-) -> Result<GovernanceProposal, Error> {
-    GOVERNANCE_PROPOSALS.with(|proposals| {
-        let mut proposals = proposals.borrow_mut();
+    #[test]
+    fn jury_votes_are_committed_then_revealed() {
+        let farmer_id = user(1, UserRole::Farmer, 0.0);
+        let consumer_id = user(2, UserRole::Consumer, 0.0);
+        let jurors: BTreeMap<u64, u8> = (3..6).map(|n| (user(n, UserRole::Farmer, 20.0), n)).collect();
+        let contract = contract(farmer_id, consumer_id);
+        act_as(principal(1));
+        let dispute = dispute(DisputeSubject::Contract(contract.id), farmer_id);
+        let round = ok(open_jury_round(&dispute, 1, 3, b"seed"));
+        assert!(round.jurors.iter().copied().collect::<BTreeSet<_>>() == jurors.keys().copied().collect());
+
+        // The first two jurors side with the farmer, the last one with the consumer
+        let votes: Vec<(u64, u8, u64)> = round
+            .jurors
+            .iter()
+            .enumerate()
+            .map(|(index, juror_id)| (*juror_id, jurors[juror_id], if index < 2 { farmer_id } else { consumer_id }))
+            .collect();
+        for (juror_id, n, winner_id) in &votes {
+            act_as(principal(*n));
+            let result = reveal_jury_vote(round.id, *juror_id, *winner_id, b"salt".to_vec());
+            assert!(matches!(result, Err(Error::InvalidInput { .. })));
+            ok(commit_jury_vote(round.id, *juror_id, commitment(*winner_id, b"salt")));
+        }
+        act_as(principal(1));
+        let result = commit_jury_vote(round.id, farmer_id, commitment(farmer_id, b"salt"));
+        assert!(matches!(result, Err(Error::Unauthorized { .. })));
+
+        advance_time(round.commit_deadline - time());
+        for (juror_id, n, winner_id) in &votes {
+            act_as(principal(*n));
+            let result = commit_jury_vote(round.id, *juror_id, commitment(*winner_id, b"other"));
+            assert!(matches!(result, Err(Error::InvalidInput { .. })));
+            let result = reveal_jury_vote(round.id, *juror_id, *winner_id, b"other".to_vec());
+            assert!(matches!(result, Err(Error::InvalidInput { .. })));
+            ok(reveal_jury_vote(round.id, *juror_id, *winner_id, b"salt".to_vec()));
+        }
 
-        // Check if the governance proposal exists
-        if let Some(mut proposal) = proposals.remove(&proposal_id) {
-            // Update the fields
-            proposal.proposal_details = proposal_details;
-            proposal.proposer_id = proposer_id;
-            proposal.voting_records = voting_records;
+        assert!(matches!(advance_jury_round(round.id), Err(Error::InvalidInput { .. })));
+        advance_time(round.reveal_deadline - time());
+        let round = ok(advance_jury_round(round.id));
+        assert!(round.status == JuryRoundStatus::Decided);
+        assert_eq!(round.winner_id, Some(farmer_id));
 
-            // Insert the updated proposal back into the map
-            proposals.insert(proposal_id, proposal.clone());
+        // The outvoted juror loses 5% of their stake to the two others
+        let stakes: Vec<f64> = votes.iter().map(|(juror_id, _, _)| stake_of(*juror_id)).collect();
+        assert_eq!(stakes, vec![20.5, 20.5, 19.0]);
+    }
 
-            // Return the updated proposal
-            Ok(proposal)
-        } else {
-            // Governance proposal not found
-            Err(Error::NotFound {
-                msg: format!("Governance proposal with id={} not found", proposal_id),
-            })
+    #[test]
+    fn jurors_who_do_not_reveal_are_penalised() {
+        let farmer_id = user(1, UserRole::Farmer, 0.0);
+        let consumer_id = user(2, UserRole::Consumer, 0.0);
+        let jurors: BTreeMap<u64, u8> = (3..5).map(|n| (user(n, UserRole::Farmer, 20.0), n)).collect();
+        let contract = contract(farmer_id, consumer_id);
+        act_as(principal(1));
+        let dispute = dispute(DisputeSubject::Contract(contract.id), farmer_id);
+        let round = ok(open_jury_round(&dispute, 1, 2, b"seed"));
+
+        let (revealing_id, silent_id) = (round.jurors[0], round.jurors[1]);
+        for juror_id in [revealing_id, silent_id] {
+            act_as(principal(jurors[&juror_id]));
+            ok(commit_jury_vote(round.id, juror_id, commitment(consumer_id, b"salt")));
         }
-    })
-}
-
-#[ic_cdk::update]
-fn delete_governance_proposal(proposal_id: u64) -> Result<GovernanceProposal, Error> {
-    GOVERNANCE_PROPOSALS
-        .with(|proposals| proposals.borrow_mut().remove(&proposal_id))
-        .ok_or(Error::NotFound {
-            msg: format!("Governance proposal with id={} not found", proposal_id),
-        })
-}
+        advance_time(round.commit_deadline - time());
+        act_as(principal(jurors[&revealing_id]));
+        ok(reveal_jury_vote(round.id, revealing_id, consumer_id, b"salt".to_vec()));
+
+        advance_time(round.reveal_deadline - time());
+        let round = ok(advance_jury_round(round.id));
+        assert_eq!(round.winner_id, Some(consumer_id));
+        assert_eq!(stake_of(revealing_id), 21.0);
+        assert_eq!(stake_of(silent_id), 19.0);
+    }
 
-#[ic_cdk::update]
-fn create_stake_adjustment(
-    user_id: u64,
-    old_stake: f64,
-    new_stake: f64,
-    reason: String,
-) -> Option<StakeAdjustment> {
-    let id = ID_COUNTER
-        .with(|counter| {
+    // Stores a claim against a new contract as verify_insurance_claim leaves it
+    fn verified_claim(farmer_id: u64, consumer_id: u64, payout_amount: f64) -> InsuranceClaim {
+        let contract = contract(farmer_id, consumer_id);
+        let id = ID_COUNTER.with(|counter| {
             let current_value = *counter.borrow().get();
-            counter.borrow_mut().set(current_value + 1)
-        })
-        .expect("cannot increment id counter");
+            counter.borrow_mut().set(current_value + 1).unwrap();
+            current_value
+        });
+        let claim = InsuranceClaim {
+            id,
+            farmer_id,
+            contract_id: contract.id,
+            status: ClaimStatus::Verified,
+            claimed_amount: payout_amount,
+            payout_amount: Some(payout_amount),
+            ..Default::default()
+        };
+        store_claim(&claim);
+        claim
+    }
 
-    let stake_adjustment = StakeAdjustment {
-        user_id,
-        old_stake,
-        new_stake,
-        reason,
-    };
+    #[test]
+    fn approved_claims_are_paid_once_the_risk_pool_covers_them() {
+        let farmer_id = user(1, UserRole::Farmer, 0.0);
+        let consumer_id = user(2, UserRole::Consumer, 0.0);
+        let claim = verified_claim(farmer_id, consumer_id, 300.0);
+
+        act_as(principal(1));
+        let result = approve_or_reject_claim(claim.id, true, "Hail".to_string());
+        assert!(matches!(result, Err(Error::Unauthorized { .. })));
+
+        act_as(controller());
+        ok(approve_or_reject_claim(claim.id, true, "Hail".to_string()));
+        assert!(!ok(read_insurance_claim(claim.id)).paid_out);
+
+        ok(fund_risk_pool(1_000.0));
+        assert!(ok(retry_claim_payout(claim.id)).paid_out);
+        assert!(matches!(retry_claim_payout(claim.id), Err(Error::InvalidInput { .. })));
+        assert_eq!(ok(get_stake_balance(farmer_id)).available, 300.0);
+        assert_eq!(get_treasury().risk_pool, 700.0);
+    }
 
-    STAKE_ADJUSTMENTS.with(|adjustments| {
-        adjustments.borrow_mut().insert(id, stake_adjustment.clone());
-    });
+    #[test]
+    fn won_claim_appeals_pay_the_claim_once() {
+        let farmer_id = user(1, UserRole::Farmer, 0.0);
+        let consumer_id = user(2, UserRole::Consumer, 0.0);
+        let arbitrator_id = user(3, UserRole::Consumer, 0.0);
+        let claim = verified_claim(farmer_id, consumer_id, 300.0);
+        act_as(controller());
+        ok(fund_risk_pool(1_000.0));
+        ok(approve_or_reject_claim(claim.id, false, "No hail".to_string()));
+
+        act_as(principal(1));
+        let dispute = ok(appeal_claim(claim.id, "There was hail".to_string()));
+        assert!(matches!(appeal_claim(claim.id, "Again".to_string()), Err(Error::InvalidInput { .. })));
+        assign(dispute.id, arbitrator_id);
+        advance_time(dispute.evidence_deadline - time());
+
+        act_as(principal(3));
+        let payout = MonetaryOutcome {
+            source: FundSource::Treasury,
+            recipient_id: farmer_id,
+            amount: 300.0,
+        };
+        let result = submit_ruling(dispute.id, arbitrator_id, farmer_id, "Pay".to_string(), vec![payout]);
+        assert!(matches!(result, Err(Error::InvalidInput { .. })));
+        ok(submit_ruling(dispute.id, arbitrator_id, farmer_id, "Pay".to_string(), Vec::new()));
+
+        let claim = ok(read_insurance_claim(claim.id));
+        assert!(claim.status == ClaimStatus::Approved && claim.paid_out);
+        assert_eq!(ok(get_stake_balance(farmer_id)).available, 300.0);
+        assert_eq!(get_treasury().risk_pool, 700.0);
+    }
 
-    Some(stake_adjustment)
-}
+    // Uploads `data` in BLOB_CHUNK_SIZE chunks as the current caller
+    fn upload(owner_id: u64, data: &[u8]) -> BlobMeta {
+        let session = ok(begin_blob_upload(owner_id, "image/jpeg".to_string(), data.len() as u64));
+        for (index, chunk) in data.chunks(BLOB_CHUNK_SIZE).enumerate() {
+            ok(upload_blob_chunk(session.id, index as u32, ByteBuf::from(chunk.to_vec())));
+        }
+        ok(commit_blob_upload(session.id))
+    }
 
-#[ic_cdk::query]
-fn read_stake_adjustment(adjustment_id: u64) -> Result<StakeAdjustment, Error> {
-    if let Some(adjustment) = STAKE_ADJUSTMENTS.with(|adjustments| adjustments.borrow().get(&adjustment_id)) {
-        Ok(adjustment.clone())
-    } else {
-        Err(Error::NotFound {
-            msg: format!("Stake adjustment with id={} not found", adjustment_id),
-        })
+    #[test]
+    fn blobs_are_uploaded_in_chunks_and_readable_by_their_owner() {
+        let owner_id = user(1, UserRole::Farmer, 0.0);
+        user(2, UserRole::Farmer, 0.0);
+        let data = vec![7u8; BLOB_CHUNK_SIZE + 10];
+
+        act_as(principal(1));
+        let session = ok(begin_blob_upload(owner_id, "image/jpeg".to_string(), data.len() as u64));
+        let result = upload_blob_chunk(session.id, 0, ByteBuf::from(vec![7u8; 10]));
+        assert!(matches!(result, Err(Error::InvalidInput { .. })));
+        assert!(matches!(commit_blob_upload(session.id), Err(Error::InvalidInput { .. })));
+        ok(abort_blob_upload(session.id));
+        assert_eq!(get_blob_usage(owner_id).used_bytes, 0);
+
+        let meta = upload(owner_id, &data);
+        assert!(meta.hash == Sha256::digest(&data).to_vec());
+        assert_eq!((meta.chunk_count, get_blob_usage(owner_id).used_bytes), (2, data.len() as u64));
+        assert!(ok(get_blob_chunk(meta.hash.clone(), 1)).into_vec() == vec![7u8; 10]);
+
+        act_as(principal(2));
+        assert!(matches!(get_blob_chunk(meta.hash, 0), Err(Error::Unauthorized { .. })));
     }
-}
 
-#[ic_cdk::update]
-fn update_stake_adjustment(
-    adjustment_id: u64,
-    user_id: u64,
-    old_stake: f64,
-    new_stake: f64,
-    reason: String,
-) -> Result<StakeAdjustment, Error> {
-    STAKE_ADJUSTMENTS.with(|adjustments| {
-        let mut adjustments = adjustments.borrow_mut();
+    #[test]
+    fn identical_blobs_are_stored_once() {
+        let first_id = user(1, UserRole::Farmer, 0.0);
+        let second_id = user(2, UserRole::Farmer, 0.0);
+        act_as(principal(1));
+        let meta = upload(first_id, b"receipt");
+        act_as(principal(2));
+        assert!(upload(second_id, b"receipt").hash == meta.hash);
+
+        // The second uploader can read it, but the space is only charged to the first
+        ok(get_blob_meta(meta.hash.clone()));
+        assert_eq!(get_blob_usage(second_id).used_bytes, 0);
+        assert!(matches!(delete_blob(meta.hash.clone()), Err(Error::Unauthorized { .. })));
+        act_as(principal(1));
+        ok(delete_blob(meta.hash));
+        assert_eq!(get_blob_usage(first_id).used_bytes, 0);
+    }
 
-        // Check if the stake adjustment exists
-        if let Some(mut adjustment) = adjustments.remove(&adjustment_id) {
-            // Update the fields
-            adjustment.user_id = user_id;
-            adjustment.old_stake = old_stake;
-            adjustment.new_stake = new_stake;
-            adjustment.reason = reason;
+    // Payload of a plot of 0.01 by 0.01 degrees with its south-west corner at the given point
+    fn square_plot(lat: f64, lon: f64) -> PlotPayload {
+        PlotPayload {
+            name: "North field".to_string(),
+            geometry: PlotGeometry::Polygon(vec![
+                GeoPoint { lat, lon },
+                GeoPoint { lat, lon: lon + 0.01 },
+                GeoPoint { lat: lat + 0.01, lon: lon + 0.01 },
+                GeoPoint { lat: lat + 0.01, lon },
+            ]),
+            area_hectares: None,
+            soil_type: SoilType::Loam,
+            region_code: "KE-30".to_string(),
+        }
+    }
 
-            // Insert the updated adjustment back into the map
-            adjustments.insert(adjustment_id, adjustment.clone());
+    #[test]
+    fn plot_areas_are_computed_from_their_polygon() {
+        let farmer_id = user(1, UserRole::Farmer, 0.0);
+        let plot = ok(register_plot(farmer_id, square_plot(0.0, 36.0)));
+        // 0.01 degrees are about 1.11 km at the equator
+        assert!((plot.area_hectares - 123.6).abs() < 0.5);
+
+        let point = PlotPayload {
+            geometry: PlotGeometry::Point(GeoPoint { lat: 0.0, lon: 36.0 }),
+            ..square_plot(0.0, 36.0)
+        };
+        assert!(matches!(register_plot(farmer_id, point), Err(Error::InvalidInput { .. })));
+        let outside = square_plot(90.0, 36.0);
+        assert!(matches!(register_plot(farmer_id, outside), Err(Error::InvalidInput { .. })));
+
+        act_as(principal(2));
+        assert!(matches!(update_plot(plot.id, square_plot(1.0, 36.0)), Err(Error::Unauthorized { .. })));
+    }
 
-            // Return the updated adjustment
-            Ok(adjustment)
-        } else {
-            // Stake adjustment not found
-            Err(Error::NotFound {
-                msg: format!("Stake adjustment with id={} not found", adjustment_id),
-            })
+    #[test]
+    fn plots_are_found_by_bounding_box_and_region() {
+        let farmer_id = user(1, UserRole::Farmer, 0.0);
+        let near = ok(register_plot(farmer_id, square_plot(0.0, 36.0)));
+        let far = ok(register_plot(
+            farmer_id,
+            PlotPayload {
+                region_code: "KE-31".to_string(),
+                ..square_plot(2.0, 38.0)
+            },
+        ));
+
+        let bounds = BoundingBox {
+            min_lat: 0.005,
+            min_lon: 35.0,
+            max_lat: 1.0,
+            max_lon: 37.0,
+        };
+        let page = ok(find_plots_in_bounding_box(bounds, 0, 10));
+        assert!(page.total == 1 && page.plots[0].id == near.id);
+        let page = find_plots_in_region("KE-31".to_string(), 0, 10);
+        assert!(page.total == 1 && page.plots[0].id == far.id);
+        assert_eq!(get_farmer_plots(farmer_id).len(), 2);
+    }
+
+    fn maize_payload() -> CropPayload {
+        CropPayload {
+            name: "Maize".to_string(),
+            aliases: vec!["Corn".to_string()],
+            growing_seasons: vec!["long rains".to_string()],
+            yield_per_hectare: 2.5,
+            eligible_perils: vec![Peril::Drought, Peril::Hail],
         }
-    })
-}
-#[ic_cdk::update]
-fn submit_insurance_claim(
-    farmer_id: u64,
-    contract_id: u64,
-    claim_details: String,
-    affected_crops: Vec<String>,
-    evidence: String,
-) -> Option<InsuranceClaim> {
-    let id = ID_COUNTER
-        .with(|counter| {
-            let current_value = *counter.borrow().get();
-            counter.borrow_mut().set(current_value + 1)
-        })
-        .expect("cannot increment id counter");
+    }
 
-    let claim = InsuranceClaim {
-        id,
-        farmer_id,
-        contract_id,
-        claim_details,
-        affected_crops,
-        evidence,
-        status: ClaimStatus::Submitted,
-    };
+    // Adds a crop the way an executed governance proposal does
+    fn maize() -> Crop {
+        execute_proposal_action(&ProposalAction::AddCrop(maize_payload())).unwrap();
+        ok(find_crop("maize".to_string()))
+    }
 
-    INSURANCE_CLAIMS.with(|claims| {
-        claims.borrow_mut().insert(id, claim.clone());
-    });
+    #[test]
+    fn crops_are_found_by_name_or_alias() {
+        let crop = maize();
+        assert!(ok(find_crop(" CORN ".to_string())).id == crop.id);
+        assert!(matches!(find_crop("corn on the cob".to_string()), Err(Error::NotFound { .. })));
+
+        let duplicate = CropPayload {
+            name: "Mealies".to_string(),
+            aliases: vec!["maize".to_string()],
+            ..maize_payload()
+        };
+        assert!(validate_proposal_action(&ProposalAction::AddCrop(duplicate)).is_err());
+        let renamed = ProposalAction::UpdateCrop {
+            crop_id: crop.id,
+            crop: CropPayload {
+                aliases: vec!["Corn".to_string(), "Mealies".to_string()],
+                ..maize_payload()
+            },
+        };
+        execute_proposal_action(&renamed).unwrap();
+        assert!(ok(find_crop("mealies".to_string())).id == crop.id);
+    }
 
-    Some(claim)
-}
-#[ic_cdk::update]
-fn verify_insurance_claim(claim_id: u64) -> Result<(), String> {
-    INSURANCE_CLAIMS.with(|claims| {
-        let mut claims_map = claims.borrow_mut();
+    #[test]
+    fn retired_crops_cannot_be_planted() {
+        let farmer_id = user(1, UserRole::Farmer, 0.0);
+        let plot = ok(register_plot(farmer_id, square_plot(0.0, 36.0)));
+        let crop = maize();
+        ok(record_plot_planting(plot.id, "2026 long rains".to_string(), crop.id));
+        let result = record_plot_planting(plot.id, "2026 long rains".to_string(), crop.id);
+        assert!(matches!(result, Err(Error::InvalidInput { .. })));
+
+        execute_proposal_action(&ProposalAction::RetireCrop(crop.id)).unwrap();
+        assert!(ok(get_crop(crop.id)).retired);
+        let result = record_plot_planting(plot.id, "2027 long rains".to_string(), crop.id);
+        assert!(matches!(result, Err(Error::InvalidInput { .. })));
+        assert!(matches!(record_plot_planting(plot.id, "2027".to_string(), 999), Err(Error::NotFound { .. })));
+    }
 
-        if let Some(mut claim) = claims_map.remove(&claim_id) {
-            // Logic to verify the claim
-            claim.status = ClaimStatus::Verified;
+    fn act_for(user_id: u64) {
+        act_as(USER_PROFILES.with(|profiles| profiles.borrow().get(&user_id)).unwrap().principal);
+    }
 
-            // Reinsert the modified claim back into the map
-            claims_map.insert(claim_id, claim);
-            Ok(())
-        } else {
-            Err("Claim not found".to_string())
-        }
-    })
-}
-#[ic_cdk::update]
-fn approve_or_reject_claim(claim_id: u64, approve: bool, _reason: String) -> Result<(), String> {
-    INSURANCE_CLAIMS.with(|claims| {
-        let mut claims_map = claims.borrow_mut();
+    // Pending contract insuring maize planted on a plot of the farmer
+    fn insured_contract(farmer_id: u64, consumer_id: u64) -> InsuranceContract {
+        act_for(farmer_id);
+        let plot = ok(register_plot(farmer_id, square_plot(0.0, 36.0)));
+        let crop = maize();
+        ok(record_plot_planting(plot.id, "2026 long rains".to_string(), crop.id));
+        let coverage = ContractCoverage {
+            plot_ids: vec![plot.id],
+            crop_ids: vec![crop.id],
+            sum_insured: 1_000.0,
+        };
+        let (terms, conditions, criteria) = ("Terms".to_string(), "Conditions".to_string(), "Criteria".to_string());
+        ok(create_insurance_contract(farmer_id, consumer_id, terms, conditions, criteria, coverage))
+    }
 
-        if let Some(mut claim) = claims_map.remove(&claim_id) {
-            if claim.status == ClaimStatus::Verified {
-                // Update the claim status based on the approval flag
-                claim.status = if approve {
-                    ClaimStatus::Approved
-                } else {
-                    ClaimStatus::Rejected
-                };
-                
-                // Optionally, store the reason for approval or rejection
-                // ... (add logic here if needed)
+    fn hail_claim(contract: &InsuranceContract) -> InsuranceClaim {
+        act_for(contract.farmer_id);
+        let affected = AffectedCrop {
+            plot_id: contract.plot_ids[0],
+            crop_id: contract.crop_ids[0],
+        };
+        ok(submit_insurance_claim(
+            contract.farmer_id,
+            contract.id,
+            "Hail flattened the maize".to_string(),
+            vec![affected],
+            Peril::Hail,
+            "Photos to follow".to_string(),
+            2_000.0,
+        ))
+    }
 
-                // Reinsert the modified claim back into the map
-                claims_map.insert(claim_id, claim);
-                Ok(())
-            } else {
-                Err("Claim must be verified before approval/rejection".to_string())
-            }
-        } else {
-            Err("Claim not found".to_string())
+    fn report(yield_loss_percent: f64, affected_area_hectares: f64) -> AssessmentReport {
+        AssessmentReport {
+            yield_loss_percent,
+            affected_area_hectares,
+            photo_hashes: Vec::new(),
+            visit_location: GeoPoint { lat: 0.005, lon: 36.005 },
+            visited_at: time(),
+            notes: "Visited the field".to_string(),
         }
-    })
-}
-#[ic_cdk::update]
-fn adjust_stake_transaction(user_id: u64, transaction_success: bool, adjustment_amount: f64) -> Result<(), String> {
-    USER_PROFILES.with(|profiles| {
-        let mut profiles_map = profiles.borrow_mut();
-
-        if let Some(mut profile) = profiles_map.remove(&user_id) {
-            // Adjust the stake based on the transaction outcome
-            if transaction_success {
-                profile.stake_in_dao += adjustment_amount;
-            } else {
-                // Ensure the stake does not go negative
-                profile.stake_in_dao = (profile.stake_in_dao - adjustment_amount).max(0.0);
-            }
+    }
 
-            // Reinsert the updated profile back into the map
-            profiles_map.insert(user_id, profile);
-            Ok(())
-        } else {
-            Err("User profile not found".to_string())
+    #[test]
+    fn payouts_follow_the_accepted_assessment() {
+        let farmer_id = user(1, UserRole::Farmer, 0.0);
+        let consumer_id = user(2, UserRole::Consumer, 0.0);
+        let first_id = user(3, UserRole::Assessor, 0.0);
+        let second_id = user(4, UserRole::Assessor, 0.0);
+        let contract = insured_contract(farmer_id, consumer_id);
+        act_for(consumer_id);
+        ok(activate_insurance_contract(contract.id));
+        let claim = hail_claim(&contract);
+        let area = ok(get_plot(contract.plot_ids[0])).area_hectares;
+
+        act_as(controller());
+        assert!(matches!(assign_assessor(claim.id, consumer_id), Err(Error::InvalidInput { .. })));
+        ok(assign_assessor(claim.id, first_id));
+        ok(assign_assessor(claim.id, second_id));
+
+        act_as(principal(3));
+        for (loss, affected) in [(120.0, area), (40.0, area * 2.0)] {
+            let result = submit_assessment(claim.id, first_id, report(loss, affected));
+            assert!(matches!(result, Err(Error::InvalidInput { .. })));
         }
-    })
-}
-#[ic_cdk::update]
-fn adjust_stake_claim_history(user_id: u64, claim_history: Vec<InsuranceClaim>) -> Result<(), String> {
-    let claim_count = claim_history.iter().filter(|claim| claim.farmer_id == user_id).count();
+        let accepted = ok(submit_assessment(claim.id, first_id, report(40.0, area / 2.0)));
+        assert!(matches!(submit_assessment(claim.id, first_id, report(40.0, area)), Err(Error::InvalidInput { .. })));
+        act_as(principal(4));
+        ok(submit_assessment(claim.id, second_id, report(80.0, area / 2.0)));
+        assert!(ok(read_insurance_claim(claim.id)).assessments_disagree);
+
+        act_as(controller());
+        let claim = ok(verify_insurance_claim(claim.id, accepted.id));
+        assert!(claim.status == ClaimStatus::Verified);
+        assert!((claim.payout_amount.unwrap() - 200.0).abs() < 1e-9);
+    }
 
-    USER_PROFILES.with(|profiles| {
-        let mut profiles_map = profiles.borrow_mut();
+    #[test]
+    fn coverage_can_only_change_while_the_contract_is_pending() {
+        let farmer_id = user(1, UserRole::Farmer, 0.0);
+        let consumer_id = user(2, UserRole::Consumer, 0.0);
+        user(3, UserRole::Consumer, 0.0);
+        let contract = insured_contract(farmer_id, consumer_id);
+        let update = |sum_insured: f64, terms: &str| {
+            let coverage = ContractCoverage {
+                plot_ids: contract.plot_ids.clone(),
+                crop_ids: contract.crop_ids.clone(),
+                sum_insured,
+            };
+            let (terms, conditions, criteria) = (terms.to_string(), "Conditions".to_string(), "Criteria".to_string());
+            update_insurance_contract(contract.id, farmer_id, consumer_id, terms, conditions, criteria, coverage)
+        };
+
+        act_as(principal(3));
+        assert!(matches!(update(2_000.0, "Terms"), Err(Error::Unauthorized { .. })));
+        act_as(principal(1));
+        assert_eq!(ok(update(2_000.0, "Terms")).sum_insured, 2_000.0);
+
+        act_as(principal(2));
+        ok(activate_insurance_contract(contract.id));
+        assert!(matches!(update(3_000.0, "Terms"), Err(Error::InvalidInput { .. })));
+        assert!(ok(update(2_000.0, "Revised terms")).terms == "Revised terms");
+    }
 
-        if let Some(mut profile) = profiles_map.remove(&user_id) {
-            // Adjust stake based on the number of claims
-            // Note: The formula can be adjusted as per the specific requirements
-            profile.stake_in_dao += 10.0 * (1.0 / (1.0 + claim_count as f64));
+    #[test]
+    fn only_the_consumer_activates_a_contract() {
+        let farmer_id = user(1, UserRole::Farmer, 0.0);
+        let consumer_id = user(2, UserRole::Consumer, 0.0);
+        let contract = insured_contract(farmer_id, consumer_id);
+
+        act_as(principal(1));
+        assert!(matches!(activate_insurance_contract(contract.id), Err(Error::Unauthorized { .. })));
+        act_as(principal(2));
+        let contract = ok(activate_insurance_contract(contract.id));
+        assert!(contract.status == ContractStatus::Active && contract.activated_at == Some(time()));
+        assert!(matches!(activate_insurance_contract(contract.id), Err(Error::InvalidInput { .. })));
+    }
 
-            // Ensure the stake does not exceed certain limits or go negative
-            // Example: profile.stake_in_dao = profile.stake_in_dao.clamp(min_stake, max_stake);
+    #[test]
+    fn suspicious_claims_are_held_for_manual_review() {
+        let farmer_id = user(1, UserRole::Farmer, 0.0);
+        let consumer_id = user(2, UserRole::Consumer, 0.0);
+        let contract = insured_contract(farmer_id, consumer_id);
+        act_as(controller());
+        ok(activate_insurance_contract(contract.id));
+        ok(record_weather_observation("KE-30".to_string(), Peril::Drought, time(), time()));
+
+        // Filed the day of activation for hail the oracle did not observe in the region
+        let claim = hail_claim(&contract);
+        assert!(claim.status == ClaimStatus::ManualReview);
+        assert!(matches!(get_fraud_report(claim.id), Err(Error::Unauthorized { .. })));
+
+        act_as(controller());
+        let report = ok(get_fraud_report(claim.id));
+        assert_eq!(report.score, FRAUD_EARLY_CLAIM_SCORE + FRAUD_WEATHER_MISMATCH_SCORE);
+        assert!(ok(clear_fraud_review(claim.id)).status == ClaimStatus::Submitted);
+    }
 
-            // Reinsert the updated profile back into the map
-            profiles_map.insert(user_id, profile);
-            Ok(())
-        } else {
-            Err("User profile not found".to_string())
-        }
-    })
-}
+    #[test]
+    fn claims_matching_observed_weather_are_not_held() {
+        let farmer_id = user(1, UserRole::Farmer, 0.0);
+        let consumer_id = user(2, UserRole::Consumer, 0.0);
+        let contract = insured_contract(farmer_id, consumer_id);
+        act_as(controller());
+        ok(activate_insurance_contract(contract.id));
+        advance_time(FRAUD_EARLY_CLAIM_WINDOW_NS);
+        ok(record_weather_observation("KE-30".to_string(), Peril::Hail, time() - 1_000, time()));
+
+        let claim = hail_claim(&contract);
+        assert!(claim.status == ClaimStatus::Submitted);
+        act_as(controller());
+        assert_eq!(ok(get_fraud_report(claim.id)).score, 0);
+
+        // A second claim for the same plot and peril is a likely duplicate
+        let duplicate = hail_claim(&contract);
+        act_as(controller());
+        assert_eq!(ok(get_fraud_report(duplicate.id)).score, FRAUD_DUPLICATE_SCORE);
+    }
 
-#[ic_cdk::update]
-fn adjust_stake_dao_participation(user_id: u64, participation_level: u64) -> Result<(), String> {
-    USER_PROFILES.with(|profiles| {
-        let mut profiles_map = profiles.borrow_mut();
+    #[test]
+    fn domain_events_are_logged_in_sequence() {
+        let farmer_id = user(1, UserRole::Farmer, 0.0);
+        let consumer_id = user(2, UserRole::Consumer, 0.0);
+        let contract = contract(farmer_id, consumer_id);
+        ok(activate_insurance_contract(contract.id));
+
+        let events = get_events(0, 100);
+        assert!(events.iter().enumerate().all(|(index, event)| event.seq == index as u64 + 1));
+        assert!(matches!(events[0].kind, EventKind::UserCreated { user_id, .. } if user_id == farmer_id));
+        let last = events.last().unwrap();
+        assert!(matches!(last.kind, EventKind::ContractActivated { contract_id } if contract_id == contract.id));
+        assert!(last.caller == principal(2));
+
+        let page = get_events(2, 2);
+        assert!(page.len() == 2 && page[0].seq == 2);
+        assert!(get_events(last.seq + 1, 10).is_empty());
+    }
 
-        if let Some(mut profile) = profiles_map.remove(&user_id) {
-            // Adjust stake based on DAO participation level
-            profile.stake_in_dao += 5.0 * participation_level as f64;
+    #[test]
+    fn certified_reads_prove_the_record_against_the_certified_root() {
+        let farmer_id = user(1, UserRole::Farmer, 0.0);
+        let consumer_id = user(2, UserRole::Consumer, 0.0);
+        let contract = contract(farmer_id, consumer_id);
+        ok(activate_insurance_contract(contract.id));
+
+        let certified = ok(read_certified_insurance_contract(contract.id));
+        assert!(certified.value.status == ContractStatus::Active);
+        let witness: ic_certification::HashTree = serde_cbor::from_slice(&certified.witness).unwrap();
+        assert!(witness.digest().to_vec() == certified.certificate.into_vec());
+        let leaf = witness.lookup_path([CERTIFIED_CONTRACTS, &contract.id.to_be_bytes()[..]]);
+        let hash: Hash = Sha256::digest(&certified.data).into();
+        assert!(leaf == ic_certification::LookupResult::Found(&hash[..]));
+
+        // The proof no longer holds for a stale copy of the record
+        act_as(controller());
+        ok(complete_insurance_contract(contract.id, None));
+        let certified = ok(read_certified_insurance_contract(contract.id));
+        let witness: ic_certification::HashTree = serde_cbor::from_slice(&certified.witness).unwrap();
+        let leaf = witness.lookup_path([CERTIFIED_CONTRACTS, &contract.id.to_be_bytes()[..]]);
+        assert!(leaf != ic_certification::LookupResult::Found(&hash[..]));
+    }
 
-            // Reinsert the updated profile back into the map
-            profiles_map.insert(user_id, profile);
-            Ok(())
-        } else {
-            Err("User profile not found".to_string())
-        }
-    })
-}
-#[ic_cdk::update]
-fn reward_user_for_positive_behavior(user_id: u64, behavior_metric: String) -> Result<(), String> {
-    let reward = match behavior_metric.as_str() {
-        "excellent" => 20.0,
-        "good" => 10.0,
-        _ => 5.0,
-    };
+    fn get(url: &str) -> (u16, serde_json::Value) {
+        let response = http_request(HttpRequest {
+            method: "GET".to_string(),
+            url: url.to_string(),
+            headers: Vec::new(),
+            body: ByteBuf::new(),
+        });
+        assert!(response.headers.iter().any(|(name, _)| name == "Access-Control-Allow-Origin"));
+        let body = serde_json::from_slice(&response.body).unwrap_or(serde_json::Value::Null);
+        (response.status_code, body)
+    }
 
-    USER_PROFILES.with(|profiles| {
-        let mut profiles_map = profiles.borrow_mut();
+    #[test]
+    fn api_claims_do_not_expose_evidence() {
+        let farmer_id = user(1, UserRole::Farmer, 0.0);
+        let consumer_id = user(2, UserRole::Consumer, 0.0);
+        let contract = insured_contract(farmer_id, consumer_id);
+        act_for(consumer_id);
+        ok(activate_insurance_contract(contract.id));
+        let claim = hail_claim(&contract);
+
+        let (status, body) = get(&format!("/api/claims/{}", claim.id));
+        assert_eq!(status, 200);
+        assert_eq!(body["claimed_amount"], 2_000.0);
+        assert!(body.get("evidence").is_none() && body.get("evidence_hashes").is_none());
+
+        let (_, body) = get("/api/claims?status=Submitted");
+        assert_eq!(body["total"], 1);
+        assert!(body["items"][0].get("evidence").is_none());
+        assert_eq!(get("/api/claims?status=Approved").1["total"], 0);
+        assert_eq!(get("/api/claims?status=Lost").0, 400);
+    }
 
-        if let Some(mut profile) = profiles_map.remove(&user_id) {
-            // Adjust stake based on positive behavior
-            profile.stake_in_dao += reward;
+    #[test]
+    fn api_lists_are_paged() {
+        let farmer_id = user(1, UserRole::Farmer, 0.0);
+        let consumer_id = user(2, UserRole::Consumer, 0.0);
+        let ids: Vec<u64> = (0..3).map(|_| contract(farmer_id, consumer_id).id).collect();
+
+        let (status, body) = get("/api/contracts?offset=1&limit=1");
+        assert_eq!(status, 200);
+        assert_eq!((body["total"].as_u64(), body["limit"].as_u64()), (Some(3), Some(1)));
+        assert_eq!(body["items"].as_array().unwrap().len(), 1);
+        assert_eq!(body["items"][0]["id"].as_u64(), Some(ids[1]));
+        assert_eq!(get("/api/contracts?offset=5").1["items"].as_array().unwrap().len(), 0);
+
+        assert_eq!(get("/api/contracts?limit=many").0, 400);
+        assert_eq!(get("/api/contracts/first").0, 400);
+        assert_eq!(get("/api/contracts/999").0, 404);
+        assert_eq!(get("/api/farmers").0, 404);
+        assert_eq!(get("/api/stats").1["contracts"], 3);
+    }
 
-            // Reinsert the updated profile back into the map
-            profiles_map.insert(user_id, profile);
-            Ok(())
-        } else {
-            Err("User profile not found".to_string())
-        }
-    })
-}
-#[ic_cdk::update]
-fn submit_governance_proposal(proposer_id: u64, proposal_details: String) -> Option<GovernanceProposal> {
-    let id = ID_COUNTER.with(|counter| {
-        let current_value = *counter.borrow().get();
-        counter.borrow_mut().set(current_value + 1).unwrap();
-        current_value
-    });
+    #[test]
+    fn metrics_count_calls_and_errors_per_endpoint() {
+        let user_id = user(1, UserRole::Farmer, 0.0);
+        assert!(matches!(withdraw_stake(user_id, 5.0), Err(Error::NotFound { .. })));
+
+        let response = http_request(HttpRequest {
+            method: "GET".to_string(),
+            url: "/metrics".to_string(),
+            headers: Vec::new(),
+            body: ByteBuf::new(),
+        });
+        assert_eq!(response.status_code, 200);
+        let body = String::from_utf8(response.body.into_vec()).unwrap();
+        let lines: Vec<&str> = body.lines().collect();
+        assert!(lines.contains(&"agrisurance_users{role=\"Farmer\"} 1"));
+        assert!(lines.contains(&"agrisurance_endpoint_calls_total{endpoint=\"withdraw_stake\"} 1"));
+        assert!(lines.contains(&"agrisurance_endpoint_errors_total{endpoint=\"withdraw_stake\"} 1"));
+        assert!(lines.contains(&"agrisurance_endpoint_errors_total{endpoint=\"create_user_profile\"} 0"));
+    }
 
-    let governance_proposal = GovernanceProposal {
-        id,
-        proposal_details,
-        proposer_id,
-        voting_records: Vec::new(),
-    };
+    #[test]
+    fn only_lifting_endpoints_run_while_writes_are_suspended() {
+        user(1, UserRole::Farmer, 0.0);
+        WRITE_FREEZE.with(|freeze| *freeze.borrow_mut() = Some(WriteFreeze::Backup { expires_at: u64::MAX }));
 
-    GOVERNANCE_PROPOSALS.with(|proposals| {
-        proposals.borrow_mut().insert(id, governance_proposal.clone());
-    });
+        let blocked = std::panic::catch_unwind(|| create_user_profile("Late".to_string(), UserRole::Farmer, 0.0));
+        assert!(blocked.is_err());
+        act_as(controller());
+        assert!(matches!(discard_backup(), Err(Error::NotFound { .. })));
+        assert!(matches!(discard_export(999), Err(Error::NotFound { .. })));
+    }
 
-    Some(governance_proposal)
-}
-#[ic_cdk::update]
-fn vote_on_proposal(user_id: u64, proposal_id: u64, vote: VoteType) -> Result<(), String> {
-    // Retrieve the user profile and get the stake
-    let user_stake = USER_PROFILES.with(|profiles| {
-        profiles.borrow()
-                .get(&user_id)
-                .map(|profile| profile.stake_in_dao)
-                .unwrap_or(0.0)
-    });
+    #[test]
+    fn exports_are_consistent_snapshots() {
+        user(1, UserRole::Farmer, 0.0);
+        user(2, UserRole::Consumer, 0.0);
+        let last_seq = get_events(0, 100).last().unwrap().seq;
+
+        act_as(principal(1));
+        assert!(matches!(begin_export(ExportFormat::JsonLines), Err(Error::Unauthorized { .. })));
+        act_as(controller());
+        let manifest = ok(begin_export(ExportFormat::JsonLines));
+        assert!(manifest.complete && manifest.event_seq == last_seq);
+        let users = manifest.files.iter().find(|file| file.dataset == ExportDataset::Users).unwrap();
+        assert_eq!((users.rows, users.chunk_count), (2, 1));
+
+        let chunk = ok(get_export_chunk(manifest.snapshot_id, ExportDataset::Users, 0)).into_vec();
+        let names: Vec<String> = String::from_utf8(chunk)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["name"].to_string())
+            .collect();
+        assert_eq!(names, vec!["\"User 1\"", "\"User 2\""]);
+        act_as(principal(1));
+        let result = get_export_chunk(manifest.snapshot_id, ExportDataset::Users, 0);
+        assert!(matches!(result, Err(Error::Unauthorized { .. })));
+    }
 
-    // Check if the user has sufficient stake to vote
-    if user_stake <= 0.0 {
-        return Err("User does not have sufficient stake to vote".to_string());
+    #[test]
+    fn writes_wait_for_an_export_spanning_several_calls() {
+        user(1, UserRole::Farmer, 0.0);
+        act_as(controller());
+        set_instruction_counter(EXPORT_STEP_INSTRUCTIONS + 1);
+        let manifest = ok(begin_export(ExportFormat::Csv));
+        assert!(!manifest.complete);
+        let result = get_export_chunk(manifest.snapshot_id, ExportDataset::Users, 0);
+        assert!(matches!(result, Err(Error::InvalidInput { .. })));
+        let blocked = std::panic::catch_unwind(|| create_user_profile("Late".to_string(), UserRole::Farmer, 0.0));
+        assert!(blocked.is_err());
+
+        set_instruction_counter(0);
+        run_due_timers();
+        let manifest = ok(load_export_manifest(manifest.snapshot_id));
+        assert!(manifest.complete);
+        let chunk = ok(get_export_chunk(manifest.snapshot_id, ExportDataset::Users, 0)).into_vec();
+        assert_eq!(String::from_utf8(chunk).unwrap().lines().count(), 2);
+        ok(create_user_profile("Late".to_string(), UserRole::Farmer, 0.0));
     }
 
-    // Retrieve the proposal and update it
-    let mut proposal = GOVERNANCE_PROPOSALS.with(|proposals| {
-        proposals.borrow_mut().remove(&proposal_id)
-    }).ok_or("Proposal not found".to_string())?;
+    fn csv_rows(text: &str) -> Vec<Result<UserImport, String>> {
+        parse_csv_rows(text, parse_user_row)
+    }
 
-    // Add the voting record
-    proposal.voting_records.push(VotingRecord { user_id, vote, stake: user_stake });
+    #[test]
+    fn csv_cells_follow_rfc_4180() {
+        let text = "name,notes\r\n\"Doe, Jane\",\"Said \"\"hi\"\"\nand left\"\r\n\r\n,\nlast,row";
+        let records = parse_csv(text);
+        assert_eq!(
+            records,
+            vec![
+                vec!["name".to_string(), "notes".to_string()],
+                vec!["Doe, Jane".to_string(), "Said \"hi\"\nand left".to_string()],
+                vec!["last".to_string(), "row".to_string()],
+            ]
+        );
+        assert!(parse_csv("").is_empty());
+    }
 
-    // Reinsert the updated proposal
-    GOVERNANCE_PROPOSALS.with(|proposals| {
-        proposals.borrow_mut().insert(proposal_id, proposal)
-    });
+    #[test]
+    fn csv_rows_are_parsed_or_reported() {
+        let principal = principal(1).to_text();
+        let text = format!(
+            "external_ref,name,role,principal\n\
+             u1, Jane ,Farmer,{principal}\n\
+             u2,John,Banker,{principal}\n\
+             u3,Jim,Consumer,not-a-principal\n\
+             u4,Joe\n"
+        );
+        let rows = csv_rows(&text);
+        assert_eq!(rows.len(), 4);
+        let user = rows[0].as_ref().unwrap();
+        assert_eq!((user.external_ref.as_str(), user.name.as_str()), ("u1", "Jane"));
+        assert!(matches!(user.role, UserRole::Farmer));
+        assert!(user.principal == Principal::from_text(&principal).unwrap());
+        assert_eq!(rows[1].as_ref().err().unwrap(), "Unknown role Banker");
+        assert_eq!(rows[2].as_ref().err().unwrap(), "Invalid principal not-a-principal");
+        assert_eq!(rows[3].as_ref().err().unwrap(), "Expected 4 columns but found 2");
+
+        let rows = csv_rows("external_ref,name,role\nu1,Jane,Farmer\n");
+        assert_eq!(rows[0].as_ref().err().unwrap(), "Missing column principal");
+    }
 
-    Ok(())
-}
+    #[test]
+    fn csv_targets_and_plots_are_parsed() {
+        assert!(matches!(csv_target("id:42"), Ok(ImportTarget::Id(42))));
+        assert!(matches!(csv_target("farm-7"), Ok(ImportTarget::ExternalRef(reference)) if reference == "farm-7"));
+        assert!(csv_target("id:x").is_err() && csv_target("").is_err());
+
+        let text = "external_ref,farmer,name,geometry,area_hectares,soil_type,region_code\n\
+                    p1,id:3,North,1 2; 1 3; 2 3,,Loam,NL-UT\n\
+                    p2,f1,South,5.5 6.5,1.5,Clay,NL-UT\n\
+                    p3,f1,East,,,Clay,NL-UT\n";
+        let rows = parse_csv_rows(text, parse_plot_row);
+        let polygon = rows[0].as_ref().unwrap();
+        assert!(matches!(&polygon.plot.geometry, PlotGeometry::Polygon(points) if points.len() == 3));
+        assert_eq!(polygon.plot.area_hectares, None);
+        let point = rows[1].as_ref().unwrap();
+        assert!(matches!(point.plot.geometry, PlotGeometry::Point(GeoPoint { lat, lon }) if lat == 5.5 && lon == 6.5));
+        assert_eq!(point.plot.area_hectares, Some(1.5));
+        assert_eq!(rows[2].as_ref().err().unwrap(), "The geometry cannot be empty");
+    }
 
-#[ic_cdk::update]
-fn enact_proposal(proposal_id: u64) -> Result<(), String> {
-    let proposal = GOVERNANCE_PROPOSALS.with(|proposals| proposals.borrow_mut().remove(&proposal_id))
-        .ok_or("Proposal not found".to_string())?;
+    #[test]
+    fn csv_batches_are_applied_all_or_nothing() {
+        let csv = |role: &str| {
+            ImportPayload::Csv(CsvImport {
+                users: format!("external_ref,name,role,principal\nf1,Jane,{},{}\n", role, principal(1).to_text()),
+                plots: "external_ref,farmer,name,geometry,area_hectares,soil_type,region_code\n\
+                        p1,f1,North,0.5 36.5,2,Loam,KE-30\n"
+                    .to_string(),
+                contracts: String::new(),
+            })
+        };
+        act_as(controller());
+        let report = ok(bulk_import(csv("Banker"), false));
+        assert!(!report.applied && matches!(report.rows[0].outcome, ImportOutcome::Invalid(_)));
+        let report = ok(bulk_import(csv("Farmer"), true));
+        assert!(!report.applied && report.rows.iter().all(|row| matches!(row.outcome, ImportOutcome::Valid)));
+        assert!(get_events(0, 10).is_empty());
+
+        let report = ok(bulk_import(csv("Farmer"), false));
+        assert!(report.applied);
+        let ImportOutcome::Created(farmer_id) = report.rows[0].outcome else {
+            panic!("The farmer was not created");
+        };
+        assert!(get_farmer_plots(farmer_id)[0].area_hectares == 2.0);
+
+        let report = ok(bulk_import(csv("Farmer"), false));
+        assert!(report.rows.iter().all(|row| matches!(row.outcome, ImportOutcome::Existing(_))));
+        assert_eq!(get_farmer_plots(farmer_id).len(), 1);
+    }
+    fn download_backup() -> Vec<u8> {
+        act_as(controller());
+        let manifest = ok(begin_backup());
+        assert!(manifest.complete);
+        let data: Vec<u8> =
+            (0..manifest.chunk_count).flat_map(|index| ok(get_backup_chunk(index)).into_vec()).collect();
+        assert_eq!(data.len() as u64, manifest.size);
+        ok(discard_backup());
+        data
+    }
 
-    let total_stake = proposal.voting_records.iter().map(|record| record.stake).sum::<f64>();
-    let approval_stake = proposal.voting_records.iter()
-        .filter(|record| matches!(record.vote, VoteType::Approve))
-        .map(|record| record.stake).sum::<f64>();
+    fn restore_backup(data: &[u8]) -> Result<BackupManifest, Error> {
+        act_as(controller());
+        begin_restore(data.len() as u64)?;
+        for (index, chunk) in data.chunks(BACKUP_CHUNK_SIZE).enumerate() {
+            upload_restore_chunk(index as u32, ByteBuf::from(chunk.to_vec()))?;
+        }
+        commit_restore()
+    }
 
-    // Define the threshold for approval, e.g., more than 50% of the total stake
-    if approval_stake > total_stake / 2.0 {
-        // Logic to enact the proposal
-        // ...
-        Ok(())
-    } else {
-        Err("Proposal not approved".to_string())
+    #[test]
+    fn backups_restore_into_a_fresh_canister() {
+        init();
+        let user_id = user(1, UserRole::Farmer, 30.0);
+        let data = download_backup();
+
+        // Another thread starts out with the empty stores of a freshly installed canister
+        let restored = std::thread::spawn(move || {
+            let manifest = ok(restore_backup(&data));
+            assert!(manifest.complete);
+            let profile = ok(read_user_profile(user_id));
+            (profile.name, profile.principal, stake_of(user_id))
+        })
+        .join()
+        .unwrap();
+        assert!(restored == ("User 1".to_string(), principal(1), 30.0));
+
+        // The canister this was taken from holds records already
+        let data = download_backup();
+        assert!(matches!(restore_backup(&data), Err(Error::InvalidInput { .. })));
     }
-}
 
-#[ic_cdk::update]
-fn create_dispute(payload: DisputePayload) -> Result<Dispute, String> {
-    let id = ID_COUNTER.with(|c| {
-        let current_value = *c.borrow().get();
-        c.borrow_mut().set(current_value + 1).unwrap();
-        current_value
-    });
+    #[test]
+    fn corrupted_backups_are_rejected() {
+        init();
+        let user_id = user(1, UserRole::Farmer, 0.0);
+        let data = download_backup();
+
+        std::thread::spawn(move || {
+            let mut corrupted = data.clone();
+            let last = corrupted.len() - 1;
+            corrupted[last] ^= 1;
+            let rejected = restore_backup(&corrupted);
+            assert!(matches!(rejected, Err(Error::InvalidInput { msg }) if msg.contains("checksum")));
+            // The bytes written during the upload are wiped again and writes resume
+            assert!(!writes_frozen());
+            assert!(read_user_profile(user_id).is_err());
+            assert!(restore_backup(&data).is_ok());
+            assert!(read_user_profile(user_id).is_ok());
+
+            let mut header = data[..BACKUP_HEADER_SIZE].to_vec();
+            header[0] ^= 1;
+            assert!(matches!(read_backup_header(&header, data.len() as u64), Err(Error::InvalidInput { .. })));
+            assert!(read_backup_header(&data[..BACKUP_HEADER_SIZE], data.len() as u64).is_err());
+        })
+        .join()
+        .unwrap();
+    }
 
-    let dispute = Dispute {
-        id,
-        farmer_id: payload.farmer_id,
-        consumer_id: payload.consumer_id,
-        reason: payload.reason,
-        status: DisputeStatus::Raised,
-        resolution: None,
-    };
+    #[test]
+    fn writes_wait_for_the_backup_to_be_discarded() {
+        init();
+        act_as(controller());
+        ok(begin_backup());
+        assert!(writes_frozen());
+        ok(discard_backup());
+        assert!(!writes_frozen());
+
+        ok(begin_backup());
+        advance_time(BACKUP_WINDOW_NS);
+        assert!(!writes_frozen());
+        assert!(matches!(get_backup_chunk(0), Err(Error::InvalidInput { .. })));
+    }
 
-    DISPUTE_STORAGE.with(|s| s.borrow_mut().insert(id, dispute.clone()));
-    Ok(dispute)
-}
-#[ic_cdk::query]
-fn get_dispute(dispute_id: u64) -> Result<Dispute, String> {
-    DISPUTE_STORAGE.with(|s| {
-        match s.borrow().get(&dispute_id) {
-            Some(dispute) => Ok(dispute.clone()),
-            None => Err("Dispute not found".to_string()),
+    // Layouts written by the first release, before any state version was stored
+    mod baseline {
+        use candid::CandidType;
+
+        #[derive(CandidType)]
+        pub(super) struct UserProfile {
+            pub id: u64,
+            pub name: String,
+            pub role: super::UserRole,
+            pub transaction_history: Vec<TransactionRecord>,
+            pub stake_in_dao: f64,
         }
-    })
-}
 
-#[ic_cdk::update]
-fn update_dispute(dispute_id: u64, status: DisputeStatus, resolution: Option<String>) -> Result<Dispute, String> {
-    DISPUTE_STORAGE.with(|s| {
-        let mut storage = s.borrow_mut();
-        if let Some(dispute) = storage.get(&dispute_id) {
-            let mut updated_dispute = dispute.clone();
-            updated_dispute.status = status;
-            updated_dispute.resolution = resolution;
-            storage.insert(dispute_id, updated_dispute.clone());
-            Ok(updated_dispute)
-        } else {
-            Err("Dispute not found".to_string())
+        #[derive(CandidType)]
+        pub(super) struct TransactionRecord {
+            pub id: u64,
+            pub amount: f64,
+            pub date: u64,
+            pub involved_parties: Vec<u64>,
         }
-    })
-}
 
-#[ic_cdk::update]
-fn delete_dispute(dispute_id: u64) -> Result<(), String> {
-    DISPUTE_STORAGE.with(|s| {
-        if s.borrow_mut().remove(&dispute_id).is_some() {
-            Ok(())
-        } else {
-            Err("Dispute not found".to_string())
+        #[derive(CandidType)]
+        pub(super) struct InsuranceContract {
+            pub id: u64,
+            pub farmer_id: u64,
+            pub consumer_id: u64,
+            pub terms: String,
+            pub conditions: String,
+            pub payout_criteria: String,
         }
-    })
-}
 
+        #[derive(CandidType)]
+        pub(super) struct GovernanceProposal {
+            pub id: u64,
+            pub proposal_details: String,
+            pub proposer_id: u64,
+            pub voting_records: Vec<VotingRecord>,
+        }
 
+        #[derive(CandidType)]
+        pub(super) struct VotingRecord {
+            pub user_id: u64,
+            pub vote: super::VoteType,
+            pub stake: f64,
+        }
 
+        #[derive(CandidType)]
+        pub(super) struct StakeAdjustment {
+            pub user_id: u64,
+            pub old_stake: f64,
+            pub new_stake: f64,
+            pub reason: String,
+        }
 
-#[ic_cdk::update]
-fn delete_stake_adjustment(adjustment_id: u64) -> Result<StakeAdjustment, Error> {
-    STAKE_ADJUSTMENTS
-        .with(|adjustments| adjustments.borrow_mut().remove(&adjustment_id))
-        .ok_or(Error::NotFound {
-            msg: format!("Stake adjustment with id={} not found", adjustment_id),
-        })
-}
+        #[derive(CandidType)]
+        pub(super) struct InsuranceClaim {
+            pub id: u64,
+            pub farmer_id: u64,
+            pub contract_id: u64,
+            pub claim_details: String,
+            pub affected_crops: Vec<String>,
+            pub evidence: String,
+            pub status: super::ClaimStatus,
+        }
 
-// need this to generate candid
-ic_cdk::export_candid!();
\ No newline at end of file
+        #[derive(CandidType)]
+        pub(super) struct Dispute {
+            pub id: u64,
+            pub farmer_id: u64,
+            pub consumer_id: u64,
+            pub reason: String,
+            pub status: super::DisputeStatus,
+            pub resolution: Option<String>,
+        }
+
+        #[derive(CandidType)]
+        pub(super) struct DisputeHistory {
+            pub dispute_id: u64,
+            pub status_updates: Vec<DisputeStatusUpdate>,
+        }
+
+        #[derive(CandidType)]
+        pub(super) struct DisputeStatusUpdate {
+            pub status: super::DisputeStatus,
+            pub resolution: Option<String>,
+            pub timestamp: u64,
+        }
+    }
+
+    fn write_baseline_record(memory_id: u8, key: u64, record: impl candid::CandidType) {
+        StableBTreeMap::<u64, RawRecord, Memory>::init(virtual_memory(memory_id))
+            .insert(key, RawRecord(Encode!(&record).unwrap()));
+    }
+
+    #[test]
+    fn baseline_records_are_migrated() {
+        let transaction = |id, involved_parties| baseline::TransactionRecord {
+            id,
+            amount: 25.0,
+            date: 1_600_000_000,
+            involved_parties,
+        };
+        write_baseline_record(2, 10, transaction(10, vec![1, 2]));
+        for (id, role) in [(1, UserRole::Farmer), (2, UserRole::Consumer)] {
+            let user = baseline::UserProfile {
+                id,
+                name: format!("User {}", id),
+                role,
+                transaction_history: vec![transaction(10, vec![1, 2]), transaction(10 + id, vec![id])],
+                stake_in_dao: 40.0,
+            };
+            write_baseline_record(1, id, user);
+        }
+        for (id, farmer_id, consumer_id) in [(3, 1, 2), (4, 5, 6)] {
+            let contract = baseline::InsuranceContract {
+                id,
+                farmer_id,
+                consumer_id,
+                terms: "Terms".to_string(),
+                conditions: "Conditions".to_string(),
+                payout_criteria: "Payout criteria".to_string(),
+            };
+            write_baseline_record(3, id, contract);
+        }
+        let proposal = baseline::GovernanceProposal {
+            id: 7,
+            proposal_details: "Lower fees".to_string(),
+            proposer_id: 1,
+            voting_records: vec![baseline::VotingRecord {
+                user_id: 2,
+                vote: VoteType::Approve,
+                stake: 40.0,
+            }],
+        };
+        write_baseline_record(4, 7, proposal);
+        let adjustment = baseline::StakeAdjustment {
+            user_id: 1,
+            old_stake: 30.0,
+            new_stake: 40.0,
+            reason: "Good behaviour".to_string(),
+        };
+        write_baseline_record(5, 8, adjustment);
+        let claim = baseline::InsuranceClaim {
+            id: 9,
+            farmer_id: 1,
+            contract_id: 3,
+            claim_details: "Hail".to_string(),
+            affected_crops: vec!["Wheat".to_string(), "Barley".to_string()],
+            evidence: "Photos".to_string(),
+            status: ClaimStatus::Verified,
+        };
+        write_baseline_record(6, 9, claim);
+        for (id, farmer_id, consumer_id) in [(20, 1, 2), (21, 1, 6)] {
+            let dispute = baseline::Dispute {
+                id,
+                farmer_id,
+                consumer_id,
+                reason: "Unpaid".to_string(),
+                status: DisputeStatus::UnderReview,
+                resolution: None,
+            };
+            write_baseline_record(7, id, dispute);
+        }
+        let history = baseline::DisputeHistory {
+            dispute_id: 20,
+            status_updates: vec![baseline::DisputeStatusUpdate {
+                status: DisputeStatus::UnderReview,
+                resolution: None,
+                timestamp: 1_600_000_000,
+            }],
+        };
+        write_baseline_record(8, 20, history);
+
+        migrate_stored_state();
+
+        let user = ok(read_user_profile(1));
+        assert_eq!((user.name.as_str(), user.stake_in_dao), ("User 1", 40.0));
+        assert!(user.principal == Principal::anonymous());
+        let transactions: Vec<u64> = get_user_transactions(1, 0, 10).records.iter().map(|record| record.id).collect();
+        assert_eq!(transactions, vec![10, 11]);
+        assert_eq!(ok(read_transaction_record(12)).involved_parties, vec![2]);
+
+        let contract = ok(read_insurance_contract(3));
+        assert!(contract.status == ContractStatus::Pending);
+        assert_eq!((contract.sum_insured, contract.activated_at), (0.0, None));
+
+        let proposal = ok(read_governance_proposal(7));
+        assert!(proposal.status == ProposalStatus::Expired);
+        assert!(proposal.deposit_status == DepositStatus::Refunded);
+        assert_eq!(get_proposal_votes(7, 0, 10).records[0].stake, 40.0);
+
+        let adjustment = ok(read_stake_adjustment(8));
+        assert_eq!((adjustment.id, adjustment.new_stake), (8, 40.0));
+        assert!(adjustment.cause == StakeCause::Legacy);
+
+        let claim = ok(read_insurance_claim(9));
+        assert_eq!(claim.claim_details, "Hail\nAffected crops: Wheat, Barley");
+        assert!(claim.status == ClaimStatus::Verified);
+        assert!(claim.affected_crops.is_empty() && !claim.paid_out);
+
+        let dispute = get_dispute(20).unwrap();
+        assert!(dispute.subject == DisputeSubject::Contract(3));
+        assert!(matches!(dispute.status, DisputeStatus::UnderReview));
+        assert_eq!(dispute.raised_by, 1);
+        let orphaned = get_dispute(21).unwrap();
+        assert!(matches!(orphaned.status, DisputeStatus::Archived));
+        let history = get_dispute_history(20).unwrap();
+        assert!(history.status_updates[0].actor == Principal::anonymous());
+
+        // Nobody can act for a migrated profile until a controller assigns it an identity
+        act_as(Principal::anonymous());
+        assert!(matches!(bond_stake(1, 1.0), Err(Error::Unauthorized { .. })));
+        act_as(controller());
+        ok(assign_user_principal(1, principal(1)));
+        act_as(principal(1));
+        ok(unbond_stake(1, 10.0));
+    }
+
+    #[test]
+    fn oversized_fields_are_rejected_naming_their_limit() {
+        let rejected = |result: Result<(), Error>, expected: &str| {
+            assert!(matches!(result, Err(Error::InvalidInput { msg }) if msg == expected));
+        };
+        act_as(principal(1));
+        let long_name = "é".repeat(MAX_USER_NAME_LENGTH + 1);
+        rejected(
+            create_user_profile(long_name, UserRole::Farmer, 0.0).map(|_| ()),
+            "name must be at most 100 characters",
+        );
+        // Limits count characters, and records larger than the old bounded sizes are stored whole
+        let farmer_id = ok(create_user_profile("é".repeat(MAX_USER_NAME_LENGTH), UserRole::Farmer, 0.0)).id;
+        act_as(principal(2));
+        let consumer_id = ok(create_user_profile("Consumer".to_string(), UserRole::Consumer, 0.0)).id;
+        let create_contract = |terms: String| {
+            let coverage = ContractCoverage {
+                plot_ids: Vec::new(),
+                crop_ids: Vec::new(),
+                sum_insured: 1_000.0,
+            };
+            let text = || "Text".to_string();
+            create_insurance_contract(farmer_id, consumer_id, terms, text(), text(), coverage)
+        };
+        let terms = "é".repeat(MAX_CONTRACT_TEXT_LENGTH);
+        let contract = ok(create_contract(terms.clone()));
+        assert_eq!(ok(read_insurance_contract(contract.id)).terms, terms);
+        rejected(create_contract(terms + "é").map(|_| ()), "terms must be at most 4000 characters");
+
+        act_as(principal(1));
+        let claim = |claim_details: String, affected_crops: Vec<AffectedCrop>| {
+            let evidence = "Photos".to_string();
+            submit_insurance_claim(farmer_id, contract.id, claim_details, affected_crops, Peril::Hail, evidence, 10.0)
+                .map(|_| ())
+        };
+        rejected(
+            claim("x".repeat(MAX_CLAIM_DETAILS_LENGTH + 1), Vec::new()),
+            "claim_details must be at most 2000 characters",
+        );
+        let crop = AffectedCrop { plot_id: 0, crop_id: 0 };
+        rejected(
+            claim("Hail".to_string(), vec![crop; MAX_AFFECTED_CROPS + 1]),
+            "affected_crops can have at most 50 entries",
+        );
+        let reason = "x".repeat(MAX_REASON_LENGTH + 1);
+        let dispute = create_dispute(DisputePayload {
+            subject: DisputeSubject::Contract(contract.id),
+            raised_by: farmer_id,
+            reason,
+        });
+        assert!(dispute.is_err_and(|msg| msg.contains("reason must be at most 1000 characters")));
+    }
+}
@@ -11,20 +11,18 @@ const GovernanceProposalForm = () => {
   const [proposerId, setProposerId] = useState(''); // This should be derived from the user's identity
   const [message, setMessage] = useState('');
 
-  // Since VotingRecord is complex, you might manage it separately
-  // For the sake of this example, we'll assume it's an empty array
-  const votingRecords = [];
-
   const handleSubmit = async (event) => {
     event.preventDefault();
     try {
       // Make sure proposerId is a number (u64)
       const numericProposerId = BigInt(proposerId);
-      const governanceProposal = await backend.create_governance_proposal(proposalDetails, numericProposerId, votingRecords);
-      if (governanceProposal) {
+      // Submitting locks the proposal deposit from the proposer's stake
+      const result = await backend.create_governance_proposal(proposalDetails, numericProposerId, []);
+      if ('Ok' in result) {
         setMessage('Governance proposal created successfully!');
       } else {
-        setMessage('Failed to create governance proposal.');
+        const [reason] = Object.values(result.Err);
+        setMessage(`Failed to create governance proposal: ${reason.msg}`);
       }
     } catch (error) {
       setMessage(`Error: ${error.message}`);
@@ -56,7 +54,6 @@ const GovernanceProposalForm = () => {
             />
           </label>
         </div>
-        {/* Other fields related to VotingRecord would go here */}
         <button type="submit">Submit Proposal</button>
       </form>
       {message && <p>{message}</p>}